@@ -57,7 +57,9 @@ export async function build(
 }
 
 export function getFeatures(target: Target): Array<string> {
-	return [`chip-${target.chip}`, `display-${target.display.type}`];
+	const storage =
+		target.sd == null ? 'storage-flash' : `storage-sd-${target.sd.type}`;
+	return [`chip-${target.chip}`, `display-${target.display.type}`, storage];
 }
 
 export type ChipInfo = { target: string; cpu?: string };
@@ -30,6 +30,8 @@ const triple = getChipInfo(target.chip).target;
 const env: Record<string, string> = {
 	VERTX_TARGET: targetName,
 	VERTX_CHIP: target.chip,
+	VERTX_PARTITIONS:
+		target.sd == null ? 'partitions-flash.csv' : 'partitions.csv',
 };
 
 if (!existsSync(baseOutDir)) {
@@ -20,12 +20,15 @@ export const schema = z
 		leds: z.strictObject({
 			status: pin,
 		}),
-		sd: z.discriminatedUnion('type', [
-			z.strictObject({
-				type: z.literal('spi'),
-				cs: pin,
-			}),
-		]),
+		// Storage falls back to internal flash without an SD card
+		sd: z
+			.discriminatedUnion('type', [
+				z.strictObject({
+					type: z.literal('spi'),
+					cs: pin,
+				}),
+			])
+			.optional(),
 		spi: z
 			.strictObject({
 				sclk: pin,
@@ -49,7 +52,7 @@ export const schema = z
 	})
 	.readonly()
 	.check((ctx) => {
-		const needsSpi = ctx.value.sd.type === 'spi';
+		const needsSpi = ctx.value.sd?.type === 'spi';
 		const hasSpi = ctx.value.spi != null;
 
 		if (needsSpi && !hasSpi) {
@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vertx_crsf::{FrameDecoder, Packet};
+
+fuzz_target!(|data: &[u8]| {
+    // Directly, as if positioned at the start of a frame
+    if let Ok(packet) = Packet::read(&mut &data[..]) {
+        let mut buffer = [0; vertx_crsf::MAX_FRAME_BYTES];
+        let _ = packet.write(&mut &mut buffer[..]);
+    }
+
+    // And as a noisy stream
+    let mut decoder = FrameDecoder::new();
+    for &byte in data {
+        if let Some(mut frame) = decoder.push(byte) {
+            let _ = Packet::read(&mut frame);
+        }
+    }
+});
@@ -0,0 +1,199 @@
+//! Compact capture format for timestamped raw frames
+//!
+//! A capture starts with [`MAGIC`] and a [`VERSION`] byte, followed by one
+//! record per frame:
+//!
+//! - an unsigned LEB128 varint holding the microseconds since the previous
+//!   record (or the start of the capture), shifted left by one, with the
+//!   [`Direction`] in the lowest bit
+//! - the raw frame, from the sync byte through the crc. Its length is taken
+//!   from the frame's own length byte.
+//!
+//! At the usual 250Hz channel rate, most records only add 2 bytes on top of the
+//! frame itself.
+
+use alloc::vec::Vec;
+use core::convert::Infallible;
+
+use crate::{MAX_FRAME_BYTES, Packet, PacketError};
+
+#[cfg(any(test, feature = "std"))]
+mod reader;
+
+#[cfg(any(test, feature = "std"))]
+pub use self::reader::Reader;
+
+pub const MAGIC: [u8; 7] = *b"CRSFCAP";
+pub const VERSION: u8 = 1;
+
+/// Longest varint needed for a `u64`
+const MAX_VARINT_BYTES: usize = 10;
+
+/// Which way a frame was travelling, from the point of view of the recorder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Received,
+    Sent,
+}
+
+impl Direction {
+    const fn raw(self) -> u64 {
+        match self {
+            Self::Received => 0,
+            Self::Sent => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E> {
+    Io(E),
+    UnexpectedEof,
+    /// The capture does not start with [`MAGIC`]
+    BadMagic,
+    UnsupportedVersion(u8),
+    /// A frame's length byte does not match its length or is out of range
+    InvalidFrame,
+    /// A timestamp varint is too long
+    Malformed,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// A single captured frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Microseconds since the start of the capture
+    pub timestamp: u64,
+    pub direction: Direction,
+    /// Raw frame, including the sync, length, and crc bytes
+    pub frame: Vec<u8>,
+}
+
+impl Record {
+    /// Decode the captured frame
+    pub fn packet(&self) -> Result<Packet, PacketError<Infallible>> {
+        Packet::read(&mut &self.frame[..])
+    }
+}
+
+/// Appends records to a capture
+#[derive(Debug)]
+pub struct Writer<W> {
+    inner: W,
+    /// Timestamp of the previous record
+    last: u64,
+}
+
+impl<W: embedded_io_async::Write> Writer<W> {
+    /// Start a new capture by writing the header to `inner`. Record timestamps
+    /// are relative to `start`.
+    pub async fn new(mut inner: W, start: u64) -> Result<Self, W::Error> {
+        inner.write_all(&MAGIC).await?;
+        inner.write_all(&[VERSION]).await?;
+        Ok(Self { inner, last: start })
+    }
+
+    /// Append a frame, as passed to or returned from
+    /// [`FrameDecoder::push`](crate::FrameDecoder::push)
+    ///
+    /// `timestamp` is in microseconds. Timestamps that go backwards are
+    /// recorded as if no time had passed.
+    pub async fn record(
+        &mut self,
+        timestamp: u64,
+        direction: Direction,
+        frame: &[u8],
+    ) -> Result<(), Error<W::Error>> {
+        if !is_valid_frame(frame) {
+            return Err(Error::InvalidFrame);
+        }
+
+        let delta = timestamp.saturating_sub(self.last).min(u64::MAX >> 1);
+        self.last = self.last.max(timestamp);
+
+        let mut buffer = [0; MAX_VARINT_BYTES + MAX_FRAME_BYTES];
+        let len = encode_varint(&mut buffer, (delta << 1) | direction.raw());
+        buffer[len..len + frame.len()].copy_from_slice(frame);
+
+        self.inner.write_all(&buffer[..len + frame.len()]).await?;
+        Ok(())
+    }
+
+    pub async fn flush(&mut self) -> Result<(), W::Error> {
+        self.inner.flush().await
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+fn is_valid_frame(frame: &[u8]) -> bool {
+    (4..=MAX_FRAME_BYTES).contains(&frame.len()) && usize::from(frame[1]) + 2 == frame.len()
+}
+
+/// Returns the number of bytes written
+fn encode_varint(buffer: &mut [u8; MAX_VARINT_BYTES + MAX_FRAME_BYTES], mut value: u64) -> usize {
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+
+        if value == 0 {
+            buffer[len] = byte;
+            return len + 1;
+        }
+
+        buffer[len] = byte | 0x80;
+        len += 1;
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    pub(super) const PING: &[u8] = &[0xC8, 0x04, 0x28, 0x00, 0xEA, 0x54];
+    pub(super) const LINK_STATISTICS: &[u8] = &[
+        0xC8, 0x0C, 0x14, 0x24, 0x00, 0x64, 0x0A, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x39,
+    ];
+
+    /// Write a capture starting at 1000µs
+    pub(super) async fn capture(records: &[(u64, Direction, &[u8])]) -> Vec<u8> {
+        let mut buffer = [0; 1024];
+        let mut remaining = &mut buffer[..];
+        let mut writer = Writer::new(&mut remaining, 1_000).await.unwrap();
+        for &(timestamp, direction, frame) in records {
+            writer.record(timestamp, direction, frame).await.unwrap();
+        }
+
+        let len = 1024 - remaining.len();
+        buffer[..len].into()
+    }
+
+    #[tokio::test]
+    async fn encoding() {
+        let bytes = capture(&[(1_100, Direction::Sent, PING)]).await;
+        assert_eq!(bytes[..8], *b"CRSFCAP\x01");
+        // (100 << 1) | 1 = 201
+        assert_eq!(bytes[8..10], [0xC9, 0x01]);
+        assert_eq!(bytes[10..], *PING);
+    }
+
+    #[tokio::test]
+    async fn invalid_frame() {
+        let mut buffer = [0; 64];
+        let mut writer = Writer::new(&mut buffer[..], 0).await.unwrap();
+        for frame in [&[][..], &PING[..5], &[0xC8, 0x01, 0x00]] {
+            assert_eq!(
+                writer.record(0, Direction::Received, frame).await,
+                Err(Error::InvalidFrame)
+            );
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::io;
+
+use super::{Direction, Error, MAGIC, Record, VERSION};
+use crate::MAX_FRAME_BYTES;
+
+/// Reads records back out of a capture
+#[derive(Debug)]
+pub struct Reader<R> {
+    inner: R,
+    timestamp: u64,
+}
+
+impl<R: io::Read> Reader<R> {
+    /// Check the header and prepare to read the first record
+    pub fn new(mut inner: R) -> Result<Self, Error<io::Error>> {
+        let mut header = [0; MAGIC.len() + 1];
+        read_exact(&mut inner, &mut header)?;
+
+        let (magic, [version]) = header.split_at(MAGIC.len()) else {
+            unreachable!()
+        };
+        if magic != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        if *version != VERSION {
+            return Err(Error::UnsupportedVersion(*version));
+        }
+
+        Ok(Self {
+            inner,
+            timestamp: 0,
+        })
+    }
+
+    /// Read the next record, or `None` at the end of the capture
+    pub fn next_record(&mut self) -> Result<Option<Record>, Error<io::Error>> {
+        let mut first = [0];
+        loop {
+            match self.inner.read(&mut first) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(Error::Io(err)),
+            }
+        }
+
+        let mut varint = u64::from(first[0] & 0x7F);
+        let mut more = first[0] & 0x80 != 0;
+        let mut shift = 7;
+        while more {
+            if shift >= u64::BITS {
+                return Err(Error::Malformed);
+            }
+
+            let mut byte = [0];
+            read_exact(&mut self.inner, &mut byte)?;
+            varint |= u64::from(byte[0] & 0x7F) << shift;
+            more = byte[0] & 0x80 != 0;
+            shift += 7;
+        }
+
+        let direction = if varint & 1 == 0 {
+            Direction::Received
+        } else {
+            Direction::Sent
+        };
+        self.timestamp = self.timestamp.saturating_add(varint >> 1);
+
+        let mut header = [0; 2];
+        read_exact(&mut self.inner, &mut header)?;
+        let len = usize::from(header[1]) + 2;
+        if !(4..=MAX_FRAME_BYTES).contains(&len) {
+            return Err(Error::InvalidFrame);
+        }
+
+        let mut frame = alloc::vec![0; len];
+        frame[..2].copy_from_slice(&header);
+        read_exact(&mut self.inner, &mut frame[2..])?;
+
+        Ok(Some(Record {
+            timestamp: self.timestamp,
+            direction,
+            frame,
+        }))
+    }
+}
+
+impl<R: io::Read> Iterator for Reader<R> {
+    type Item = Result<Record, Error<io::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+fn read_exact(reader: &mut impl io::Read, buf: &mut [u8]) -> Result<(), Error<io::Error>> {
+    reader.read_exact(buf).map_err(|err| {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            Error::UnexpectedEof
+        } else {
+            Error::Io(err)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::super::tests::{LINK_STATISTICS, PING, capture};
+    use super::*;
+    use crate::{Address, Packet};
+
+    #[tokio::test]
+    async fn round_trip() {
+        let bytes = capture(&[
+            (1_000, Direction::Sent, PING),
+            (5_000, Direction::Received, LINK_STATISTICS),
+            // Goes backwards
+            (4_000, Direction::Received, PING),
+            (3_600_001_000, Direction::Sent, PING),
+        ])
+        .await;
+
+        let records = Reader::new(&bytes[..])
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let summary: Vec<_> = records
+            .iter()
+            .map(|record| (record.timestamp, record.direction, &record.frame[..]))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (0, Direction::Sent, PING),
+                (4_000, Direction::Received, LINK_STATISTICS),
+                (4_000, Direction::Received, PING),
+                (3_600_000_000, Direction::Sent, PING),
+            ]
+        );
+
+        assert_eq!(
+            records[0].packet().unwrap(),
+            Packet::DevicePing {
+                to: Address::Broadcast,
+                from: Address::Handset,
+            }
+        );
+        assert!(matches!(
+            records[1].packet(),
+            Ok(Packet::LinkStatistics { .. })
+        ));
+    }
+
+    #[test]
+    fn reader_errors() {
+        assert!(matches!(
+            Reader::new(&b"CRSFCAX\x01"[..]),
+            Err(Error::BadMagic)
+        ));
+        assert!(matches!(
+            Reader::new(&b"CRSFCAP\x02"[..]),
+            Err(Error::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            Reader::new(&b"CRSF"[..]),
+            Err(Error::UnexpectedEof)
+        ));
+
+        let truncated = [&b"CRSFCAP\x01\x00"[..], &PING[..4]].concat();
+        let mut reader = Reader::new(&truncated[..]).unwrap();
+        assert!(matches!(reader.next_record(), Err(Error::UnexpectedEof)));
+
+        let overlong = [&b"CRSFCAP\x01"[..], &[0xFF; 11]].concat();
+        let mut reader = Reader::new(&overlong[..]).unwrap();
+        assert!(matches!(reader.next_record(), Err(Error::Malformed)));
+    }
+}
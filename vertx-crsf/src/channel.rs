@@ -0,0 +1,148 @@
+//! Conversions between raw 11-bit channel ticks, microseconds, and normalized
+//! values
+//!
+//! All conversions saturate at the ExpressLRS limits, `172..=1811` ticks or
+//! `988..=2012`µs, and map the centers (`992` and `1500`µs) exactly onto each
+//! other and onto zero.
+
+/// Lowest tick value, equivalent to [`MIN_US`]
+pub const MIN: u16 = 172;
+/// Center tick value, equivalent to [`CENTER_US`]
+pub const CENTER: u16 = 992;
+/// Highest tick value, equivalent to [`MAX_US`]
+pub const MAX: u16 = 1811;
+
+pub const MIN_US: u16 = 988;
+pub const CENTER_US: u16 = 1500;
+pub const MAX_US: u16 = 2012;
+
+const TICKS_SPAN: u32 = (MAX - MIN) as u32;
+const US_SPAN: u32 = (MAX_US - MIN_US) as u32;
+
+/// Convert ticks to microseconds, rounding to the nearest microsecond
+pub fn to_us(ticks: u16) -> u16 {
+    let ticks = u32::from(ticks.clamp(MIN, MAX) - MIN);
+    let us = (ticks * US_SPAN + TICKS_SPAN / 2) / TICKS_SPAN;
+    MIN_US + us as u16
+}
+
+/// Convert microseconds to ticks, rounding to the nearest tick
+pub fn from_us(us: u16) -> u16 {
+    let us = u32::from(us.clamp(MIN_US, MAX_US) - MIN_US);
+    let ticks = (us * TICKS_SPAN + US_SPAN / 2) / US_SPAN;
+    MIN + ticks as u16
+}
+
+/// Convert ticks to `-1.0..=1.0`
+pub fn to_f32(ticks: u16) -> f32 {
+    let ticks = ticks.clamp(MIN, MAX);
+    if ticks < CENTER {
+        -f32::from(CENTER - ticks) / f32::from(CENTER - MIN)
+    } else {
+        f32::from(ticks - CENTER) / f32::from(MAX - CENTER)
+    }
+}
+
+/// Convert `-1.0..=1.0` to ticks. `NaN` maps to [`CENTER`].
+pub fn from_f32(value: f32) -> u16 {
+    if value.is_nan() {
+        CENTER
+    } else if value < 0.0 {
+        let offset = value.max(-1.0) * -f32::from(CENTER - MIN);
+        CENTER - (offset + 0.5) as u16
+    } else {
+        let offset = value.min(1.0) * f32::from(MAX - CENTER);
+        CENTER + (offset + 0.5) as u16
+    }
+}
+
+/// Convert ticks to a fixed-point value, with `±i16::MAX` at the limits
+pub fn to_i16(ticks: u16) -> i16 {
+    let ticks = ticks.clamp(MIN, MAX);
+    let max = i32::from(i16::MAX);
+    let scaled = if ticks < CENTER {
+        let offset = i32::from(CENTER - ticks);
+        -(offset * max + i32::from(CENTER - MIN) / 2) / i32::from(CENTER - MIN)
+    } else {
+        let offset = i32::from(ticks - CENTER);
+        (offset * max + i32::from(MAX - CENTER) / 2) / i32::from(MAX - CENTER)
+    };
+    scaled as i16
+}
+
+/// Inverse of [`to_i16`]. `i16::MIN` saturates to [`MIN`].
+pub fn from_i16(value: i16) -> u16 {
+    let max = u32::from(i16::MAX.cast_unsigned());
+    let magnitude = u32::from(value.unsigned_abs()).min(max);
+    if value < 0 {
+        let span = u32::from(CENTER - MIN);
+        CENTER - ((magnitude * span + max / 2) / max) as u16
+    } else {
+        let span = u32::from(MAX - CENTER);
+        CENTER + ((magnitude * span + max / 2) / max) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits() {
+        assert_eq!(to_us(MIN), MIN_US);
+        assert_eq!(to_us(CENTER), CENTER_US);
+        assert_eq!(to_us(MAX), MAX_US);
+        assert_eq!(from_us(MIN_US), MIN);
+        assert_eq!(from_us(CENTER_US), CENTER);
+        assert_eq!(from_us(MAX_US), MAX);
+
+        assert_eq!(to_f32(MIN), -1.0);
+        assert_eq!(to_f32(CENTER), 0.0);
+        assert_eq!(to_f32(MAX), 1.0);
+        assert_eq!(from_f32(-1.0), MIN);
+        assert_eq!(from_f32(0.0), CENTER);
+        assert_eq!(from_f32(1.0), MAX);
+
+        assert_eq!(to_i16(MIN), -i16::MAX);
+        assert_eq!(to_i16(CENTER), 0);
+        assert_eq!(to_i16(MAX), i16::MAX);
+        assert_eq!(from_i16(-i16::MAX), MIN);
+        assert_eq!(from_i16(0), CENTER);
+        assert_eq!(from_i16(i16::MAX), MAX);
+    }
+
+    #[test]
+    fn saturating() {
+        assert_eq!(to_us(0), MIN_US);
+        assert_eq!(to_us(MIN - 1), MIN_US);
+        assert_eq!(to_us(MAX + 1), MAX_US);
+        assert_eq!(to_us(u16::MAX), MAX_US);
+        assert_eq!(from_us(0), MIN);
+        assert_eq!(from_us(MIN_US - 1), MIN);
+        assert_eq!(from_us(MAX_US + 1), MAX);
+
+        assert_eq!(to_f32(0), -1.0);
+        assert_eq!(to_f32(2047), 1.0);
+        assert_eq!(from_f32(-1.5), MIN);
+        assert_eq!(from_f32(f32::INFINITY), MAX);
+        assert_eq!(from_f32(f32::NAN), CENTER);
+
+        assert_eq!(to_i16(0), -i16::MAX);
+        assert_eq!(from_i16(i16::MIN), MIN);
+    }
+
+    #[test]
+    fn us_round_trip() {
+        for us in MIN_US..=MAX_US {
+            assert_eq!(to_us(from_us(us)), us);
+        }
+    }
+
+    #[test]
+    fn normalized_round_trip() {
+        for ticks in MIN..=MAX {
+            assert_eq!(from_f32(to_f32(ticks)), ticks);
+            assert_eq!(from_i16(to_i16(ticks)), ticks);
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use crate::{BASIC_CRC, MAX_FRAME_BYTES};
+
+/// Byte-at-a-time frame decoder that resynchronizes on noisy input
+///
+/// Bytes that cannot start a frame, frames with invalid lengths, and frames
+/// with bad CRCs are dropped. After a bad CRC, the already buffered bytes are
+/// rescanned for the next sync byte, so a valid frame hiding behind garbage is
+/// not lost.
+#[derive(Debug)]
+pub struct FrameDecoder {
+    buffer: [u8; MAX_FRAME_BYTES],
+    /// Number of buffered bytes
+    len: usize,
+    /// Length of the complete frame at the start of `buffer`, if the last call
+    /// to `push` or `next_frame` returned one
+    complete: usize,
+    dropped_bytes: u32,
+    crc_errors: u32,
+}
+
+impl FrameDecoder {
+    /// Largest valid length byte: everything but the sync and length bytes
+    const MAX_LENGTH: u8 = MAX_FRAME_BYTES as u8 - 2;
+    /// Smallest valid length byte: just the type and crc
+    const MIN_LENGTH: u8 = 2;
+
+    pub const fn new() -> Self {
+        Self {
+            buffer: [0; MAX_FRAME_BYTES],
+            len: 0,
+            complete: 0,
+            dropped_bytes: 0,
+            crc_errors: 0,
+        }
+    }
+
+    /// Add the next byte and return a complete, crc checked frame if there is
+    /// one.
+    ///
+    /// The returned frame includes the sync, length, and crc bytes and can be
+    /// passed directly to [`Packet::read`](crate::Packet::read). If a rescan
+    /// uncovers more than one complete frame, the rest are returned by
+    /// [`next_frame`](Self::next_frame).
+    pub fn push(&mut self, byte: u8) -> Option<&[u8]> {
+        self.consume_complete();
+
+        // There is always room: either a frame was just consumed or the buffered
+        // bytes are a strict prefix of a frame
+        self.buffer[self.len] = byte;
+        self.len += 1;
+
+        self.scan_complete()
+    }
+
+    /// Return the next complete frame that is already buffered, without adding
+    /// any input.
+    ///
+    /// Call this after [`push`](Self::push) returns a frame until it returns
+    /// `None`, so frames uncovered by a rescan are not held back until more
+    /// bytes arrive.
+    pub fn next_frame(&mut self) -> Option<&[u8]> {
+        self.consume_complete();
+        self.scan_complete()
+    }
+
+    /// Discard any partially received frame
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.complete = 0;
+    }
+
+    /// Total number of bytes discarded while searching for a valid frame
+    pub fn dropped_bytes(&self) -> u32 {
+        self.dropped_bytes
+    }
+
+    /// Total number of frames discarded due to a bad crc
+    pub fn crc_errors(&self) -> u32 {
+        self.crc_errors
+    }
+
+    /// Drop the frame returned by the last call to `push` or `next_frame`
+    fn consume_complete(&mut self) {
+        if self.complete > 0 {
+            self.consume(self.complete);
+            self.complete = 0;
+        }
+    }
+
+    fn scan_complete(&mut self) -> Option<&[u8]> {
+        self.complete = self.scan();
+        (self.complete > 0).then(|| &self.buffer[0..self.complete])
+    }
+
+    /// Drop bytes until the buffer either starts with a complete frame, in
+    /// which case its length is returned, or is a valid prefix of one.
+    fn scan(&mut self) -> usize {
+        while let Some(&sync) = self.buffer[0..self.len].first() {
+            if !crate::is_sync_byte(sync) {
+                self.drop_byte();
+                continue;
+            }
+
+            let Some(&length) = self.buffer[0..self.len].get(1) else {
+                return 0;
+            };
+
+            if !(Self::MIN_LENGTH..=Self::MAX_LENGTH).contains(&length) {
+                self.drop_byte();
+                continue;
+            }
+
+            // Include sync and length bytes
+            let total = usize::from(length) + 2;
+            if self.len < total {
+                return 0;
+            }
+
+            let crc = BASIC_CRC.checksum(&self.buffer[2..(total - 1)]);
+            if crc == self.buffer[total - 1] {
+                return total;
+            }
+
+            self.crc_errors = self.crc_errors.wrapping_add(1);
+            self.drop_byte();
+        }
+
+        0
+    }
+
+    fn drop_byte(&mut self) {
+        self.dropped_bytes = self.dropped_bytes.wrapping_add(1);
+        self.consume(1);
+    }
+
+    fn consume(&mut self, count: usize) {
+        self.buffer.copy_within(count..self.len, 0);
+        self.len -= count;
+    }
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::Packet;
+
+    const PING: &[u8] = &[0xC8, 0x04, 0x28, 0x00, 0xEA, 0x54];
+    const LINK_STATISTICS: &[u8] = &[
+        0xC8, 0x0C, 0x14, 0x24, 0x00, 0x64, 0x0A, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x39,
+    ];
+
+    fn decode_all(decoder: &mut FrameDecoder, bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut frames = Vec::new();
+        for &byte in bytes {
+            let mut frame = decoder.push(byte).map(<[u8]>::to_vec);
+            while let Some(complete) = frame {
+                frames.push(complete);
+                frame = decoder.next_frame().map(<[u8]>::to_vec);
+            }
+        }
+        frames
+    }
+
+    #[test]
+    fn clean_frames() {
+        let mut decoder = FrameDecoder::new();
+        let input = [PING, LINK_STATISTICS, PING].concat();
+
+        let frames = decode_all(&mut decoder, &input);
+        assert_eq!(frames, [PING, LINK_STATISTICS, PING]);
+        assert_eq!(decoder.dropped_bytes(), 0);
+        assert_eq!(decoder.crc_errors(), 0);
+
+        let packet = Packet::read(&mut &frames[1][..]).unwrap();
+        assert!(matches!(packet, Packet::LinkStatistics { .. }));
+    }
+
+    #[test]
+    fn leading_garbage() {
+        let mut decoder = FrameDecoder::new();
+        let input = [&[0x00, 0x12, 0xFF][..], PING].concat();
+
+        assert_eq!(decode_all(&mut decoder, &input), [PING]);
+        assert_eq!(decoder.dropped_bytes(), 3);
+    }
+
+    #[test]
+    fn bad_length() {
+        let mut decoder = FrameDecoder::new();
+        let input = [&[0xC8, 0x01, 0xC8, 0xFF][..], PING].concat();
+
+        assert_eq!(decode_all(&mut decoder, &input), [PING]);
+        assert_eq!(decoder.dropped_bytes(), 4);
+        assert_eq!(decoder.crc_errors(), 0);
+    }
+
+    #[test]
+    fn other_sync_bytes() {
+        let mut decoder = FrameDecoder::new();
+        let mut ping = PING.to_vec();
+        ping[0] = 0xEE;
+
+        assert_eq!(decode_all(&mut decoder, &ping), [&ping[..]]);
+        Packet::read(&mut &ping[..]).unwrap();
+    }
+
+    #[test]
+    fn rescan_after_bad_crc() {
+        let mut decoder = FrameDecoder::new();
+
+        // A bogus header claiming a 10 byte frame that swallows the start of a
+        // real one
+        let input = [&[0xC8, 0x08, 0x00][..], LINK_STATISTICS, PING].concat();
+
+        assert_eq!(decode_all(&mut decoder, &input), [LINK_STATISTICS, PING]);
+        assert_eq!(decoder.crc_errors(), 1);
+        assert_eq!(decoder.dropped_bytes(), 3);
+    }
+
+    #[test]
+    fn rescan_finds_multiple_frames() {
+        let mut decoder = FrameDecoder::new();
+
+        // A bogus header long enough to swallow two complete frames
+        let input = [&[0xC8, 0x15, 0x00][..], PING, PING, LINK_STATISTICS].concat();
+
+        assert_eq!(
+            decode_all(&mut decoder, &input),
+            [PING, PING, LINK_STATISTICS]
+        );
+        assert_eq!(decoder.crc_errors(), 1);
+    }
+
+    #[test]
+    fn rescan_without_more_input() {
+        let mut decoder = FrameDecoder::new();
+
+        // The bogus frame ends partway through the link statistics frame
+        let input = [&[0xC8, 0x15, 0x00][..], PING, PING, LINK_STATISTICS].concat();
+        let (head, tail) = input.split_at(0x15 + 2);
+        let (&last, head) = head.split_last().unwrap();
+
+        for &byte in head {
+            assert!(decoder.push(byte).is_none());
+        }
+
+        assert_eq!(decoder.push(last), Some(PING));
+        assert_eq!(decoder.next_frame(), Some(PING));
+        assert_eq!(decoder.next_frame(), None);
+        assert_eq!(decode_all(&mut decoder, tail), [LINK_STATISTICS]);
+    }
+
+    #[test]
+    fn bad_crc() {
+        let mut decoder = FrameDecoder::new();
+        let mut corrupt = PING.to_vec();
+        corrupt[4] ^= 0x01;
+        let input = [&corrupt[..], PING].concat();
+
+        assert_eq!(decode_all(&mut decoder, &input), [PING]);
+        assert_eq!(decoder.crc_errors(), 1);
+        assert_eq!(decoder.dropped_bytes(), corrupt.len() as u32);
+    }
+
+    #[test]
+    fn reset() {
+        let mut decoder = FrameDecoder::new();
+        for &byte in &LINK_STATISTICS[0..5] {
+            assert!(decoder.push(byte).is_none());
+        }
+
+        decoder.reset();
+        assert_eq!(decode_all(&mut decoder, PING), [PING]);
+    }
+}
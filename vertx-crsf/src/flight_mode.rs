@@ -0,0 +1,68 @@
+use core::{fmt, str};
+
+use crate::MAX_PAYLOAD_BYTES;
+
+#[cfg(not(feature = "heapless"))]
+type Inner = alloc::vec::Vec<u8>;
+#[cfg(feature = "heapless")]
+type Inner = heapless::Vec<u8, { FlightModeName::MAX_LEN }>;
+
+/// Name from a [`Packet::FlightMode`](crate::Packet::FlightMode), without the
+/// nul terminator
+///
+/// With the `heapless` feature, this is stored inline rather than allocated.
+#[derive(Clone, PartialEq, Eq, Hash, Default)]
+pub struct FlightModeName(Inner);
+
+impl FlightModeName {
+    /// Longest name that fits in a frame alongside its nul terminator
+    pub const MAX_LEN: usize = MAX_PAYLOAD_BYTES - 1;
+
+    /// Returns `None` if `name` contains a nul byte or is longer than
+    /// [`Self::MAX_LEN`]
+    pub fn new(name: &[u8]) -> Option<Self> {
+        if name.len() > Self::MAX_LEN || name.contains(&0) {
+            return None;
+        }
+
+        let mut inner = Inner::new();
+        inner.extend(name.iter().copied());
+        Some(Self(inner))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn to_str(&self) -> Result<&str, str::Utf8Error> {
+        str::from_utf8(self.as_bytes())
+    }
+}
+
+impl fmt::Debug for FlightModeName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}\"", self.as_bytes().escape_ascii())
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for FlightModeName {
+    fn format(&self, fmt: defmt::Formatter<'_>) {
+        defmt::write!(fmt, "{=[u8]:a}", self.as_bytes());
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for FlightModeName {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.as_bytes().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for FlightModeName {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = Inner::deserialize(deserializer)?;
+        Self::new(&name).ok_or_else(|| serde::de::Error::custom("invalid flight mode name"))
+    }
+}
@@ -1,13 +1,39 @@
 #![no_std]
 
 extern crate alloc;
+#[cfg(any(test, feature = "std"))]
+extern crate std;
+
+pub mod capture;
+pub mod channel;
+mod decoder;
+mod flight_mode;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod msp;
+pub mod parameter;
+mod transport;
 
 use alloc::ffi::CString;
-use alloc::vec;
+use alloc::vec::Vec;
+use core::ffi::CStr;
 use core::fmt;
+use core::ops::{Deref, DerefMut};
 
 use crc::Crc;
 
+pub use self::decoder::FrameDecoder;
+pub use self::flight_mode::FlightModeName;
+pub use self::transport::{Duplex, Transport};
+
+/// Maximum size of a full frame, including the sync, length, type, and CRC
+/// bytes
+pub const MAX_FRAME_BYTES: usize = 64;
+/// Maximum size of a frame payload, excluding the sync, length, type, and CRC
+/// bytes
+pub const MAX_PAYLOAD_BYTES: usize = MAX_FRAME_BYTES - 4;
+const SYNC_BYTE: u8 = 0xC8;
+
 // TODO: benchmark lookup table options
 const BASIC_CRC: Crc<u8> = Crc::<u8>::new(&crc::Algorithm {
     width: 8,
@@ -20,10 +46,11 @@ const BASIC_CRC: Crc<u8> = Crc::<u8>::new(&crc::Algorithm {
     residue: 0x00,
 });
 
-#[expect(unused)]
+/// Inner crc of `COMMAND` frames, covering the type byte through the end of
+/// the command payload
 const EXTENDED_CRC: Crc<u8> = Crc::<u8>::new(&crc::Algorithm {
     width: 8,
-    poly: 0xD5,
+    poly: 0xBA,
     init: 0x00,
     refin: false,
     refout: false,
@@ -48,7 +75,7 @@ macro_rules! enum_repr {
         }
 
         impl $name {
-            fn from_raw(raw: $repr) -> Option<Self> {
+            pub(crate) fn from_raw(raw: $repr) -> Option<Self> {
                 match raw {
                     $( $value => Some(Self::$variant), )*
                     _ => None,
@@ -57,10 +84,13 @@ macro_rules! enum_repr {
         }
     }
 }
+use enum_repr;
 
 enum_repr! {
     #[repr(u8)]
     #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub enum Address {
         Broadcast = 0x00,
         Usb = 0x10,
@@ -89,17 +119,20 @@ enum_repr! {
     }
 }
 
-impl Address {
-    fn from_u16(raw: u16) -> Option<Self> {
-        if raw > u16::from(u8::MAX) {
-            None
-        } else {
-            Self::from_raw(raw as u8)
-        }
-    }
+/// Check if `byte` can start a frame. Along with the standard `0xC8` sync byte
+/// (which doubles as the flight controller address), frames to/from the
+/// handset, receiver, and transmitter may start with the destination address.
+fn is_sync_byte(byte: u8) -> bool {
+    byte == SYNC_BYTE
+        || matches!(
+            Address::from_raw(byte),
+            Some(Address::Handset | Address::Receiver | Address::Transmitter)
+        )
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Packet {
     /// GPS position, ground speed, heading, altitude, satellite count
     Gps {
@@ -169,15 +202,46 @@ pub enum Packet {
     /// Channel data (both handset to TX and RX to flight controller)
     RcChannelsPacked(RcChannelsPacked<[u8; 22]>),
     /// Channels subset data **(CRSFv3 only)**
-    SubsetRcChannelsPacked,
-    /// Receiver RSSI percent, power?
-    LinkRxId,
-    /// Transmitter RSSI percent, power, fps?
-    LinkTxId,
+    SubsetRcChannelsPacked(SubsetRcChannels),
+    /// Receiver side link statistics
+    LinkRxId {
+        /// RSSI in negative dBm
+        rssi: u8,
+        /// RSSI in whole percentage points
+        rssi_percent: u8,
+        /// Link quality in whole percentage points
+        link_quality: u8,
+        /// Signal to noise ratio in dB
+        snr: i8,
+        /// Transmit power in dBm
+        power: u8,
+    },
+    /// Transmitter side link statistics
+    LinkTxId {
+        /// RSSI in negative dBm
+        rssi: u8,
+        /// RSSI in whole percentage points
+        rssi_percent: u8,
+        /// Link quality in whole percentage points
+        link_quality: u8,
+        /// Signal to noise ratio in dB
+        snr: i8,
+        /// Transmit power in dBm
+        power: u8,
+        /// Packet rate in units of 10Hz
+        rate: u8,
+    },
     /// Attitude: pitch, roll, yaw
-    Attitude,
+    Attitude {
+        /// Pitch in radians (4 decimals)
+        pitch: i16,
+        /// Roll in radians (4 decimals)
+        roll: i16,
+        /// Yaw in radians (4 decimals)
+        yaw: i16,
+    },
     /// Flight controller flight mode string
-    FlightMode(CString),
+    FlightMode(FlightModeName),
     /// Sender requesting `DeviceInfo` from all destination devices
     DevicePing {
         to: Address,
@@ -185,43 +249,241 @@ pub enum Packet {
     },
     /// Device name, firmware version, hardware version, serial number (`Ping`
     /// response)
-    DeviceInfo,
-    /// Configuration item data chunk
-    ParameterSettingsEntry,
+    DeviceInfo {
+        to: Address,
+        from: Address,
+        #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+        name: CString,
+        serial_number: u32,
+        hardware_version: u32,
+        /// Firmware version. ExpressLRS uses `0x00MMmmpp`
+        software_version: u32,
+        /// Number of configuration parameters the device exposes
+        parameter_count: u8,
+        /// Parameter protocol version
+        protocol_version: u8,
+    },
+    /// Configuration item data chunk. See [`parameter::Client`] for
+    /// reassembling and parsing complete entries.
+    ParameterSettingsEntry {
+        to: Address,
+        from: Address,
+        index: u8,
+        /// Number of chunks still to be requested after this one
+        chunks_remaining: u8,
+        data: Vec<u8>,
+    },
     /// Configuration item read request
-    ParameterRead,
+    ParameterRead {
+        to: Address,
+        from: Address,
+        index: u8,
+        /// Zero-based chunk number
+        chunk: u8,
+    },
     /// Configuration item write request
-    ParameterWrite,
+    ParameterWrite {
+        to: Address,
+        from: Address,
+        index: u8,
+        /// Encoded value. Its layout depends on the parameter type; see
+        /// [`parameter::Value`]
+        value: Vec<u8>,
+    },
     /// **Non-standard** ExpressLRS good/bad packet count, status flags
-    ElrsStatus,
+    ElrsStatus {
+        to: Address,
+        from: Address,
+        /// Packets lost in the last second
+        bad_packets: u8,
+        /// Packets received in the last second
+        good_packets: u16,
+        flags: ElrsFlags,
+        /// Warning or error message, may be empty
+        #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+        message: CString,
+    },
     /// **CRSF** command execute
-    Command,
-    /// Extended type used for OPENTX_SYNC
-    RadioId,
+    Command {
+        to: Address,
+        from: Address,
+        command: Command,
+    },
+    /// Extended type used for OPENTX_SYNC, the only subtype that is decoded.
+    /// Lets the handset lock its channel frame timing to the transmitter's
+    /// packet rate.
+    RadioId {
+        to: Address,
+        from: Address,
+        /// Desired interval between channel frames in 0.1µs
+        interval: u32,
+        /// How early the last channel frame arrived in 0.1µs, after allowing
+        /// for the transmitter's safety margin
+        offset: i32,
+    },
     KissRequest,
     KissResponse,
-    /// MSP parameter request / command
-    MspRequest,
+    /// MSP parameter request / command chunk. See [`msp::Client`].
+    MspRequest {
+        to: Address,
+        from: Address,
+        status: msp::Status,
+        data: Vec<u8>,
+    },
     /// MSP parameter response chunk
-    MspResponse,
-    /// MSP parameter write
-    MspWrite,
+    MspResponse {
+        to: Address,
+        from: Address,
+        status: msp::Status,
+        data: Vec<u8>,
+    },
+    /// MSP parameter write chunk
+    MspWrite {
+        to: Address,
+        from: Address,
+        status: msp::Status,
+        data: Vec<u8>,
+    },
     /// MSP DisplayPort control command **(CRSFv3 only)**
     DisplayportCommand,
     /// Ardupilot output?
     ArdupilotResponse,
+    /// Any frame type that is not (yet) supported
+    Raw(RawPacket),
+}
+
+/// Payload of a `COMMAND` frame. Unsupported commands decode to
+/// [`Packet::Raw`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Command {
+    /// Put the receiver into bind mode
+    Bind,
+    /// Set the model ID used for model match
+    SelectModel(u8),
+    /// Propose switching a port to a different baud rate
+    SpeedProposal { port: u8, baud_rate: u32 },
+    /// Reply to a [`SpeedProposal`](Self::SpeedProposal)
+    SpeedResponse { port: u8, accepted: bool },
+}
+
+bitfield::bitfield! {
+    /// Status flags from [`Packet::ElrsStatus`]
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct ElrsFlags(u8);
+    impl Debug;
+    /// A receiver is connected
+    pub connected, set_connected: 0;
+    /// The connected receiver has a different model ID
+    pub model_mismatch, set_model_mismatch: 2;
+    pub armed, set_armed: 3;
+    /// Critical warning bits, which the ExpressLRS Lua script blocks on
+    pub critical, _: 7, 5;
+}
+
+/// Undecoded frame
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "RawPacketRepr", try_from = "RawPacketRepr")
+)]
+pub struct RawPacket {
+    kind: u8,
+    len: u8,
+    payload: [u8; MAX_PAYLOAD_BYTES],
+}
+
+impl RawPacket {
+    /// Returns `None` if `payload` is longer than [`MAX_PAYLOAD_BYTES`]
+    pub fn new(kind: u8, payload: &[u8]) -> Option<Self> {
+        let mut raw = Self {
+            kind,
+            len: payload.len().try_into().ok()?,
+            payload: [0; MAX_PAYLOAD_BYTES],
+        };
+
+        raw.payload
+            .get_mut(0..payload.len())?
+            .copy_from_slice(payload);
+        Some(raw)
+    }
+
+    /// Frame type byte
+    pub fn kind(&self) -> u8 {
+        self.kind
+    }
+
+    /// Frame payload, including the destination & origin addresses of extended
+    /// frames
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[0..self.len.into()]
+    }
+}
+
+impl fmt::Debug for RawPacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawPacket")
+            .field("kind", &self.kind)
+            .field("payload", &self.payload())
+            .finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for RawPacket {
+    fn format(&self, fmt: defmt::Formatter<'_>) {
+        defmt::write!(
+            fmt,
+            "RawPacket {{ kind: {=u8:#x}, payload: {=[u8]:#x} }}",
+            self.kind,
+            self.payload()
+        );
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RawPacketRepr {
+    kind: u8,
+    payload: Vec<u8>,
+}
+
+#[cfg(feature = "serde")]
+impl From<RawPacket> for RawPacketRepr {
+    fn from(raw: RawPacket) -> Self {
+        Self {
+            kind: raw.kind,
+            payload: raw.payload().into(),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RawPacketRepr> for RawPacket {
+    type Error = &'static str;
+
+    fn try_from(raw: RawPacketRepr) -> Result<Self, Self::Error> {
+        Self::new(raw.kind, &raw.payload).ok_or("payload too long")
+    }
 }
 
 enum_repr! {
     #[repr(u8)]
     #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     #[expect(non_camel_case_types)]
     pub enum TxPower {
         mW_0 = 0,
         mW_10 = 1,
         mW_25 = 2,
-        mW_50 = 7,
+        mW_50 = 8,
         mW_100 = 3,
+        mW_250 = 7,
         mW_500 = 4,
         mW_1000 = 5,
         mW_2000 = 6,
@@ -236,6 +498,7 @@ impl fmt::Debug for TxPower {
             Self::mW_25 => "25mW",
             Self::mW_50 => "50mW",
             Self::mW_100 => "100mW",
+            Self::mW_250 => "250mW",
             Self::mW_500 => "500mW",
             Self::mW_1000 => "1000mW",
             Self::mW_2000 => "2000mW",
@@ -246,6 +509,8 @@ impl fmt::Debug for TxPower {
 
 bitfield::bitfield! {
     #[derive(Debug, Clone, PartialEq)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct RcChannelsPacked([u8]);
     u16;
     channel0, set_channel0: 10, 0;
@@ -267,6 +532,58 @@ bitfield::bitfield! {
 }
 
 impl RcChannelsPacked<[u8; 22]> {
+    /// Pack raw ticks, saturating at the 11-bit maximum
+    pub fn pack(channels: &[u16; 16]) -> Self {
+        let [
+            ch0,
+            ch1,
+            ch2,
+            ch3,
+            ch4,
+            ch5,
+            ch6,
+            ch7,
+            ch8,
+            ch9,
+            ch10,
+            ch11,
+            ch12,
+            ch13,
+            ch14,
+            ch15,
+        ] = channels.map(|ch| ch.min(0x7FF));
+
+        let mut packed = Self([0; 22]);
+        packed.set_channel0(ch0);
+        packed.set_channel1(ch1);
+        packed.set_channel2(ch2);
+        packed.set_channel3(ch3);
+        packed.set_channel4(ch4);
+        packed.set_channel5(ch5);
+        packed.set_channel6(ch6);
+        packed.set_channel7(ch7);
+        packed.set_channel8(ch8);
+        packed.set_channel9(ch9);
+        packed.set_channel10(ch10);
+        packed.set_channel11(ch11);
+        packed.set_channel12(ch12);
+        packed.set_channel13(ch13);
+        packed.set_channel14(ch14);
+        packed.set_channel15(ch15);
+        packed
+    }
+
+    /// Pack channels given in microseconds. See [`channel::from_us`].
+    pub fn from_us(channels: &[u16; 16]) -> Self {
+        Self::pack(&channels.map(channel::from_us))
+    }
+
+    /// Unpack channels as microseconds. See [`channel::to_us`].
+    pub fn to_us(&self) -> [u16; 16] {
+        self.unpack().map(channel::to_us)
+    }
+
+    /// Unpack raw ticks
     pub fn unpack(&self) -> [u16; 16] {
         [
             self.channel0(),
@@ -289,13 +606,226 @@ impl RcChannelsPacked<[u8; 22]> {
     }
 }
 
+enum_repr! {
+    #[repr(u8)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub enum Resolution {
+        Bits10 = 0,
+        Bits11 = 1,
+        Bits12 = 2,
+        Bits13 = 3,
+    }
+}
+
+impl Resolution {
+    pub const fn bits(self) -> u8 {
+        self as u8 + 10
+    }
+
+    /// Largest channel value representable at this resolution
+    pub const fn max(self) -> u16 {
+        (1 << self.bits()) - 1
+    }
+}
+
+/// A contiguous run of channels at a configurable resolution
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "SubsetRcChannelsRepr", try_from = "SubsetRcChannelsRepr")
+)]
+pub struct SubsetRcChannels {
+    start: u8,
+    resolution: Resolution,
+    len: u8,
+    channels: [u16; Self::MAX_CHANNELS],
+}
+
+impl SubsetRcChannels {
+    /// Most channels that fit in a single frame, at the lowest resolution
+    pub const MAX_CHANNELS: usize = (MAX_PAYLOAD_BYTES - 1) * 8 / 10;
+    /// Highest starting channel index
+    pub const MAX_START: u8 = 0x1F;
+
+    /// Returns `None` if `start` is greater than [`Self::MAX_START`], any
+    /// channel is greater than [`Resolution::max`], or the channels do not fit
+    /// in a single frame.
+    pub fn new(start: u8, resolution: Resolution, channels: &[u16]) -> Option<Self> {
+        let max_len = (MAX_PAYLOAD_BYTES - 1) * 8 / usize::from(resolution.bits());
+        if start > Self::MAX_START
+            || channels.len() > max_len
+            || channels.iter().any(|&ch| ch > resolution.max())
+        {
+            return None;
+        }
+
+        let mut subset = Self {
+            start,
+            resolution,
+            len: channels.len() as u8,
+            channels: [0; Self::MAX_CHANNELS],
+        };
+        subset.channels[0..channels.len()].copy_from_slice(channels);
+        Some(subset)
+    }
+
+    /// Index of the first channel
+    pub fn start(&self) -> u8 {
+        self.start
+    }
+
+    pub fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    /// Channel values, starting with channel [`start`](Self::start)
+    pub fn channels(&self) -> &[u16] {
+        &self.channels[0..self.len.into()]
+    }
+
+    fn unpack(payload: &[u8]) -> Result<Self, Malformed> {
+        let (&config, packed) = payload.split_first().ok_or(Malformed)?;
+        let start = config & Self::MAX_START;
+        let resolution = Resolution::from_raw((config >> 5) & 0b11).ok_or(Malformed)?;
+
+        let bits = u32::from(resolution.bits());
+        let len = (packed.len() * 8 / bits as usize).min(Self::MAX_CHANNELS);
+
+        let mut subset = Self {
+            start,
+            resolution,
+            len: len as u8,
+            channels: [0; Self::MAX_CHANNELS],
+        };
+
+        let mut buffer = 0u32;
+        let mut buffered = 0;
+        let mut bytes = packed.iter();
+        for channel in &mut subset.channels[0..len] {
+            while buffered < bits {
+                let byte = bytes.next().ok_or(Malformed)?;
+                buffer |= u32::from(*byte) << buffered;
+                buffered += 8;
+            }
+
+            *channel = (buffer & u32::from(resolution.max())) as u16;
+            buffer >>= bits;
+            buffered -= bits;
+        }
+
+        Ok(subset)
+    }
+
+    fn pack(&self, writer: &mut PacketWriter) {
+        writer.u8(self.start | ((self.resolution as u8) << 5));
+
+        let bits = u32::from(self.resolution.bits());
+        let mut buffer = 0u32;
+        let mut buffered = 0;
+        for &channel in self.channels() {
+            buffer |= u32::from(channel) << buffered;
+            buffered += bits;
+
+            while buffered >= 8 {
+                writer.u8(buffer as u8);
+                buffer >>= 8;
+                buffered -= 8;
+            }
+        }
+
+        if buffered > 0 {
+            writer.u8(buffer as u8);
+        }
+    }
+}
+
+impl fmt::Debug for SubsetRcChannels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SubsetRcChannels")
+            .field("start", &self.start)
+            .field("resolution", &self.resolution)
+            .field("channels", &self.channels())
+            .finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for SubsetRcChannels {
+    fn format(&self, fmt: defmt::Formatter<'_>) {
+        defmt::write!(
+            fmt,
+            "SubsetRcChannels {{ start: {=u8}, resolution: {}, channels: {} }}",
+            self.start,
+            self.resolution,
+            self.channels()
+        );
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SubsetRcChannelsRepr {
+    start: u8,
+    resolution: Resolution,
+    channels: Vec<u16>,
+}
+
+#[cfg(feature = "serde")]
+impl From<SubsetRcChannels> for SubsetRcChannelsRepr {
+    fn from(subset: SubsetRcChannels) -> Self {
+        Self {
+            start: subset.start,
+            resolution: subset.resolution,
+            channels: subset.channels().into(),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<SubsetRcChannelsRepr> for SubsetRcChannels {
+    type Error = &'static str;
+
+    fn try_from(subset: SubsetRcChannelsRepr) -> Result<Self, Self::Error> {
+        Self::new(subset.start, subset.resolution, &subset.channels).ok_or("invalid channel subset")
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WriteError<E> {
+    Io(E),
+    /// The packet does not fit in [`MAX_FRAME_BYTES`]
+    PayloadTooLong,
+    /// Encoding this packet type is not (yet) supported
+    Unsupported,
+}
+
 #[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum PacketError<E> {
     ReadError(E),
     UnexpectedEof,
     InvalidSyncByte(u8),
-    InvalidPacketKind(u8),
     BadCrc,
+    /// The inner crc of a `COMMAND` frame is invalid
+    BadCommandCrc,
+    /// The length byte or payload is invalid for the frame type
+    MalformedPayload,
+    /// An address field does not match any known [`Address`]
+    UnknownAddress(u8),
+}
+
+/// Marker for a payload that is too short or contains invalid data
+#[derive(Debug)]
+struct Malformed;
+
+impl<E> From<Malformed> for PacketError<E> {
+    fn from(Malformed: Malformed) -> Self {
+        Self::MalformedPayload
+    }
 }
 
 impl<E> From<embedded_io::ReadExactError<E>> for PacketError<E> {
@@ -308,6 +838,27 @@ impl<E> From<embedded_io::ReadExactError<E>> for PacketError<E> {
 }
 
 impl Packet {
+    /// Destination of an extended frame, or `None` for frames without one
+    pub fn destination(&self) -> Option<Address> {
+        match self {
+            Self::DevicePing { to, .. }
+            | Self::DeviceInfo { to, .. }
+            | Self::ParameterSettingsEntry { to, .. }
+            | Self::ParameterRead { to, .. }
+            | Self::ParameterWrite { to, .. }
+            | Self::ElrsStatus { to, .. }
+            | Self::Command { to, .. }
+            | Self::RadioId { to, .. }
+            | Self::MspRequest { to, .. }
+            | Self::MspResponse { to, .. }
+            | Self::MspWrite { to, .. } => Some(*to),
+            Self::Raw(raw) if raw.kind() >= 0x28 => {
+                raw.payload().first().copied().and_then(Address::from_raw)
+            }
+            _ => None,
+        }
+    }
+
     pub fn read<R: embedded_io::Read<Error = E>, E>(raw: &mut R) -> Result<Self, PacketError<E>> {
         let mut reader = PacketReader::new(raw)?;
 
@@ -315,85 +866,88 @@ impl Packet {
             match reader.packet_type {
                 // CRSF_FRAMETYPE_GPS
                 0x02 => Self::Gps {
-                    latitude: reader.i32(),
-                    longitude: reader.i32(),
-                    speed: reader.i16(),
-                    heading: reader.i16(),
-                    altitude: i32::from(reader.u16()) - 1000,
-                    satellites: reader.u8(),
+                    latitude: reader.i32()?,
+                    longitude: reader.i32()?,
+                    speed: reader.i16()?,
+                    heading: reader.i16()?,
+                    altitude: i32::from(reader.u16()?) - 1000,
+                    satellites: reader.u8()?,
                 },
                 // CRSF_FRAMETYPE_VARIO
                 0x07 => Self::Vario {
-                    vertical_speed: reader.i16(),
+                    vertical_speed: reader.i16()?,
                 },
                 // CRSF_FRAMETYPE_BATTERY_SENSOR
                 0x08 => Self::BatterySensor {
-                    voltage: reader.i16(),
-                    current: reader.i16(),
-                    used: reader.i24(),
-                    remaining: reader.i8(),
+                    voltage: reader.i16()?,
+                    current: reader.i16()?,
+                    used: reader.i24()?,
+                    remaining: reader.i8()?,
                 },
                 // CRSF_FRAMETYPE_BARO_ALTITUDE
                 0x09 => Self::BarometricAltitude {
-                    altitude: convert_barometric_altitude(reader.i16()),
-                    vertical_speed: (reader.payload_length == 2).then(|| reader.i16()),
+                    altitude: convert_barometric_altitude(reader.i16()?),
+                    vertical_speed: if reader.payload_length == 4 {
+                        Some(reader.i16()?)
+                    } else {
+                        None
+                    },
                 },
                 // CRSF_FRAMETYPE_HEARTBEAT
                 0x0B => {
-                    // FIXME
-                    let origin = Address::from_u16(reader.u16()).unwrap();
+                    let origin = u8::try_from(reader.u16()?).map_err(|_| Malformed)?;
+                    let origin =
+                        Address::from_raw(origin).ok_or(PacketError::UnknownAddress(origin))?;
 
                     Self::Heartbeat { origin }
                 }
                 // CRSF_FRAMETYPE_LINK_STATISTICS
-                0x14 => {
-                    Self::LinkStatistics {
-                        up_rssi1: reader.u8(),
-                        up_rssi2: reader.u8(),
-                        up_lq: reader.u8(),
-                        up_snr: reader.i8(),
-                        active_antenna: reader.u8(),
-                        mode: reader.u8(),
-                        // FIXME
-                        tx_power: TxPower::from_raw(reader.u8()).unwrap(),
-                        down_rssi: reader.u8(),
-                        down_lq: reader.u8(),
-                        down_snr: reader.i8(),
-                    }
-                }
+                0x14 => Self::LinkStatistics {
+                    up_rssi1: reader.u8()?,
+                    up_rssi2: reader.u8()?,
+                    up_lq: reader.u8()?,
+                    up_snr: reader.i8()?,
+                    active_antenna: reader.u8()?,
+                    mode: reader.u8()?,
+                    tx_power: TxPower::from_raw(reader.u8()?).ok_or(Malformed)?,
+                    down_rssi: reader.u8()?,
+                    down_lq: reader.u8()?,
+                    down_snr: reader.i8()?,
+                },
                 // CRSF_FRAMETYPE_RC_CHANNELS_PACKED
-                0x16 => {
-                    // FIXME
-                    let mut channels = [0; 22];
-                    channels.clone_from_slice(reader.payload());
-                    Self::RcChannelsPacked(RcChannelsPacked(channels))
-                }
+                0x16 => Self::RcChannelsPacked(RcChannelsPacked(reader.array()?)),
                 // CRSF_FRAMETYPE_SUBSET_RC_CHANNELS_PACKED
-                0x17 => todo!(),
+                0x17 => Self::SubsetRcChannelsPacked(SubsetRcChannels::unpack(reader.rest())?),
                 // CRSF_FRAMETYPE_LINK_RX_ID
-                0x1C => todo!(),
+                0x1C => Self::LinkRxId {
+                    rssi: reader.u8()?,
+                    rssi_percent: reader.u8()?,
+                    link_quality: reader.u8()?,
+                    snr: reader.i8()?,
+                    power: reader.u8()?,
+                },
                 // CRSF_FRAMETYPE_LINK_TX_ID
-                0x1D => todo!(),
+                0x1D => Self::LinkTxId {
+                    rssi: reader.u8()?,
+                    rssi_percent: reader.u8()?,
+                    link_quality: reader.u8()?,
+                    snr: reader.i8()?,
+                    power: reader.u8()?,
+                    rate: reader.u8()?,
+                },
                 // CRSF_FRAMETYPE_ATTITUDE
-                0x1E => todo!(),
+                0x1E => Self::Attitude {
+                    pitch: reader.i16()?,
+                    roll: reader.i16()?,
+                    yaw: reader.i16()?,
+                },
                 // CRSF_FRAMETYPE_FLIGHT_MODE
-                0x21 => {
-                    let length = reader.payload_length.into();
-                    // FIXME
-                    let mut data = vec![0; length];
-                    data.clone_from_slice(reader.payload());
-                    // Strip trailing 0
-                    data.truncate(length - 1);
-
-                    // FIXME: return error instead of unwrapping?
-                    let mode = CString::new(data).unwrap();
-                    Self::FlightMode(mode)
-                }
+                0x21 => Self::FlightMode(reader.flight_mode()?),
 
-                kind => return Err(PacketError::InvalidPacketKind(kind)),
+                _ => Self::Raw(reader.raw()),
             }
         } else {
-            let reader = reader.extended()?;
+            let mut reader = reader.extended()?;
 
             match reader.packet_type() {
                 // CRSF_FRAMETYPE_DEVICE_PING
@@ -402,40 +956,407 @@ impl Packet {
                     from: reader.from,
                 },
                 // CRSF_FRAMETYPE_DEVICE_INFO
-                0x29 => todo!(), // extended
+                0x29 => Self::DeviceInfo {
+                    to: reader.to,
+                    from: reader.from,
+                    name: reader.c_string()?,
+                    serial_number: reader.u32()?,
+                    hardware_version: reader.u32()?,
+                    software_version: reader.u32()?,
+                    parameter_count: reader.u8()?,
+                    protocol_version: reader.u8()?,
+                },
                 // CRSF_FRAMETYPE_PARAMETER_SETTINGS_ENTRY
-                0x2B => todo!(), // extended
+                0x2B => Self::ParameterSettingsEntry {
+                    to: reader.to,
+                    from: reader.from,
+                    index: reader.u8()?,
+                    chunks_remaining: reader.u8()?,
+                    data: reader.rest().to_vec(),
+                },
                 // CRSF_FRAMETYPE_PARAMETER_READ
-                0x2C => todo!(), // extended
+                0x2C => Self::ParameterRead {
+                    to: reader.to,
+                    from: reader.from,
+                    index: reader.u8()?,
+                    chunk: reader.u8()?,
+                },
                 // CRSF_FRAMETYPE_PARAMETER_WRITE
-                0x2D => todo!(), // extended
+                0x2D => Self::ParameterWrite {
+                    to: reader.to,
+                    from: reader.from,
+                    index: reader.u8()?,
+                    value: reader.rest().to_vec(),
+                },
                 // CRSF_FRAMETYPE_ELRS_STATUS
-                0x2E => todo!(), // extended
+                0x2E => Self::ElrsStatus {
+                    to: reader.to,
+                    from: reader.from,
+                    bad_packets: reader.u8()?,
+                    good_packets: reader.u16()?,
+                    flags: ElrsFlags(reader.u8()?),
+                    message: reader.c_string()?,
+                },
                 // CRSF_FRAMETYPE_COMMAND
-                0x32 => todo!(), // extended
+                0x32 => match reader.command()? {
+                    Some(command) => Self::Command {
+                        to: reader.to,
+                        from: reader.from,
+                        command,
+                    },
+                    None => Self::Raw(reader.raw()),
+                },
                 // CRSF_FRAMETYPE_RADIO_ID
-                0x3A => todo!(), // extended
-                // CRSF_FRAMETYPE_KISS_REQ
-                0x78 => todo!(), // extended
-                // CRSF_FRAMETYPE_KISS_RESP
-                0x79 => todo!(), // extended
+                0x3A => match reader.u8()? {
+                    // CRSF_FRAMETYPE_OPENTX_SYNC
+                    0x10 => Self::RadioId {
+                        to: reader.to,
+                        from: reader.from,
+                        interval: reader.u32()?,
+                        offset: reader.i32()?,
+                    },
+                    _ => Self::Raw(reader.raw()),
+                },
                 // CRSF_FRAMETYPE_MSP_REQ
-                0x7A => todo!(), // extended
+                0x7A => Self::MspRequest {
+                    to: reader.to,
+                    from: reader.from,
+                    status: msp::Status(reader.u8()?),
+                    data: reader.rest().to_vec(),
+                },
                 // CRSF_FRAMETYPE_MSP_RESP
-                0x7B => todo!(), // extended
+                0x7B => Self::MspResponse {
+                    to: reader.to,
+                    from: reader.from,
+                    status: msp::Status(reader.u8()?),
+                    data: reader.rest().to_vec(),
+                },
                 // CRSF_FRAMETYPE_MSP_WRITE
-                0x7C => todo!(), // extended
-                // CRSF_FRAMETYPE_DISPLAYPORT_CMD
-                0x7D => todo!(), // extended
-                // CRSF_FRAMETYPE_ARDUPILOT_RESP
-                0x80 => todo!(), // extended
+                0x7C => Self::MspWrite {
+                    to: reader.to,
+                    from: reader.from,
+                    status: msp::Status(reader.u8()?),
+                    data: reader.rest().to_vec(),
+                },
 
-                kind => return Err(PacketError::InvalidPacketKind(kind)),
+                _ => Self::Raw(reader.raw()),
             }
         };
 
         Ok(packet)
     }
+
+    pub fn write<W: embedded_io::Write<Error = E>, E>(
+        &self,
+        raw: &mut W,
+    ) -> Result<(), WriteError<E>> {
+        let mut writer = match self {
+            Self::Gps {
+                latitude,
+                longitude,
+                speed,
+                heading,
+                altitude,
+                satellites,
+            } => {
+                // CRSF_FRAMETYPE_GPS
+                let mut writer = PacketWriter::new(0x02);
+                writer.i32(*latitude);
+                writer.i32(*longitude);
+                writer.i16(*speed);
+                writer.i16(*heading);
+                writer.u16((altitude + 1000).clamp(0, u16::MAX.into()) as u16);
+                writer.u8(*satellites);
+                writer
+            }
+            Self::Vario { vertical_speed } => {
+                // CRSF_FRAMETYPE_VARIO
+                let mut writer = PacketWriter::new(0x07);
+                writer.i16(*vertical_speed);
+                writer
+            }
+            Self::BatterySensor {
+                voltage,
+                current,
+                used,
+                remaining,
+            } => {
+                // CRSF_FRAMETYPE_BATTERY_SENSOR
+                let mut writer = PacketWriter::new(0x08);
+                writer.i16(*voltage);
+                writer.i16(*current);
+                writer.i24(*used);
+                writer.i8(*remaining);
+                writer
+            }
+            Self::BarometricAltitude {
+                altitude,
+                vertical_speed,
+            } => {
+                // CRSF_FRAMETYPE_BARO_ALTITUDE
+                let mut writer = PacketWriter::new(0x09);
+                writer.i16(pack_barometric_altitude(*altitude));
+                if let Some(vertical_speed) = vertical_speed {
+                    writer.i16(*vertical_speed);
+                }
+                writer
+            }
+            Self::Heartbeat { origin } => {
+                // CRSF_FRAMETYPE_HEARTBEAT
+                let mut writer = PacketWriter::new(0x0B);
+                writer.u16((*origin as u8).into());
+                writer
+            }
+            Self::LinkStatistics {
+                up_rssi1,
+                up_rssi2,
+                up_lq,
+                up_snr,
+                active_antenna,
+                mode,
+                tx_power,
+                down_rssi,
+                down_lq,
+                down_snr,
+            } => {
+                // CRSF_FRAMETYPE_LINK_STATISTICS
+                let mut writer = PacketWriter::new(0x14);
+                writer.u8(*up_rssi1);
+                writer.u8(*up_rssi2);
+                writer.u8(*up_lq);
+                writer.i8(*up_snr);
+                writer.u8(*active_antenna);
+                writer.u8(*mode);
+                writer.u8(*tx_power as u8);
+                writer.u8(*down_rssi);
+                writer.u8(*down_lq);
+                writer.i8(*down_snr);
+                writer
+            }
+            Self::RcChannelsPacked(channels) => {
+                // CRSF_FRAMETYPE_RC_CHANNELS_PACKED
+                let mut writer = PacketWriter::new(0x16);
+                writer.bytes(&channels.0);
+                writer
+            }
+            Self::SubsetRcChannelsPacked(channels) => {
+                // CRSF_FRAMETYPE_SUBSET_RC_CHANNELS_PACKED
+                let mut writer = PacketWriter::new(0x17);
+                channels.pack(&mut writer);
+                writer
+            }
+            Self::LinkRxId {
+                rssi,
+                rssi_percent,
+                link_quality,
+                snr,
+                power,
+            } => {
+                // CRSF_FRAMETYPE_LINK_RX_ID
+                let mut writer = PacketWriter::new(0x1C);
+                writer.u8(*rssi);
+                writer.u8(*rssi_percent);
+                writer.u8(*link_quality);
+                writer.i8(*snr);
+                writer.u8(*power);
+                writer
+            }
+            Self::LinkTxId {
+                rssi,
+                rssi_percent,
+                link_quality,
+                snr,
+                power,
+                rate,
+            } => {
+                // CRSF_FRAMETYPE_LINK_TX_ID
+                let mut writer = PacketWriter::new(0x1D);
+                writer.u8(*rssi);
+                writer.u8(*rssi_percent);
+                writer.u8(*link_quality);
+                writer.i8(*snr);
+                writer.u8(*power);
+                writer.u8(*rate);
+                writer
+            }
+            Self::Attitude { pitch, roll, yaw } => {
+                // CRSF_FRAMETYPE_ATTITUDE
+                let mut writer = PacketWriter::new(0x1E);
+                writer.i16(*pitch);
+                writer.i16(*roll);
+                writer.i16(*yaw);
+                writer
+            }
+            Self::FlightMode(mode) => {
+                // CRSF_FRAMETYPE_FLIGHT_MODE
+                let mut writer = PacketWriter::new(0x21);
+                writer.bytes(mode.as_bytes());
+                writer.u8(0);
+                writer
+            }
+            Self::DevicePing { to, from } => {
+                // CRSF_FRAMETYPE_DEVICE_PING
+                PacketWriter::extended(0x28, *to, *from)
+            }
+            Self::DeviceInfo {
+                to,
+                from,
+                name,
+                serial_number,
+                hardware_version,
+                software_version,
+                parameter_count,
+                protocol_version,
+            } => {
+                // CRSF_FRAMETYPE_DEVICE_INFO
+                let mut writer = PacketWriter::extended(0x29, *to, *from);
+                writer.c_string(name);
+                writer.u32(*serial_number);
+                writer.u32(*hardware_version);
+                writer.u32(*software_version);
+                writer.u8(*parameter_count);
+                writer.u8(*protocol_version);
+                writer
+            }
+            Self::ParameterSettingsEntry {
+                to,
+                from,
+                index,
+                chunks_remaining,
+                data,
+            } => {
+                // CRSF_FRAMETYPE_PARAMETER_SETTINGS_ENTRY
+                let mut writer = PacketWriter::extended(0x2B, *to, *from);
+                writer.u8(*index);
+                writer.u8(*chunks_remaining);
+                writer.bytes(data);
+                writer
+            }
+            Self::ParameterRead {
+                to,
+                from,
+                index,
+                chunk,
+            } => {
+                // CRSF_FRAMETYPE_PARAMETER_READ
+                let mut writer = PacketWriter::extended(0x2C, *to, *from);
+                writer.u8(*index);
+                writer.u8(*chunk);
+                writer
+            }
+            Self::ParameterWrite {
+                to,
+                from,
+                index,
+                value,
+            } => {
+                // CRSF_FRAMETYPE_PARAMETER_WRITE
+                let mut writer = PacketWriter::extended(0x2D, *to, *from);
+                writer.u8(*index);
+                writer.bytes(value);
+                writer
+            }
+            Self::ElrsStatus {
+                to,
+                from,
+                bad_packets,
+                good_packets,
+                flags,
+                message,
+            } => {
+                // CRSF_FRAMETYPE_ELRS_STATUS
+                let mut writer = PacketWriter::extended(0x2E, *to, *from);
+                writer.u8(*bad_packets);
+                writer.u16(*good_packets);
+                writer.u8(flags.0);
+                writer.c_string(message);
+                writer
+            }
+            Self::Command { to, from, command } => {
+                // CRSF_FRAMETYPE_COMMAND
+                let mut writer = PacketWriter::extended(0x32, *to, *from);
+                match *command {
+                    Command::Bind => {
+                        // CRSF_COMMAND_SUBCMD_RX_BIND
+                        writer.bytes(&[0x10, 0x01]);
+                    }
+                    Command::SelectModel(id) => {
+                        // CRSF_COMMAND_SUBCMD_RX_MODEL_SELECTION
+                        writer.bytes(&[0x10, 0x05]);
+                        writer.u8(id);
+                    }
+                    Command::SpeedProposal { port, baud_rate } => {
+                        // CRSF_COMMAND_SUBCMD_GENERAL_CRSF_SPEED_PROPOSAL
+                        writer.bytes(&[0x0A, 0x70]);
+                        writer.u8(port);
+                        writer.u32(baud_rate);
+                    }
+                    Command::SpeedResponse { port, accepted } => {
+                        // CRSF_COMMAND_SUBCMD_GENERAL_CRSF_SPEED_RESPONSE
+                        writer.bytes(&[0x0A, 0x71]);
+                        writer.u8(port);
+                        writer.u8(accepted.into());
+                    }
+                }
+                writer.command_crc();
+                writer
+            }
+            Self::RadioId {
+                to,
+                from,
+                interval,
+                offset,
+            } => {
+                // CRSF_FRAMETYPE_RADIO_ID
+                let mut writer = PacketWriter::extended(0x3A, *to, *from);
+                // CRSF_FRAMETYPE_OPENTX_SYNC
+                writer.u8(0x10);
+                writer.u32(*interval);
+                writer.i32(*offset);
+                writer
+            }
+            Self::MspRequest {
+                to,
+                from,
+                status,
+                data,
+            } => {
+                // CRSF_FRAMETYPE_MSP_REQ
+                PacketWriter::msp(0x7A, *to, *from, *status, data)
+            }
+            Self::MspResponse {
+                to,
+                from,
+                status,
+                data,
+            } => {
+                // CRSF_FRAMETYPE_MSP_RESP
+                PacketWriter::msp(0x7B, *to, *from, *status, data)
+            }
+            Self::MspWrite {
+                to,
+                from,
+                status,
+                data,
+            } => {
+                // CRSF_FRAMETYPE_MSP_WRITE
+                PacketWriter::msp(0x7C, *to, *from, *status, data)
+            }
+            Self::Raw(raw) => {
+                let mut writer = PacketWriter::new(raw.kind);
+                writer.bytes(raw.payload());
+                writer
+            }
+
+            Self::KissRequest
+            | Self::KissResponse
+            | Self::DisplayportCommand
+            | Self::ArdupilotResponse => return Err(WriteError::Unsupported),
+        };
+
+        let frame = writer.finish().ok_or(WriteError::PayloadTooLong)?;
+        raw.write_all(frame).map_err(WriteError::Io)
+    }
 }
 
 fn convert_barometric_altitude(altitude: i16) -> i32 {
@@ -446,10 +1367,21 @@ fn convert_barometric_altitude(altitude: i16) -> i32 {
     }
 }
 
+/// Inverse of [`convert_barometric_altitude`]. Altitudes that do not fit in
+/// decimeter precision fall back to whole meters.
+fn pack_barometric_altitude(altitude: i32) -> i16 {
+    let decimeters = altitude + 10_000;
+    if decimeters <= i32::from(i16::MAX) {
+        decimeters.max(0) as i16
+    } else {
+        let meters = (altitude / 10).min(0x7FFF) as u16;
+        (meters | 0x8000).cast_signed()
+    }
+}
+
 #[derive(Debug)]
 struct PacketReader {
-    /// Max packet size excluding sync, length, and type bytes
-    payload: [u8; 61],
+    payload: [u8; MAX_PAYLOAD_BYTES],
     /// Next byte index to read from
     next: usize,
     /// Actual packet size excluding sync, length, type, and crc bytes
@@ -466,14 +1398,17 @@ impl PacketReader {
         reader.read_exact(&mut buffer)?;
         let [sync, length, packet_type] = buffer;
 
-        if sync != 0xC8 {
+        if !is_sync_byte(sync) {
             return Err(PacketError::InvalidSyncByte(sync));
         }
 
         // Exclude type and crc bytes
-        let payload_length = length - 2;
+        let payload_length = length
+            .checked_sub(2)
+            .filter(|&len| usize::from(len) <= MAX_PAYLOAD_BYTES)
+            .ok_or(Malformed)?;
 
-        let mut payload = [0; 61];
+        let mut payload = [0; MAX_PAYLOAD_BYTES];
         reader.read_exact(&mut payload[0..usize::from(payload_length)])?;
 
         let mut checksum = [0];
@@ -500,63 +1435,90 @@ impl PacketReader {
         ExtendedPacketReader::new(self)
     }
 
-    fn payload(&mut self) -> &[u8] {
-        let rest = &self.payload[self.next..self.payload_length.into()];
-        self.next = self.payload_length.into();
-        rest
+    /// The entire payload, regardless of how much has been read
+    fn raw(&self) -> RawPacket {
+        RawPacket {
+            kind: self.packet_type,
+            len: self.payload_length,
+            payload: self.payload,
+        }
     }
 
-    fn u8(&mut self) -> u8 {
-        let x = u8::from_be(self.payload[self.next]);
-        self.next += 1;
-        x
+    /// Read all remaining bytes
+    fn rest(&mut self) -> &[u8] {
+        let start = self.next;
+        self.next = self.payload_length.into();
+        self.payload.get(start..self.next).unwrap_or_default()
     }
 
-    fn i8(&mut self) -> i8 {
-        self.u8().cast_signed()
+    /// Read a null-terminated string
+    fn c_str(&mut self) -> Result<&CStr, Malformed> {
+        let rest = &self.payload[self.next..self.payload_length.into()];
+        let string = CStr::from_bytes_until_nul(rest).map_err(|_| Malformed)?;
+        self.next += string.count_bytes() + 1;
+        Ok(string)
     }
 
-    fn u16(&mut self) -> u16 {
-        let bytes = [self.payload[self.next], self.payload[self.next + 1]];
-        self.next += 2;
-        u16::from_be_bytes(bytes)
+    fn c_string(&mut self) -> Result<CString, Malformed> {
+        self.c_str().map(CString::from)
     }
 
-    fn i16(&mut self) -> i16 {
-        self.u16().cast_signed()
+    fn flight_mode(&mut self) -> Result<FlightModeName, Malformed> {
+        let name = self.c_str()?;
+        FlightModeName::new(name.to_bytes()).ok_or(Malformed)
     }
 
-    fn i24(&mut self) -> i32 {
-        let b1 = self.payload[self.next];
-        let b2 = self.payload[self.next + 1];
-        let b3 = self.payload[self.next + 2];
-        let b4 = if b3.leading_ones() > 0 { 0xFF } else { 0x00 };
-        self.next += 3;
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Malformed> {
+        let end = self.next + N;
+        let bytes = self.payload[0..self.payload_length.into()]
+            .get(self.next..end)
+            .ok_or(Malformed)?;
+        self.next = end;
 
-        i32::from_be_bytes([b1, b2, b3, b4])
+        let mut array = [0; N];
+        array.copy_from_slice(bytes);
+        Ok(array)
     }
 
-    fn u32(&mut self) -> u32 {
-        let bytes = [
-            self.payload[self.next],
-            self.payload[self.next + 1],
-            self.payload[self.next + 2],
-            self.payload[self.next + 3],
-        ];
-        self.next += 4;
-        u32::from_be_bytes(bytes)
+    fn u8(&mut self) -> Result<u8, Malformed> {
+        let [x] = self.array()?;
+        Ok(x)
     }
 
-    fn i32(&mut self) -> i32 {
-        self.u32().cast_signed()
+    fn i8(&mut self) -> Result<i8, Malformed> {
+        self.u8().map(u8::cast_signed)
+    }
+
+    fn u16(&mut self) -> Result<u16, Malformed> {
+        self.array().map(u16::from_be_bytes)
+    }
+
+    fn i16(&mut self) -> Result<i16, Malformed> {
+        self.u16().map(u16::cast_signed)
+    }
+
+    fn i24(&mut self) -> Result<i32, Malformed> {
+        let [b1, b2, b3] = self.array()?;
+        let b0 = if b1.leading_ones() > 0 { 0xFF } else { 0x00 };
+        Ok(i32::from_be_bytes([b0, b1, b2, b3]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Malformed> {
+        self.array().map(u32::from_be_bytes)
+    }
+
+    fn i32(&mut self) -> Result<i32, Malformed> {
+        self.u32().map(u32::cast_signed)
     }
 }
 
 impl Drop for PacketReader {
     fn drop(&mut self) {
-        if cfg!(debug_assertions) && self.next != self.payload_length.into() {
+        // Trailing bytes are fine: newer senders may append fields this version
+        // does not know about yet
+        if cfg!(debug_assertions) && self.next > self.payload_length.into() {
             panic!(
-                "Expected to read {} bytes. Actually read {} bytes",
+                "Expected to read at most {} bytes. Actually read {} bytes",
                 self.payload_length, self.next
             );
         }
@@ -572,9 +1534,13 @@ struct ExtendedPacketReader<'a> {
 
 impl<'a> ExtendedPacketReader<'a> {
     fn new<E>(reader: &'a mut PacketReader) -> Result<Self, PacketError<E>> {
-        // FIXME: return error
-        let to = Address::from_raw(reader.u8()).unwrap();
-        let from = Address::from_raw(reader.u8()).unwrap();
+        let mut address = || {
+            let raw = reader.u8()?;
+            Address::from_raw(raw).ok_or(PacketError::UnknownAddress(raw))
+        };
+
+        let to = address()?;
+        let from = address()?;
 
         Ok(Self { reader, to, from })
     }
@@ -582,12 +1548,188 @@ impl<'a> ExtendedPacketReader<'a> {
     fn packet_type(&self) -> u8 {
         self.reader.packet_type
     }
+
+    /// Check the inner crc and decode a `COMMAND` payload, or `None` if the
+    /// command is not supported
+    fn command<E>(&mut self) -> Result<Option<Command>, PacketError<E>> {
+        let payload = &self.payload[0..self.payload_length.into()];
+        let (&crc, payload) = payload
+            .split_last()
+            .filter(|(_, payload)| payload.len() >= self.next)
+            .ok_or(Malformed)?;
+
+        let mut digest = EXTENDED_CRC.digest();
+        digest.update(&[self.packet_type()]);
+        digest.update(payload);
+        if digest.finalize() != crc {
+            return Err(PacketError::BadCommandCrc);
+        }
+        let crc_index = payload.len();
+
+        let command = match (self.u8()?, self.u8()?) {
+            (0x10, 0x01) => Command::Bind,
+            (0x10, 0x05) => Command::SelectModel(self.u8()?),
+            (0x0A, 0x70) => Command::SpeedProposal {
+                port: self.u8()?,
+                baud_rate: self.u32()?,
+            },
+            (0x0A, 0x71) => Command::SpeedResponse {
+                port: self.u8()?,
+                accepted: self.u8()? != 0,
+            },
+            _ => return Ok(None),
+        };
+
+        // Only the crc should be left
+        if self.next != crc_index {
+            return Err(Malformed.into());
+        }
+
+        Ok(Some(command))
+    }
+}
+
+impl Deref for ExtendedPacketReader<'_> {
+    type Target = PacketReader;
+
+    fn deref(&self) -> &Self::Target {
+        self.reader
+    }
+}
+
+impl DerefMut for ExtendedPacketReader<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.reader
+    }
+}
+
+#[derive(Debug)]
+struct PacketWriter {
+    /// Full frame, including sync, length, type, and crc bytes
+    frame: [u8; MAX_FRAME_BYTES],
+    /// Next byte index to write to
+    next: usize,
+    /// Set if the payload did not fit in `frame`
+    overflowed: bool,
+}
+
+impl PacketWriter {
+    /// Sync, length, and type bytes
+    const HEADER_BYTES: usize = 3;
+
+    fn new(packet_type: u8) -> Self {
+        let mut frame = [0; MAX_FRAME_BYTES];
+        frame[0] = SYNC_BYTE;
+        frame[2] = packet_type;
+
+        Self {
+            frame,
+            next: Self::HEADER_BYTES,
+            overflowed: false,
+        }
+    }
+
+    fn extended(packet_type: u8, to: Address, from: Address) -> Self {
+        let mut writer = Self::new(packet_type);
+        writer.u8(to as u8);
+        writer.u8(from as u8);
+        writer
+    }
+
+    fn msp(packet_type: u8, to: Address, from: Address, status: msp::Status, data: &[u8]) -> Self {
+        let mut writer = Self::extended(packet_type, to, from);
+        writer.u8(status.0);
+        writer.bytes(data);
+        writer
+    }
+
+    /// Fill in the length and crc bytes and return the complete frame, or
+    /// `None` if the payload was too long.
+    fn finish(&mut self) -> Option<&[u8]> {
+        // Leave room for the crc
+        if self.overflowed || self.next >= MAX_FRAME_BYTES {
+            return None;
+        }
+
+        // Include type and crc bytes
+        self.frame[1] = (self.next - Self::HEADER_BYTES + 2) as u8;
+        self.frame[self.next] = BASIC_CRC.checksum(&self.frame[2..self.next]);
+
+        Some(&self.frame[0..=self.next])
+    }
+
+    /// Append the inner crc of a `COMMAND` frame
+    fn command_crc(&mut self) {
+        let crc = EXTENDED_CRC.checksum(self.frame.get(2..self.next).unwrap_or_default());
+        self.u8(crc);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        let end = self.next + bytes.len();
+        if let Some(out) = self.frame.get_mut(self.next..end) {
+            out.copy_from_slice(bytes);
+            self.next = end;
+        } else {
+            self.overflowed = true;
+        }
+    }
+
+    fn c_string(&mut self, string: &CStr) {
+        self.bytes(string.to_bytes_with_nul());
+    }
+
+    fn u8(&mut self, x: u8) {
+        self.bytes(&[x]);
+    }
+
+    fn i8(&mut self, x: i8) {
+        self.u8(x.cast_unsigned());
+    }
+
+    fn u16(&mut self, x: u16) {
+        self.bytes(&x.to_be_bytes());
+    }
+
+    fn i16(&mut self, x: i16) {
+        self.u16(x.cast_unsigned());
+    }
+
+    fn i24(&mut self, x: i32) {
+        self.bytes(&x.to_be_bytes()[1..]);
+    }
+
+    fn u32(&mut self, x: u32) {
+        self.bytes(&x.to_be_bytes());
+    }
+
+    fn i32(&mut self, x: i32) {
+        self.u32(x.cast_unsigned());
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::iter;
+
     use super::*;
 
+    fn write(packet: &Packet) -> Vec<u8> {
+        let mut buffer = [0; MAX_FRAME_BYTES];
+        let mut raw = &mut buffer[..];
+        packet.write(&mut raw).unwrap();
+        let len = MAX_FRAME_BYTES - raw.len();
+        buffer[0..len].to_vec()
+    }
+
+    /// Assert that `raw` decodes to `expected` and that `expected` encodes
+    /// back to `raw`
+    fn round_trip(raw: &[u8], expected: &Packet) {
+        assert_eq!(*expected, Packet::read(&mut &raw[..]).unwrap());
+        assert_eq!(raw, write(expected));
+    }
+
     #[test]
     fn packet_rc_channels_packed_all_1500() {
         let mut raw: &[u8] = &[
@@ -626,6 +1768,46 @@ mod tests {
         assert_eq!(expected, Packet::read(&mut raw).unwrap());
     }
 
+    #[test]
+    fn packet_battery_sensor() {
+        let raw = &[
+            0xC8, 0x0A, 0x08, 0x00, 0xA8, 0x00, 0x0C, 0x00, 0x04, 0xD2, 0x32, 0x21,
+        ];
+
+        let expected = Packet::BatterySensor {
+            voltage: 168,
+            current: 12,
+            used: 1234,
+            remaining: 50,
+        };
+
+        round_trip(raw, &expected);
+    }
+
+    #[test]
+    fn packet_barometric_altitude() {
+        let raw = &[0xC8, 0x04, 0x09, 0x27, 0x74, 0x74];
+
+        let expected = Packet::BarometricAltitude {
+            altitude: 100,
+            vertical_speed: None,
+        };
+
+        round_trip(raw, &expected);
+    }
+
+    #[test]
+    fn packet_barometric_altitude_vertical_speed() {
+        let raw = &[0xC8, 0x06, 0x09, 0x27, 0x74, 0xFF, 0x38, 0x31];
+
+        let expected = Packet::BarometricAltitude {
+            altitude: 100,
+            vertical_speed: Some(-200),
+        };
+
+        round_trip(raw, &expected);
+    }
+
     #[test]
     fn packet_flight_mode() {
         let mut raw: &[u8] = &[
@@ -633,8 +1815,642 @@ mod tests {
             0x67, 0x21, 0x00, 0x46,
         ];
 
-        let expected = Packet::FlightMode(CString::new("Lithobraking!").unwrap());
+        let expected = Packet::FlightMode(FlightModeName::new(b"Lithobraking!").unwrap());
+
+        assert_eq!(expected, Packet::read(&mut raw).unwrap());
+    }
+
+    #[test]
+    fn flight_mode_name() {
+        let max = [b'a'; FlightModeName::MAX_LEN + 1];
+        assert!(FlightModeName::new(&max[1..]).is_some());
+        assert!(FlightModeName::new(&max).is_none());
+        assert!(FlightModeName::new(b"AC\0RO").is_none());
+
+        let name = FlightModeName::new(b"ACRO").unwrap();
+        assert_eq!(name.to_str(), Ok("ACRO"));
+        assert_eq!(alloc::format!("{name:?}"), r#""ACRO""#);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let packets = [
+            Packet::FlightMode(FlightModeName::new(b"ANGL").unwrap()),
+            Packet::SubsetRcChannelsPacked(
+                SubsetRcChannels::new(4, Resolution::Bits11, &[992, 172, 1811]).unwrap(),
+            ),
+            Packet::Raw(RawPacket::new(0x78, &[0xC8, 0xEA, 0x01]).unwrap()),
+            Packet::DevicePing {
+                to: Address::Transmitter,
+                from: Address::Handset,
+            },
+        ];
+
+        for packet in packets {
+            let bytes = postcard::to_allocvec(&packet).unwrap();
+            assert_eq!(postcard::from_bytes::<Packet>(&bytes).unwrap(), packet);
+        }
+    }
+
+    #[test]
+    fn write_existing_vectors() {
+        let vectors: &[&[u8]] = &[
+            &[
+                0xC8, 0x18, 0x16, 0xE0, 0x03, 0x1F, 0xF8, 0xC0, 0x07, 0x3E, 0xF0, 0x81, 0x0F, 0x7C,
+                0xE0, 0x03, 0x1F, 0xF8, 0xC0, 0x07, 0x3E, 0xF0, 0x81, 0x0F, 0x7C, 0xAD,
+            ],
+            &[
+                0xC8, 0x0C, 0x14, 0x24, 0x00, 0x64, 0x0A, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x39,
+            ],
+            &[
+                0xC8, 0x10, 0x21, 0x4C, 0x69, 0x74, 0x68, 0x6F, 0x62, 0x72, 0x61, 0x6B, 0x69, 0x6E,
+                0x67, 0x21, 0x00, 0x46,
+            ],
+        ];
+
+        for raw in vectors {
+            let packet = Packet::read(&mut &raw[..]).unwrap();
+            assert_eq!(*raw, write(&packet));
+        }
+    }
+
+    #[test]
+    fn packet_gps() {
+        let raw = &[
+            0xC8, 0x11, 0x02, 0x1C, 0x60, 0x21, 0x94, 0xB7, 0x15, 0x9B, 0x84, 0x00, 0x7B, 0x69,
+            0x78, 0x04, 0x60, 0x09, 0x03,
+        ];
+
+        let expected = Packet::Gps {
+            latitude: 476_062_100,
+            longitude: -1_223_320_700,
+            speed: 123,
+            heading: 27000,
+            altitude: 120,
+            satellites: 9,
+        };
+
+        round_trip(raw, &expected);
+    }
+
+    #[test]
+    fn packet_device_ping() {
+        let raw = &[0xC8, 0x04, 0x28, 0x00, 0xEA, 0x54];
+
+        let expected = Packet::DevicePing {
+            to: Address::Broadcast,
+            from: Address::Handset,
+        };
+
+        round_trip(raw, &expected);
+    }
+
+    #[test]
+    fn trailing_payload_bytes() {
+        let mut raw: &[u8] = &[0xC8, 0x06, 0x07, 0x00, 0x64, 0x01, 0x02, 0xF9];
+
+        let expected = Packet::Vario {
+            vertical_speed: 100,
+        };
 
         assert_eq!(expected, Packet::read(&mut raw).unwrap());
     }
+
+    #[test]
+    fn write_round_trip() {
+        let packets = [
+            Packet::Vario {
+                vertical_speed: -250,
+            },
+            Packet::BatterySensor {
+                voltage: 252,
+                current: -1,
+                used: -2,
+                remaining: -3,
+            },
+            Packet::BarometricAltitude {
+                altitude: -10_000,
+                vertical_speed: None,
+            },
+            Packet::BarometricAltitude {
+                altitude: 50_000,
+                vertical_speed: Some(0),
+            },
+            Packet::Heartbeat {
+                origin: Address::FlightController,
+            },
+        ];
+
+        for packet in packets {
+            let raw = write(&packet);
+            assert_eq!(packet, Packet::read(&mut &raw[..]).unwrap());
+        }
+    }
+
+    #[test]
+    fn write_too_long() {
+        let packet = Packet::DeviceInfo {
+            to: Address::Handset,
+            from: Address::Transmitter,
+            name: CString::new([b'a'; MAX_FRAME_BYTES]).unwrap(),
+            serial_number: 0,
+            hardware_version: 0,
+            software_version: 0,
+            parameter_count: 0,
+            protocol_version: 0,
+        };
+        let mut buffer = [0; MAX_FRAME_BYTES * 2];
+        let result = packet.write(&mut &mut buffer[..]);
+        assert!(matches!(result, Err(WriteError::PayloadTooLong)));
+    }
+
+    #[test]
+    fn write_unsupported() {
+        let mut buffer = [0; MAX_FRAME_BYTES];
+        let result = Packet::KissRequest.write(&mut &mut buffer[..]);
+        assert!(matches!(result, Err(WriteError::Unsupported)));
+    }
+
+    #[test]
+    fn raw_unsupported_kind() {
+        let raw = &[0xC8, 0x07, 0x78, 0xC8, 0xEA, 0x01, 0x02, 0x03, 0x99];
+
+        let expected = RawPacket::new(0x78, &[0xC8, 0xEA, 0x01, 0x02, 0x03]).unwrap();
+        round_trip(raw, &Packet::Raw(expected));
+    }
+
+    #[test]
+    fn malformed_short_payload() {
+        let mut raw: &[u8] = &[0xC8, 0x08, 0x14, 0x24, 0x00, 0x64, 0x0A, 0x00, 0x02, 0xB1];
+
+        let result = Packet::read(&mut raw);
+        assert!(matches!(result, Err(PacketError::MalformedPayload)));
+    }
+
+    #[test]
+    fn tx_power() {
+        let frames: [(&[u8], TxPower); 2] = [
+            (
+                &[
+                    0xC8, 0x0C, 0x14, 0x24, 0x00, 0x64, 0x0A, 0x00, 0x02, 0x07, 0x00, 0x00, 0x00,
+                    0x37,
+                ],
+                TxPower::mW_250,
+            ),
+            (
+                &[
+                    0xC8, 0x0C, 0x14, 0x24, 0x00, 0x64, 0x0A, 0x00, 0x02, 0x08, 0x00, 0x00, 0x00,
+                    0x6E,
+                ],
+                TxPower::mW_50,
+            ),
+        ];
+
+        for (mut raw, expected) in frames {
+            let Packet::LinkStatistics { tx_power, .. } = Packet::read(&mut raw).unwrap() else {
+                panic!()
+            };
+
+            assert_eq!(tx_power, expected);
+        }
+    }
+
+    #[test]
+    fn malformed_tx_power() {
+        let mut raw: &[u8] = &[
+            0xC8, 0x0C, 0x14, 0x24, 0x00, 0x64, 0x0A, 0x00, 0x02, 0x09, 0x00, 0x00, 0x00, 0x2B,
+        ];
+
+        let result = Packet::read(&mut raw);
+        assert!(matches!(result, Err(PacketError::MalformedPayload)));
+    }
+
+    #[test]
+    fn malformed_length() {
+        for length in [0x00, 0x01, 0x3F, 0xFF] {
+            let mut raw: &[u8] = &[0xC8, length, 0x28, 0x00];
+
+            let result = Packet::read(&mut raw);
+            assert!(matches!(result, Err(PacketError::MalformedPayload)));
+        }
+    }
+
+    #[test]
+    fn unknown_address() {
+        let mut raw: &[u8] = &[0xC8, 0x04, 0x28, 0x01, 0xEA, 0x5F];
+
+        let result = Packet::read(&mut raw);
+        assert!(matches!(result, Err(PacketError::UnknownAddress(0x01))));
+    }
+
+    #[test]
+    fn arbitrary_payloads() {
+        // xorshift32, to keep this deterministic and dependency free
+        let mut state = 0x1234_5678u32;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state.to_le_bytes()[0]
+        };
+
+        // Every type and length with a valid crc, so the payload actually gets
+        // parsed
+        for kind in 0..=u8::MAX {
+            for len in 0..=MAX_PAYLOAD_BYTES {
+                let mut raw = vec![SYNC_BYTE, len as u8 + 2, kind];
+                raw.extend(iter::repeat_with(&mut random).take(len));
+                raw.push(BASIC_CRC.checksum(&raw[2..]));
+
+                if let Ok(packet) = Packet::read(&mut &raw[..]) {
+                    let _ = write(&packet);
+                }
+            }
+        }
+
+        // Arbitrary bytes through the streaming decoder
+        let mut decoder = FrameDecoder::new();
+        for _ in 0..100_000 {
+            if let Some(mut frame) = decoder.push(random()) {
+                let _ = Packet::read(&mut frame);
+            }
+        }
+    }
+
+    #[test]
+    fn packet_device_info() {
+        let raw = &[
+            0xC8, 0x20, 0x29, 0xEA, 0xEE, 0x45, 0x78, 0x70, 0x72, 0x65, 0x73, 0x73, 0x4C, 0x52,
+            0x53, 0x20, 0x54, 0x58, 0x00, 0x45, 0x4C, 0x52, 0x53, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x03, 0x05, 0x02, 0x16, 0x00, 0xA3,
+        ];
+
+        let expected = Packet::DeviceInfo {
+            to: Address::Handset,
+            from: Address::Transmitter,
+            name: CString::new("ExpressLRS TX").unwrap(),
+            serial_number: u32::from_be_bytes(*b"ELRS"),
+            hardware_version: 0,
+            software_version: 0x0003_0502,
+            parameter_count: 22,
+            protocol_version: 0,
+        };
+
+        round_trip(raw, &expected);
+    }
+
+    #[test]
+    fn device_info_missing_terminator() {
+        let mut payload = vec![0xEA, 0xEE];
+        payload.extend_from_slice(b"ExpressLRS TX");
+        let raw = write(&Packet::Raw(RawPacket::new(0x29, &payload).unwrap()));
+
+        let result = Packet::read(&mut &raw[..]);
+        assert!(matches!(result, Err(PacketError::MalformedPayload)));
+    }
+
+    #[test]
+    fn packet_parameter_read_write() {
+        let raw = &[0xC8, 0x06, 0x2C, 0xEE, 0xEA, 0x01, 0x00, 0x86];
+        let expected = Packet::ParameterRead {
+            to: Address::Transmitter,
+            from: Address::Handset,
+            index: 1,
+            chunk: 0,
+        };
+        round_trip(raw, &expected);
+
+        let raw = &[0xC8, 0x06, 0x2D, 0xEE, 0xEA, 0x01, 0x03, 0x9A];
+        let expected = Packet::ParameterWrite {
+            to: Address::Transmitter,
+            from: Address::Handset,
+            index: 1,
+            value: vec![3],
+        };
+        round_trip(raw, &expected);
+    }
+
+    #[test]
+    fn packet_parameter_settings_entry() {
+        let packet = Packet::ParameterSettingsEntry {
+            to: Address::Handset,
+            from: Address::Transmitter,
+            index: 4,
+            chunks_remaining: 1,
+            data: b"\x00\x0BFolder\x00".to_vec(),
+        };
+
+        let raw = write(&packet);
+        assert_eq!(raw[0..7], [0xC8, 0x0F, 0x2B, 0xEA, 0xEE, 0x04, 0x01]);
+        assert_eq!(packet, Packet::read(&mut &raw[..]).unwrap());
+    }
+
+    #[test]
+    fn packet_command() {
+        let vectors: [(&[u8], _); 4] = [
+            (
+                &[0xC8, 0x07, 0x32, 0xEC, 0xEA, 0x10, 0x01, 0x34, 0xF6],
+                Packet::Command {
+                    to: Address::Receiver,
+                    from: Address::Handset,
+                    command: Command::Bind,
+                },
+            ),
+            (
+                &[0xC8, 0x08, 0x32, 0xEE, 0xEA, 0x10, 0x05, 0x03, 0xA8, 0xC5],
+                Packet::Command {
+                    to: Address::Transmitter,
+                    from: Address::Handset,
+                    command: Command::SelectModel(3),
+                },
+            ),
+            (
+                &[
+                    0xC8, 0x0C, 0x32, 0xEE, 0xEA, 0x0A, 0x70, 0x00, 0x00, 0x1E, 0x84, 0x80, 0xB8,
+                    0x39,
+                ],
+                Packet::Command {
+                    to: Address::Transmitter,
+                    from: Address::Handset,
+                    command: Command::SpeedProposal {
+                        port: 0,
+                        baud_rate: 2_000_000,
+                    },
+                },
+            ),
+            (
+                &[
+                    0xC8, 0x09, 0x32, 0xEA, 0xEE, 0x0A, 0x71, 0x00, 0x01, 0x4C, 0xDE,
+                ],
+                Packet::Command {
+                    to: Address::Handset,
+                    from: Address::Transmitter,
+                    command: Command::SpeedResponse {
+                        port: 0,
+                        accepted: true,
+                    },
+                },
+            ),
+        ];
+
+        for (raw, expected) in vectors {
+            round_trip(raw, &expected);
+        }
+    }
+
+    #[test]
+    fn command_bad_inner_crc() {
+        // Valid outer crc, corrupted inner crc
+        let mut payload = vec![0xEC, 0xEA, 0x10, 0x01, 0x35];
+        let raw = write(&Packet::Raw(RawPacket::new(0x32, &payload).unwrap()));
+        let result = Packet::read(&mut &raw[..]);
+        assert!(matches!(result, Err(PacketError::BadCommandCrc)));
+
+        // No room for an inner crc at all
+        payload.truncate(2);
+        let raw = write(&Packet::Raw(RawPacket::new(0x32, &payload).unwrap()));
+        let result = Packet::read(&mut &raw[..]);
+        assert!(matches!(result, Err(PacketError::MalformedPayload)));
+    }
+
+    #[test]
+    fn command_unsupported() {
+        // CRSF_COMMAND_SUBCMD_RX_CANCEL_BIND
+        let mut payload = vec![0xEC, 0xEA, 0x10, 0x02];
+        payload.push(EXTENDED_CRC.checksum(&[&[0x32], &payload[..]].concat()));
+
+        let raw = write(&Packet::Raw(RawPacket::new(0x32, &payload).unwrap()));
+        let expected = Packet::Raw(RawPacket::new(0x32, &payload).unwrap());
+        assert_eq!(expected, Packet::read(&mut &raw[..]).unwrap());
+    }
+
+    #[test]
+    fn packet_attitude() {
+        let raw = &[0xC8, 0x08, 0x1E, 0xFB, 0x2E, 0x09, 0xC4, 0x85, 0x49, 0x2E];
+
+        let expected = Packet::Attitude {
+            pitch: -1234,
+            roll: 2500,
+            yaw: -31415,
+        };
+
+        round_trip(raw, &expected);
+    }
+
+    #[test]
+    fn packet_link_rx_id() {
+        let raw = &[0xC8, 0x07, 0x1C, 0x3C, 0x55, 0x64, 0x08, 0x14, 0xAD];
+
+        let expected = Packet::LinkRxId {
+            rssi: 60,
+            rssi_percent: 85,
+            link_quality: 100,
+            snr: 8,
+            power: 20,
+        };
+
+        round_trip(raw, &expected);
+    }
+
+    #[test]
+    fn packet_link_tx_id() {
+        let raw = &[0xC8, 0x08, 0x1D, 0x2D, 0x5F, 0x63, 0x0B, 0x14, 0x32, 0xCC];
+
+        let expected = Packet::LinkTxId {
+            rssi: 45,
+            rssi_percent: 95,
+            link_quality: 99,
+            snr: 11,
+            power: 20,
+            rate: 50,
+        };
+
+        round_trip(raw, &expected);
+    }
+
+    #[test]
+    fn packet_elrs_status() {
+        let raw = &[
+            0xC8, 0x17, 0x2E, 0xEA, 0xEE, 0x02, 0x01, 0xF4, 0x05, 0x4D, 0x6F, 0x64, 0x65, 0x6C,
+            0x20, 0x4D, 0x69, 0x73, 0x6D, 0x61, 0x74, 0x63, 0x68, 0x00, 0xA9,
+        ];
+
+        let expected = Packet::ElrsStatus {
+            to: Address::Handset,
+            from: Address::Transmitter,
+            bad_packets: 2,
+            good_packets: 500,
+            flags: ElrsFlags(0x05),
+            message: CString::new("Model Mismatch").unwrap(),
+        };
+
+        round_trip(raw, &expected);
+
+        let Packet::ElrsStatus { flags, .. } = expected else {
+            unreachable!()
+        };
+        assert!(flags.connected());
+        assert!(flags.model_mismatch());
+        assert!(!flags.armed());
+        assert_eq!(flags.critical(), 0);
+    }
+
+    #[test]
+    fn packet_subset_rc_channels() {
+        // Channels 4-7 at 11 bits: 992, 172, 1811, 0
+        let raw = &[
+            0xC8, 0x09, 0x17, 0x24, 0xE0, 0x63, 0xC5, 0xC4, 0x01, 0x00, 0x31,
+        ];
+
+        let channels = SubsetRcChannels::new(4, Resolution::Bits11, &[992, 172, 1811, 0]).unwrap();
+        round_trip(raw, &Packet::SubsetRcChannelsPacked(channels));
+    }
+
+    #[test]
+    fn subset_rc_channels_resolutions() {
+        for resolution in [
+            Resolution::Bits10,
+            Resolution::Bits11,
+            Resolution::Bits12,
+            Resolution::Bits13,
+        ] {
+            let max_len = (MAX_PAYLOAD_BYTES - 1) * 8 / usize::from(resolution.bits());
+            for len in [0, 1, 7, 16, max_len] {
+                let values: Vec<u16> = (0..len)
+                    .map(|i| (i as u16 * 997) & resolution.max())
+                    .collect();
+
+                let channels = SubsetRcChannels::new(31, resolution, &values).unwrap();
+                let packet = Packet::SubsetRcChannelsPacked(channels);
+                let raw = write(&packet);
+                assert_eq!(packet, Packet::read(&mut &raw[..]).unwrap());
+            }
+
+            let too_many = vec![0; max_len + 1];
+            assert!(SubsetRcChannels::new(0, resolution, &too_many).is_none());
+            assert!(SubsetRcChannels::new(0, resolution, &[resolution.max() + 1]).is_none());
+        }
+
+        assert!(SubsetRcChannels::new(32, Resolution::Bits10, &[]).is_none());
+    }
+
+    #[test]
+    fn rc_channels_pack() {
+        let channels = [
+            channel::MIN,
+            channel::CENTER,
+            channel::MAX,
+            0,
+            0x7FF,
+            u16::MAX,
+            1,
+            2,
+            3,
+            4,
+            5,
+            6,
+            7,
+            8,
+            9,
+            10,
+        ];
+
+        let packed = RcChannelsPacked::pack(&channels);
+        let mut expected = channels;
+        expected[5] = 0x7FF;
+        assert_eq!(packed.unpack(), expected);
+
+        // Same as packet_rc_channels_packed_all_1500
+        let packed = RcChannelsPacked::from_us(&[1500; 16]);
+        assert_eq!(
+            packed.0,
+            [
+                0xE0, 0x03, 0x1F, 0xF8, 0xC0, 0x07, 0x3E, 0xF0, 0x81, 0x0F, 0x7C, 0xE0, 0x03, 0x1F,
+                0xF8, 0xC0, 0x07, 0x3E, 0xF0, 0x81, 0x0F, 0x7C,
+            ]
+        );
+        assert_eq!(packed.to_us(), [1500; 16]);
+
+        let packed =
+            RcChannelsPacked::from_us(&[900, 988, 2012, 2100].repeat(4).try_into().unwrap());
+        assert_eq!(packed.unpack(), [172, 172, 1811, 1811].repeat(4)[..]);
+    }
+
+    #[test]
+    fn packet_msp() {
+        // MSP_API_VERSION request
+        let raw = &[0xC8, 0x07, 0x7A, 0xC8, 0xEA, 0x30, 0x00, 0x01, 0xBD];
+
+        let expected = Packet::MspRequest {
+            to: Address::FlightController,
+            from: Address::Handset,
+            status: msp::Status(0x30),
+            data: vec![0x00, 0x01],
+        };
+
+        round_trip(raw, &expected);
+
+        let packets = [
+            Packet::MspResponse {
+                to: Address::Handset,
+                from: Address::FlightController,
+                status: msp::Status(0xB1),
+                data: vec![0x03, 0x01, 0x00, 0x01, 0x2C],
+            },
+            Packet::MspWrite {
+                to: Address::FlightController,
+                from: Address::Handset,
+                status: msp::Status(0x22),
+                data: vec![],
+            },
+        ];
+
+        for packet in packets {
+            let raw = write(&packet);
+            assert_eq!(packet, Packet::read(&mut &raw[..]).unwrap());
+        }
+    }
+
+    #[test]
+    fn destination() {
+        let ping = Packet::DevicePing {
+            to: Address::Transmitter,
+            from: Address::Handset,
+        };
+        assert_eq!(ping.destination(), Some(Address::Transmitter));
+
+        let extended = Packet::Raw(RawPacket::new(0x3A, &[0xEA, 0xEE, 0x01]).unwrap());
+        assert_eq!(extended.destination(), Some(Address::Handset));
+
+        let basic = Packet::Raw(RawPacket::new(0x27, &[0xEA, 0xEE]).unwrap());
+        assert_eq!(basic.destination(), None);
+        assert_eq!(
+            Packet::FlightMode(FlightModeName::default()).destination(),
+            None
+        );
+    }
+
+    #[test]
+    fn packet_radio_id() {
+        let raw = &[
+            0xC8, 0x0D, 0x3A, 0xEA, 0xEE, 0x10, 0x00, 0x00, 0x9C, 0x40, 0xFF, 0xFF, 0xFB, 0x2E,
+            0xAA,
+        ];
+
+        let expected = Packet::RadioId {
+            to: Address::Handset,
+            from: Address::Transmitter,
+            interval: 40_000,
+            offset: -1234,
+        };
+
+        round_trip(raw, &expected);
+
+        // Other subtypes are left undecoded
+        let payload = [0xEA, 0xEE, 0x11, 0x00];
+        let raw = write(&Packet::Raw(RawPacket::new(0x3A, &payload).unwrap()));
+        let expected = Packet::Raw(RawPacket::new(0x3A, &payload).unwrap());
+        assert_eq!(expected, Packet::read(&mut &raw[..]).unwrap());
+    }
 }
@@ -0,0 +1,611 @@
+//! Test double that behaves like an ExpressLRS transmitter module
+//!
+//! Time is passed in explicitly as microseconds, so tests stay deterministic
+//! and can run under any executor (or none at all).
+
+use alloc::collections::VecDeque;
+use alloc::ffi::CString;
+use alloc::vec::Vec;
+use core::ffi::CStr;
+
+use crate::parameter::{CommandStatus, Kind, Parameter};
+use crate::{Address, FrameDecoder, MAX_FRAME_BYTES, MAX_PAYLOAD_BYTES, Packet, TxPower};
+
+/// Bytes of entry data per `PARAMETER_SETTINGS_ENTRY` chunk, after the
+/// extended header, index, and chunks remaining bytes
+const PARAMETER_CHUNK_BYTES: usize = MAX_PAYLOAD_BYTES - 4;
+
+/// ExpressLRS subtracts this from the sync offset so channel frames arrive a
+/// little before they are needed, in µs
+const SYNC_MARGIN: u64 = 100;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub name: CString,
+    pub serial_number: u32,
+    pub software_version: u32,
+    /// Time between RF packets in µs
+    pub packet_interval: u32,
+    /// RF packets between `LINK_STATISTICS` frames, or 0 to never send them
+    pub link_statistics_interval: u32,
+    /// RF packets between OpenTX sync frames, or 0 to never send them
+    pub sync_interval: u32,
+}
+
+impl Default for Config {
+    /// 250Hz, with link statistics and sync a few times per second
+    fn default() -> Self {
+        Self {
+            name: CString::new("Mock ELRS TX").unwrap(),
+            serial_number: u32::from_be_bytes(*b"ELRS"),
+            software_version: 0x0003_0500,
+            packet_interval: 4_000,
+            link_statistics_interval: 50,
+            sync_interval: 50,
+        }
+    }
+}
+
+/// A channel frame received from the handset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelFrame {
+    /// Time it was received in µs
+    pub timestamp: u64,
+    pub channels: [u16; 16],
+}
+
+#[derive(Debug)]
+pub struct MockTransmitter {
+    config: Config,
+    /// Parameters by index, starting at 1
+    parameters: Vec<Parameter>,
+    link_statistics: Packet,
+    channels: Vec<ChannelFrame>,
+    /// Other packets addressed to the module that it does not handle
+    unhandled: Vec<Packet>,
+    outbox: VecDeque<Packet>,
+    /// Time of the next RF packet in µs
+    next_rf: u64,
+    rf_packets: u64,
+    decoder: FrameDecoder,
+}
+
+impl MockTransmitter {
+    /// Create a module serving `parameters`, which must be numbered
+    /// consecutively from 1. The root folder is generated from their parents.
+    pub fn new(config: Config, parameters: Vec<Parameter>) -> Self {
+        for (i, parameter) in parameters.iter().enumerate() {
+            assert_eq!(
+                usize::from(parameter.index),
+                i + 1,
+                "parameters must be numbered consecutively from 1"
+            );
+        }
+
+        let next_rf = config.packet_interval.into();
+        Self {
+            config,
+            parameters,
+            link_statistics: Packet::LinkStatistics {
+                up_rssi1: 50,
+                up_rssi2: 50,
+                up_lq: 100,
+                up_snr: 10,
+                active_antenna: 0,
+                mode: 7,
+                tx_power: TxPower::mW_100,
+                down_rssi: 50,
+                down_lq: 100,
+                down_snr: 10,
+            },
+            channels: Vec::new(),
+            unhandled: Vec::new(),
+            outbox: VecDeque::new(),
+            next_rf,
+            rf_packets: 0,
+            decoder: FrameDecoder::new(),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Change the packet rate and sync intervals, taking effect from the next
+    /// RF packet
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    /// Replace the statistics reported from now on
+    ///
+    /// # Panics
+    ///
+    /// If `packet` is not a [`Packet::LinkStatistics`]
+    pub fn set_link_statistics(&mut self, packet: Packet) {
+        assert!(matches!(packet, Packet::LinkStatistics { .. }));
+        self.link_statistics = packet;
+    }
+
+    /// Current state of parameter `index`, including any writes
+    pub fn parameter(&self, index: u8) -> Option<&Parameter> {
+        self.parameters.get(usize::from(index).checked_sub(1)?)
+    }
+
+    /// All channel frames received so far
+    pub fn channels(&self) -> &[ChannelFrame] {
+        &self.channels
+    }
+
+    /// Packets sent to the module that it has no behavior for
+    pub fn unhandled(&self) -> &[Packet] {
+        &self.unhandled
+    }
+
+    /// Handle raw bytes from the handset's UART
+    pub fn receive_bytes(&mut self, now: u64, bytes: &[u8]) {
+        for &byte in bytes {
+            if let Some(mut frame) = self.decoder.push(byte)
+                && let Ok(packet) = Packet::read(&mut frame)
+            {
+                self.receive(now, &packet);
+            }
+        }
+    }
+
+    /// Handle a packet from the handset
+    pub fn receive(&mut self, now: u64, packet: &Packet) {
+        match packet {
+            Packet::RcChannelsPacked(channels) => self.channels.push(ChannelFrame {
+                timestamp: now,
+                channels: channels.unpack(),
+            }),
+            Packet::DevicePing {
+                to: Address::Transmitter | Address::Broadcast,
+                from,
+            } => self.outbox.push_back(self.device_info(*from)),
+            Packet::ParameterRead {
+                to: Address::Transmitter,
+                from,
+                index,
+                chunk,
+            } => {
+                if let Some(entry) = self.entry(*from, *index, *chunk) {
+                    self.outbox.push_back(entry);
+                }
+            }
+            Packet::ParameterWrite {
+                to: Address::Transmitter,
+                from,
+                index,
+                value,
+            } => self.write(*from, *index, value),
+            packet => self.unhandled.push(packet.clone()),
+        }
+    }
+
+    /// Return the next packet the module sends at or before `now`, if any
+    ///
+    /// Replies are sent as soon as possible. Link statistics and sync frames
+    /// are sent after RF packets, according to the [`Config`].
+    pub fn poll(&mut self, now: u64) -> Option<Packet> {
+        while self.next_rf <= now {
+            self.rf_packet();
+        }
+
+        self.outbox.pop_front()
+    }
+
+    /// Like [`poll`](Self::poll), but returns all pending packets as raw
+    /// bytes for the handset's UART
+    pub fn poll_bytes(&mut self, now: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        while let Some(packet) = self.poll(now) {
+            let mut buffer = [0; MAX_FRAME_BYTES];
+            let mut remaining = &mut buffer[..];
+            packet.write(&mut remaining).unwrap();
+            let len = MAX_FRAME_BYTES - remaining.len();
+            bytes.extend_from_slice(&buffer[..len]);
+        }
+        bytes
+    }
+
+    fn rf_packet(&mut self) {
+        let now = self.next_rf;
+        self.next_rf += u64::from(self.config.packet_interval);
+        self.rf_packets += 1;
+
+        let is_due =
+            |interval: u32| interval != 0 && self.rf_packets.is_multiple_of(u64::from(interval));
+
+        if is_due(self.config.link_statistics_interval) {
+            self.outbox.push_back(self.link_statistics.clone());
+        }
+
+        if is_due(self.config.sync_interval) {
+            let offset = self.channels.last().map_or(0, |last| {
+                let early = now.saturating_sub(last.timestamp);
+                early as i64 - SYNC_MARGIN as i64
+            });
+
+            self.outbox.push_back(Packet::RadioId {
+                to: Address::Handset,
+                from: Address::Transmitter,
+                interval: self.config.packet_interval * 10,
+                offset: (offset * 10).clamp(i32::MIN.into(), i32::MAX.into()) as i32,
+            });
+        }
+    }
+
+    fn device_info(&self, to: Address) -> Packet {
+        Packet::DeviceInfo {
+            to,
+            from: Address::Transmitter,
+            name: self.config.name.clone(),
+            serial_number: self.config.serial_number,
+            hardware_version: 0,
+            software_version: self.config.software_version,
+            parameter_count: self.parameters.len() as u8,
+            protocol_version: 0,
+        }
+    }
+
+    fn entry(&self, to: Address, index: u8, chunk: u8) -> Option<Packet> {
+        let data = if index == 0 {
+            self.root().encode()
+        } else {
+            self.parameter(index)?.encode()
+        };
+
+        let mut chunks = data.chunks(PARAMETER_CHUNK_BYTES);
+        let chunks_remaining = chunks.len().checked_sub(usize::from(chunk) + 1)?;
+        let data = chunks.nth(chunk.into())?;
+
+        Some(Packet::ParameterSettingsEntry {
+            to,
+            from: Address::Transmitter,
+            index,
+            chunks_remaining: chunks_remaining as u8,
+            data: data.into(),
+        })
+    }
+
+    fn root(&self) -> Parameter {
+        let children = self
+            .parameters
+            .iter()
+            .filter(|parameter| parameter.parent == 0)
+            .map(|parameter| parameter.index)
+            .collect();
+
+        Parameter {
+            index: 0,
+            parent: 0,
+            hidden: false,
+            name: CString::new("ROOT").unwrap(),
+            kind: Kind::Folder { children },
+        }
+    }
+
+    fn write(&mut self, from: Address, index: u8, value: &[u8]) {
+        let Some(parameter) = usize::from(index)
+            .checked_sub(1)
+            .and_then(|i| self.parameters.get_mut(i))
+        else {
+            return;
+        };
+
+        let is_command = matches!(parameter.kind, Kind::Command(_));
+        if !apply(&mut parameter.kind, value) {
+            return;
+        }
+
+        // ExpressLRS answers command writes with the updated entry
+        if is_command && let Some(entry) = self.entry(from, index, 0) {
+            self.outbox.push_back(entry);
+        }
+    }
+}
+
+/// Update a parameter with a written value, returning `false` if it does not
+/// match the parameter's type
+fn apply(kind: &mut Kind, value: &[u8]) -> bool {
+    match kind {
+        Kind::Uint8(number) => set(&mut number.value, value, |[x]| x),
+        Kind::Int8(number) => set(&mut number.value, value, i8::from_be_bytes),
+        Kind::Uint16(number) => set(&mut number.value, value, u16::from_be_bytes),
+        Kind::Int16(number) => set(&mut number.value, value, i16::from_be_bytes),
+        Kind::Uint32(number) => set(&mut number.value, value, u32::from_be_bytes),
+        Kind::Int32(number) => set(&mut number.value, value, i32::from_be_bytes),
+        Kind::Float(float) => set(&mut float.value, value, i32::from_be_bytes),
+        Kind::TextSelection(selection) => set(&mut selection.value, value, |[x]| x),
+        Kind::String { value: string, .. } => {
+            let Ok(new) = CStr::from_bytes_with_nul(value) else {
+                return false;
+            };
+            *string = new.into();
+            true
+        }
+        Kind::Command(command) => {
+            let Some(status) = value.first().copied().and_then(CommandStatus::from_raw) else {
+                return false;
+            };
+
+            command.status = match status {
+                CommandStatus::Start => CommandStatus::ConfirmationNeeded,
+                CommandStatus::Confirm | CommandStatus::Cancel => CommandStatus::Ready,
+                CommandStatus::Poll => command.status,
+                status => status,
+            };
+            true
+        }
+        Kind::Folder { .. } | Kind::Info(_) | Kind::OutOfRange => false,
+    }
+}
+
+fn set<T, const N: usize>(field: &mut T, value: &[u8], from_bytes: fn([u8; N]) -> T) -> bool {
+    match value.try_into() {
+        Ok(bytes) => {
+            *field = from_bytes(bytes);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::RcChannelsPacked;
+    use crate::parameter::{Client, Command, Number, Response, TextSelection, Value};
+
+    fn c(s: &str) -> CString {
+        CString::new(s).unwrap()
+    }
+
+    fn parameters() -> Vec<Parameter> {
+        vec![
+            Parameter {
+                index: 1,
+                parent: 0,
+                hidden: false,
+                name: c("Packet Rate"),
+                kind: Kind::TextSelection(TextSelection {
+                    options: c("50Hz;100Hz;150Hz;250Hz;333Hz;500Hz;D250;D500;F500;F1000"),
+                    value: 3,
+                    min: 0,
+                    max: 9,
+                    default: 3,
+                    units: c("Hz"),
+                }),
+            },
+            Parameter {
+                index: 2,
+                parent: 0,
+                hidden: false,
+                name: c("TX Power"),
+                kind: Kind::Folder { children: vec![3] },
+            },
+            Parameter {
+                index: 3,
+                parent: 2,
+                hidden: false,
+                name: c("Max Power"),
+                kind: Kind::Uint16(Number {
+                    value: 100,
+                    min: 10,
+                    max: 1000,
+                    default: 100,
+                    units: c("mW"),
+                }),
+            },
+            Parameter {
+                index: 4,
+                parent: 0,
+                hidden: false,
+                name: c("Bind"),
+                kind: Kind::Command(Command {
+                    status: CommandStatus::Ready,
+                    timeout: 200,
+                    info: c(""),
+                }),
+            },
+        ]
+    }
+
+    fn module() -> MockTransmitter {
+        MockTransmitter::new(Config::default(), parameters())
+    }
+
+    /// Read a parameter through the real client
+    fn read(module: &mut MockTransmitter, index: u8) -> Parameter {
+        let mut client = Client::new(Address::Transmitter, Address::Handset);
+        let mut request = client.read(index);
+        loop {
+            module.receive(0, &request);
+            let reply = module.poll(0).expect("no reply");
+            match client.receive(&reply).unwrap() {
+                Response::Complete(parameter) => return parameter,
+                Response::Request(next) => request = next,
+                Response::Ignored => panic!("ignored reply: {reply:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn device_ping() {
+        let mut module = module();
+        module.receive(
+            0,
+            &Packet::DevicePing {
+                to: Address::Broadcast,
+                from: Address::Handset,
+            },
+        );
+
+        let Some(Packet::DeviceInfo {
+            to,
+            from,
+            name,
+            parameter_count,
+            ..
+        }) = module.poll(0)
+        else {
+            panic!();
+        };
+        assert_eq!((to, from), (Address::Handset, Address::Transmitter));
+        assert_eq!(name, c("Mock ELRS TX"));
+        assert_eq!(parameter_count, 4);
+        assert_eq!(module.poll(0), None);
+    }
+
+    #[test]
+    fn parameter_tree() {
+        let mut module = module();
+
+        let root = read(&mut module, 0);
+        assert_eq!(
+            root.kind,
+            Kind::Folder {
+                children: vec![1, 2, 4]
+            }
+        );
+
+        // Long enough to need two chunks
+        for parameter in parameters() {
+            assert_eq!(read(&mut module, parameter.index), parameter);
+        }
+
+        module.receive(
+            0,
+            &Packet::ParameterRead {
+                to: Address::Transmitter,
+                from: Address::Handset,
+                index: 5,
+                chunk: 0,
+            },
+        );
+        assert_eq!(module.poll(0), None);
+    }
+
+    #[test]
+    fn parameter_write() {
+        let mut module = module();
+        let client = Client::new(Address::Transmitter, Address::Handset);
+
+        module.receive(0, &client.write(1, &Value::TextSelection(5)));
+        module.receive(0, &client.write(3, &Value::Uint16(250)));
+        // Wrong type
+        module.receive(0, &client.write(3, &Value::Uint8(1)));
+        assert_eq!(module.poll(0), None);
+
+        let Kind::TextSelection(selection) = &module.parameter(1).unwrap().kind else {
+            panic!();
+        };
+        assert_eq!(selection.selected(), Some(&b"500Hz"[..]));
+        let Kind::Uint16(number) = &module.parameter(3).unwrap().kind else {
+            panic!();
+        };
+        assert_eq!(number.value, 250);
+
+        module.receive(0, &client.write(4, &Value::Command(CommandStatus::Start)));
+        let Some(Packet::ParameterSettingsEntry { index: 4, data, .. }) = module.poll(0) else {
+            panic!();
+        };
+        // After the parent, type, and name
+        assert_eq!(data[7], CommandStatus::ConfirmationNeeded as u8);
+    }
+
+    #[test]
+    fn telemetry_schedule() {
+        let mut module = MockTransmitter::new(
+            Config {
+                packet_interval: 2_000,
+                link_statistics_interval: 2,
+                sync_interval: 3,
+                ..Config::default()
+            },
+            Vec::new(),
+        );
+
+        let mut received = Vec::new();
+        for now in (0..=12_000).step_by(1_000) {
+            while let Some(packet) = module.poll(now) {
+                received.push((now, packet));
+            }
+        }
+
+        let times = |f: fn(&Packet) -> bool| -> Vec<u64> {
+            received
+                .iter()
+                .filter(|(_, packet)| f(packet))
+                .map(|(now, _)| *now)
+                .collect()
+        };
+        assert_eq!(
+            times(|packet| matches!(packet, Packet::LinkStatistics { .. })),
+            [4_000, 8_000, 12_000]
+        );
+        assert_eq!(
+            times(|packet| matches!(packet, Packet::RadioId { .. })),
+            [6_000, 12_000]
+        );
+    }
+
+    #[test]
+    fn channels_and_sync() {
+        let mut module = MockTransmitter::new(
+            Config {
+                sync_interval: 1,
+                link_statistics_interval: 0,
+                ..Config::default()
+            },
+            Vec::new(),
+        );
+
+        let mut channels = [992; 16];
+        channels[2] = 172;
+        let packet = Packet::RcChannelsPacked(RcChannelsPacked::pack(&channels));
+
+        let mut frame = [0; MAX_FRAME_BYTES];
+        let mut remaining = &mut frame[..];
+        packet.write(&mut remaining).unwrap();
+        let len = MAX_FRAME_BYTES - remaining.len();
+        module.receive_bytes(3_500, &frame[..len]);
+
+        assert_eq!(
+            module.channels(),
+            [ChannelFrame {
+                timestamp: 3_500,
+                channels,
+            }]
+        );
+
+        let Some(Packet::RadioId {
+            interval, offset, ..
+        }) = module.poll(4_000)
+        else {
+            panic!();
+        };
+        assert_eq!(interval, 40_000);
+        assert_eq!(offset, 4_000);
+
+        // Only raw bytes from here
+        let bytes = module.poll_bytes(8_000);
+        assert_eq!(bytes[2], 0x3A);
+    }
+
+    #[test]
+    fn unhandled() {
+        let mut module = module();
+        let bind = Packet::Command {
+            to: Address::Transmitter,
+            from: Address::Handset,
+            command: crate::Command::Bind,
+        };
+        module.receive(0, &bind);
+        assert_eq!(module.unhandled(), [bind]);
+    }
+}
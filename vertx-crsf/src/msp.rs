@@ -0,0 +1,425 @@
+//! MSP tunneled through CRSF, as supported by Betaflight and iNav
+//!
+//! Each frame starts with a [`Status`] byte. The first chunk of a message then
+//! has an MSP header, followed by the payload, which may continue across
+//! further chunks. Chunks are numbered with a 4-bit sequence number.
+
+use alloc::vec::Vec;
+
+use crate::{Address, Packet};
+
+/// Bytes per outgoing frame, including the status byte. The Betaflight Lua
+/// scripts send no more than this, so it is the size receivers are guaranteed
+/// to forward.
+const REQUEST_CHUNK_BYTES: usize = 8;
+
+bitfield::bitfield! {
+    /// First byte of every MSP frame
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct Status(u8);
+    impl Debug;
+    pub u8, sequence, set_sequence: 3, 0;
+    /// Set on the first chunk of a message
+    pub start, set_start: 4;
+    pub u8, version, set_version: 6, 5;
+    /// Set on responses if the command failed
+    pub error, set_error: 7;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    V1,
+    V2,
+}
+
+impl Version {
+    fn from_raw(raw: u8) -> Option<Self> {
+        match raw {
+            1 => Some(Self::V1),
+            2 => Some(Self::V2),
+            _ => None,
+        }
+    }
+
+    const fn raw(self) -> u8 {
+        match self {
+            Self::V1 => 1,
+            Self::V2 => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The command does not fit in an MSPv1 header
+    CommandOutOfRange,
+    /// The payload is too long for the MSP version
+    PayloadTooLong,
+    /// A response chunk was lost, so the response was dropped
+    Sequence,
+    /// A response header is invalid
+    Malformed,
+    /// The flight controller reported an error for this command
+    Rejected(u16),
+}
+
+/// A complete response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub command: u16,
+    pub payload: Vec<u8>,
+}
+
+/// Sends MSP commands to the flight controller and reassembles the responses
+#[derive(Debug)]
+pub struct Client {
+    origin: Address,
+    /// Sequence number of the next outgoing chunk
+    sequence: u8,
+    pending: Option<PendingResponse>,
+}
+
+#[derive(Debug)]
+struct PendingResponse {
+    command: u16,
+    size: usize,
+    error: bool,
+    /// Sequence number of the last received chunk
+    sequence: u8,
+    payload: Vec<u8>,
+}
+
+impl Client {
+    /// Create a client that sends requests from `origin`
+    pub const fn new(origin: Address) -> Self {
+        Self {
+            origin,
+            sequence: 0,
+            pending: None,
+        }
+    }
+
+    /// Build the frames for an `MSP_REQUEST`, which should be sent in order
+    pub fn request(
+        &mut self,
+        version: Version,
+        command: u16,
+        payload: &[u8],
+    ) -> Result<Vec<Packet>, Error> {
+        self.encode(version, command, payload, |to, from, status, data| {
+            Packet::MspRequest {
+                to,
+                from,
+                status,
+                data,
+            }
+        })
+    }
+
+    /// Build the frames for an `MSP_WRITE`, which should be sent in order
+    pub fn write(
+        &mut self,
+        version: Version,
+        command: u16,
+        payload: &[u8],
+    ) -> Result<Vec<Packet>, Error> {
+        self.encode(version, command, payload, |to, from, status, data| {
+            Packet::MspWrite {
+                to,
+                from,
+                status,
+                data,
+            }
+        })
+    }
+
+    /// Handle a packet from the flight controller, returning the response once
+    /// all of its chunks have arrived
+    pub fn receive(&mut self, packet: &Packet) -> Result<Option<Response>, Error> {
+        let Packet::MspResponse {
+            to,
+            from: Address::FlightController,
+            status,
+            data,
+        } = packet
+        else {
+            return Ok(None);
+        };
+
+        if *to != self.origin {
+            return Ok(None);
+        }
+
+        let mut pending = if status.start() {
+            let version = Version::from_raw(status.version()).ok_or(Error::Malformed)?;
+            let (command, size, rest) = parse_header(version, data).ok_or(Error::Malformed)?;
+
+            PendingResponse {
+                command,
+                size,
+                error: status.error(),
+                sequence: status.sequence(),
+                payload: rest.into(),
+            }
+        } else {
+            let Some(mut pending) = self.pending.take() else {
+                return Ok(None);
+            };
+
+            if status.sequence() != (pending.sequence + 1) & 0x0F {
+                return Err(Error::Sequence);
+            }
+
+            pending.sequence = status.sequence();
+            pending.payload.extend_from_slice(data);
+            pending
+        };
+
+        if pending.payload.len() < pending.size {
+            self.pending = Some(pending);
+            return Ok(None);
+        }
+
+        if pending.error {
+            return Err(Error::Rejected(pending.command));
+        }
+
+        // Drop any trailing checksum
+        pending.payload.truncate(pending.size);
+        Ok(Some(Response {
+            command: pending.command,
+            payload: pending.payload,
+        }))
+    }
+
+    fn encode(
+        &mut self,
+        version: Version,
+        command: u16,
+        payload: &[u8],
+        packet: impl Fn(Address, Address, Status, Vec<u8>) -> Packet,
+    ) -> Result<Vec<Packet>, Error> {
+        let mut message = Vec::with_capacity(payload.len() + 5);
+        match version {
+            Version::V1 => {
+                let size = u8::try_from(payload.len()).map_err(|_| Error::PayloadTooLong)?;
+                let command = u8::try_from(command).map_err(|_| Error::CommandOutOfRange)?;
+                message.extend_from_slice(&[size, command]);
+            }
+            Version::V2 => {
+                let size = u16::try_from(payload.len()).map_err(|_| Error::PayloadTooLong)?;
+                message.push(0);
+                message.extend_from_slice(&command.to_le_bytes());
+                message.extend_from_slice(&size.to_le_bytes());
+            }
+        }
+        message.extend_from_slice(payload);
+
+        let packets = message
+            .chunks(REQUEST_CHUNK_BYTES - 1)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let mut status = Status(0);
+                status.set_sequence(self.sequence);
+                status.set_start(i == 0);
+                status.set_version(version.raw());
+                self.sequence = (self.sequence + 1) & 0x0F;
+
+                packet(Address::FlightController, self.origin, status, chunk.into())
+            })
+            .collect();
+
+        Ok(packets)
+    }
+}
+
+/// Split the first chunk of a response into command, payload size, and the
+/// start of the payload
+fn parse_header(version: Version, data: &[u8]) -> Option<(u16, usize, &[u8])> {
+    match version {
+        Version::V1 => {
+            let ([size, command], rest) = data.split_first_chunk()?;
+            Some(((*command).into(), (*size).into(), rest))
+        }
+        Version::V2 => {
+            let ([_flags, c0, c1, s0, s1], rest) = data.split_first_chunk()?;
+            let command = u16::from_le_bytes([*c0, *c1]);
+            let size = u16::from_le_bytes([*s0, *s1]);
+            Some((command, size.into(), rest))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    const MSP_API_VERSION: u16 = 1;
+    const MSP_PID: u16 = 112;
+
+    fn response(sequence: u8, start: bool, error: bool, data: &[u8]) -> Packet {
+        let mut status = Status(0);
+        status.set_sequence(sequence);
+        status.set_start(start);
+        status.set_version(1);
+        status.set_error(error);
+
+        Packet::MspResponse {
+            to: Address::Handset,
+            from: Address::FlightController,
+            status,
+            data: data.into(),
+        }
+    }
+
+    fn data(packet: &Packet) -> (Status, &[u8]) {
+        let Packet::MspRequest {
+            to, status, data, ..
+        } = packet
+        else {
+            panic!("not a request: {packet:?}");
+        };
+        assert_eq!(*to, Address::FlightController);
+        (*status, data)
+    }
+
+    #[test]
+    fn request_v1() {
+        let mut client = Client::new(Address::Handset);
+        let packets = client.request(Version::V1, MSP_API_VERSION, &[]).unwrap();
+
+        let [packet] = &packets[..] else { panic!() };
+        let (status, data) = data(packet);
+        assert_eq!(status.0, 0x30);
+        assert_eq!(data, [0, 1]);
+    }
+
+    #[test]
+    fn request_chunked() {
+        let mut client = Client::new(Address::Handset);
+        let payload: Vec<u8> = (0..20).collect();
+        let packets = client.request(Version::V2, 0x1F03, &payload).unwrap();
+
+        // 5 header bytes + 20 payload bytes in chunks of 7
+        assert_eq!(packets.len(), 4);
+
+        let mut message = Vec::new();
+        for (i, packet) in packets.iter().enumerate() {
+            let (status, data) = data(packet);
+            assert_eq!(status.sequence(), i as u8);
+            assert_eq!(status.start(), i == 0);
+            assert_eq!(status.version(), 2);
+            message.extend_from_slice(data);
+        }
+
+        assert_eq!(message[0..5], [0, 0x03, 0x1F, 20, 0]);
+        assert_eq!(message[5..], payload);
+
+        // Sequence numbers continue across messages
+        let packets = client.request(Version::V1, MSP_PID, &[]).unwrap();
+        assert_eq!(data(&packets[0]).0.sequence(), 4);
+    }
+
+    #[test]
+    fn request_limits() {
+        let mut client = Client::new(Address::Handset);
+        assert_eq!(
+            client.request(Version::V1, 0x100, &[]),
+            Err(Error::CommandOutOfRange)
+        );
+        assert_eq!(
+            client.request(Version::V1, MSP_PID, &[0; 256]),
+            Err(Error::PayloadTooLong)
+        );
+        assert!(client.request(Version::V2, 0x100, &[0; 256]).is_ok());
+    }
+
+    #[test]
+    fn response_reassembly() {
+        let mut client = Client::new(Address::Handset);
+
+        // 30 bytes of PIDs, plus a trailing checksum
+        let pids: Vec<u8> = (100..130).collect();
+        let mut message = vec![30, MSP_PID as u8];
+        message.extend_from_slice(&pids);
+        message.push(0xAA);
+
+        let chunks: Vec<_> = message.chunks(12).collect();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let result = client.receive(&response((15 + i as u8) & 0x0F, i == 0, false, chunk));
+            if i < chunks.len() - 1 {
+                assert_eq!(result, Ok(None));
+            } else {
+                let expected = Response {
+                    command: MSP_PID,
+                    payload: pids.clone(),
+                };
+                assert_eq!(result, Ok(Some(expected)));
+            }
+        }
+    }
+
+    #[test]
+    fn response_lost_chunk() {
+        let mut client = Client::new(Address::Handset);
+        let start = [20, MSP_PID as u8, 1, 2, 3];
+
+        assert_eq!(client.receive(&response(3, true, false, &start)), Ok(None));
+        assert_eq!(
+            client.receive(&response(5, false, false, &[0; 20])),
+            Err(Error::Sequence)
+        );
+
+        // The rest of the response is ignored
+        assert_eq!(
+            client.receive(&response(6, false, false, &[0; 20])),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn response_error() {
+        let mut client = Client::new(Address::Handset);
+        assert_eq!(
+            client.receive(&response(0, true, true, &[0, MSP_PID as u8])),
+            Err(Error::Rejected(MSP_PID))
+        );
+    }
+
+    #[test]
+    fn response_v2() {
+        let mut client = Client::new(Address::Handset);
+        let mut status = Status(0);
+        status.set_start(true);
+        status.set_version(2);
+
+        let packet = Packet::MspResponse {
+            to: Address::Handset,
+            from: Address::FlightController,
+            status,
+            data: vec![0, 0x03, 0x1F, 2, 0, 0xAB, 0xCD],
+        };
+
+        let expected = Response {
+            command: 0x1F03,
+            payload: vec![0xAB, 0xCD],
+        };
+        assert_eq!(client.receive(&packet), Ok(Some(expected)));
+    }
+
+    #[test]
+    fn ignores_other_devices() {
+        let mut client = Client::new(Address::Handset);
+        let mut packet = response(0, true, false, &[0, MSP_PID as u8]);
+        let Packet::MspResponse { to, .. } = &mut packet else {
+            unreachable!()
+        };
+        *to = Address::ElrsLua;
+
+        assert_eq!(client.receive(&packet), Ok(None));
+    }
+}
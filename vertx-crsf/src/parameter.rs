@@ -0,0 +1,785 @@
+//! Client side of the CRSF parameter protocol, used to configure devices like
+//! ExpressLRS transmitter modules. [`Parameter::encode`] covers the device side
+//! of reads.
+//!
+//! Parameters are addressed by index, starting at 1; index 0 is the root
+//! folder. Each entry may be split over several `PARAMETER_SETTINGS_ENTRY`
+//! chunks, which must be requested one at a time.
+
+use alloc::ffi::CString;
+use alloc::vec::Vec;
+use core::ffi::CStr;
+
+use crate::{Address, Malformed, Packet, enum_repr};
+
+/// Set on the raw type byte for parameters that should not be displayed
+const HIDDEN: u8 = 0x80;
+
+/// Children list terminator in ExpressLRS folder entries
+const END_OF_CHILDREN: u8 = 0xFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The reassembled entry is too short or contains invalid data
+    Malformed,
+    /// The entry has a type this client does not understand
+    UnknownKind(u8),
+}
+
+impl From<Malformed> for Error {
+    fn from(Malformed: Malformed) -> Self {
+        Self::Malformed
+    }
+}
+
+/// Reads and writes the parameters of a single device
+#[derive(Debug)]
+pub struct Client {
+    device: Address,
+    origin: Address,
+    pending: Option<PendingRead>,
+}
+
+#[derive(Debug)]
+struct PendingRead {
+    index: u8,
+    /// Next chunk to request
+    chunk: u8,
+    /// `chunks_remaining` expected in the next received chunk, once known
+    remaining: Option<u8>,
+    data: Vec<u8>,
+}
+
+/// Result of passing a received packet to [`Client::receive`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    /// The packet was not part of the read in progress
+    Ignored,
+    /// The entry is incomplete; send this request for the next chunk
+    Request(Packet),
+    /// The entry has been completely received
+    Complete(Parameter),
+}
+
+impl Client {
+    /// Create a client for `device`, sending requests from `origin`
+    pub const fn new(device: Address, origin: Address) -> Self {
+        Self {
+            device,
+            origin,
+            pending: None,
+        }
+    }
+
+    pub fn device(&self) -> Address {
+        self.device
+    }
+
+    /// Start reading parameter `index`, abandoning any read in progress, and
+    /// return the request to send
+    pub fn read(&mut self, index: u8) -> Packet {
+        self.pending = Some(PendingRead {
+            index,
+            chunk: 0,
+            remaining: None,
+            data: Vec::new(),
+        });
+        self.request(index, 0)
+    }
+
+    /// Request to resend if the device did not answer the last one
+    pub fn retry(&self) -> Option<Packet> {
+        self.pending
+            .as_ref()
+            .map(|pending| self.request(pending.index, pending.chunk))
+    }
+
+    /// Handle a packet from the device
+    ///
+    /// If a chunk arrives out of order, the read restarts from the first
+    /// chunk. A malformed entry ends the read.
+    pub fn receive(&mut self, packet: &Packet) -> Result<Response, Error> {
+        let Packet::ParameterSettingsEntry {
+            from,
+            index,
+            chunks_remaining,
+            data,
+            ..
+        } = packet
+        else {
+            return Ok(Response::Ignored);
+        };
+
+        if *from != self.device {
+            return Ok(Response::Ignored);
+        }
+
+        let Some(mut pending) = self.pending.take_if(|pending| pending.index == *index) else {
+            return Ok(Response::Ignored);
+        };
+
+        if pending
+            .remaining
+            .is_some_and(|remaining| remaining != *chunks_remaining)
+        {
+            pending.chunk = 0;
+            pending.remaining = None;
+            pending.data.clear();
+            self.pending = Some(pending);
+            return Ok(Response::Request(self.request(*index, 0)));
+        }
+
+        pending.data.extend_from_slice(data);
+
+        if let Some(remaining) = chunks_remaining.checked_sub(1) {
+            pending.chunk = pending.chunk.checked_add(1).ok_or(Malformed)?;
+            pending.remaining = Some(remaining);
+            let request = self.request(*index, pending.chunk);
+            self.pending = Some(pending);
+            return Ok(Response::Request(request));
+        }
+
+        Parameter::parse(*index, &pending.data).map(Response::Complete)
+    }
+
+    /// Build a request to set parameter `index` to `value`
+    pub fn write(&self, index: u8, value: &Value) -> Packet {
+        Packet::ParameterWrite {
+            to: self.device,
+            from: self.origin,
+            index,
+            value: value.encode(),
+        }
+    }
+
+    fn request(&self, index: u8, chunk: u8) -> Packet {
+        Packet::ParameterRead {
+            to: self.device,
+            from: self.origin,
+            index,
+            chunk,
+        }
+    }
+}
+
+/// A complete parameter entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+    pub index: u8,
+    /// Index of the containing folder
+    pub parent: u8,
+    pub hidden: bool,
+    pub name: CString,
+    pub kind: Kind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Kind {
+    Uint8(Number<u8>),
+    Int8(Number<i8>),
+    Uint16(Number<u16>),
+    Int16(Number<i16>),
+    Uint32(Number<u32>),
+    Int32(Number<i32>),
+    Float(Float),
+    TextSelection(TextSelection),
+    String {
+        value: CString,
+        max_length: Option<u8>,
+    },
+    Folder {
+        /// Indices of the parameters in this folder. Empty if the device does
+        /// not list them, in which case the parents of all parameters must be
+        /// checked.
+        children: Vec<u8>,
+    },
+    /// Read-only text
+    Info(CString),
+    Command(Command),
+    /// Requested index is past the last parameter
+    OutOfRange,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Number<T> {
+    pub value: T,
+    pub min: T,
+    pub max: T,
+    pub default: T,
+    pub units: CString,
+}
+
+/// Fixed point number
+#[derive(Debug, Clone, PartialEq)]
+pub struct Float {
+    pub value: i32,
+    pub min: i32,
+    pub max: i32,
+    pub default: i32,
+    /// Number of decimal places
+    pub precision: u8,
+    pub step: u32,
+    pub units: CString,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextSelection {
+    /// `;` separated list of options
+    pub options: CString,
+    /// Index into `options`
+    pub value: u8,
+    pub min: u8,
+    pub max: u8,
+    pub default: u8,
+    pub units: CString,
+}
+
+impl TextSelection {
+    pub fn options(&self) -> impl Iterator<Item = &[u8]> {
+        self.options.to_bytes().split(|&b| b == b';')
+    }
+
+    /// The currently selected option
+    pub fn selected(&self) -> Option<&[u8]> {
+        self.options().nth(self.value.into())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Command {
+    pub status: CommandStatus,
+    /// Time the device may take to respond, in units of 10ms
+    pub timeout: u8,
+    /// Status message
+    pub info: CString,
+}
+
+enum_repr! {
+    #[repr(u8)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum CommandStatus {
+        Ready = 0,
+        /// Sent by the handset to start the command
+        Start = 1,
+        Progress = 2,
+        /// The device is waiting for the handset to confirm or cancel
+        ConfirmationNeeded = 3,
+        /// Sent by the handset in response to `ConfirmationNeeded`
+        Confirm = 4,
+        /// Sent by the handset to cancel the command
+        Cancel = 5,
+        /// Sent by the handset to request a status update
+        Poll = 6,
+    }
+}
+
+/// New value for a parameter. This must match the type of the parameter being
+/// written.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Uint8(u8),
+    Int8(i8),
+    Uint16(u16),
+    Int16(i16),
+    Uint32(u32),
+    Int32(i32),
+    Float(i32),
+    /// Index of the selected option
+    TextSelection(u8),
+    String(CString),
+    Command(CommandStatus),
+}
+
+impl Value {
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            Self::Uint8(x) | Self::TextSelection(x) => [*x].into(),
+            Self::Int8(x) => x.to_be_bytes().into(),
+            Self::Uint16(x) => x.to_be_bytes().into(),
+            Self::Int16(x) => x.to_be_bytes().into(),
+            Self::Uint32(x) => x.to_be_bytes().into(),
+            Self::Int32(x) | Self::Float(x) => x.to_be_bytes().into(),
+            Self::String(x) => x.to_bytes_with_nul().into(),
+            Self::Command(status) => [*status as u8].into(),
+        }
+    }
+}
+
+impl Parameter {
+    /// Serialize the entry as it is sent in `PARAMETER_SETTINGS_ENTRY` chunks,
+    /// before splitting. Folders always get a children list.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.push(self.parent);
+        data.push(self.kind.raw() | if self.hidden { HIDDEN } else { 0 });
+        data.extend_from_slice(self.name.to_bytes_with_nul());
+
+        match &self.kind {
+            Kind::Uint8(number) => encode_number(&mut data, number, |x| [x]),
+            Kind::Int8(number) => encode_number(&mut data, number, i8::to_be_bytes),
+            Kind::Uint16(number) => encode_number(&mut data, number, u16::to_be_bytes),
+            Kind::Int16(number) => encode_number(&mut data, number, i16::to_be_bytes),
+            Kind::Uint32(number) => encode_number(&mut data, number, u32::to_be_bytes),
+            Kind::Int32(number) => encode_number(&mut data, number, i32::to_be_bytes),
+            Kind::Float(float) => {
+                for x in [float.value, float.min, float.max, float.default] {
+                    data.extend_from_slice(&x.to_be_bytes());
+                }
+                data.push(float.precision);
+                data.extend_from_slice(&float.step.to_be_bytes());
+                data.extend_from_slice(float.units.to_bytes_with_nul());
+            }
+            Kind::TextSelection(selection) => {
+                data.extend_from_slice(selection.options.to_bytes_with_nul());
+                data.extend_from_slice(&[
+                    selection.value,
+                    selection.min,
+                    selection.max,
+                    selection.default,
+                ]);
+                data.extend_from_slice(selection.units.to_bytes_with_nul());
+            }
+            Kind::String { value, max_length } => {
+                data.extend_from_slice(value.to_bytes_with_nul());
+                data.extend(max_length);
+            }
+            Kind::Folder { children } => {
+                data.extend_from_slice(children);
+                data.push(END_OF_CHILDREN);
+            }
+            Kind::Info(info) => data.extend_from_slice(info.to_bytes_with_nul()),
+            Kind::Command(command) => {
+                data.extend_from_slice(&[command.status as u8, command.timeout]);
+                data.extend_from_slice(command.info.to_bytes_with_nul());
+            }
+            Kind::OutOfRange => {}
+        }
+
+        data
+    }
+
+    fn parse(index: u8, data: &[u8]) -> Result<Self, Error> {
+        let mut data = Cursor(data);
+
+        let parent = data.u8()?;
+        let raw_kind = data.u8()?;
+        let name = data.c_string()?;
+
+        let kind = match raw_kind & !HIDDEN {
+            0 => Kind::Uint8(data.number(Cursor::u8)?),
+            1 => Kind::Int8(data.number(Cursor::i8)?),
+            2 => Kind::Uint16(data.number(Cursor::u16)?),
+            3 => Kind::Int16(data.number(Cursor::i16)?),
+            4 => Kind::Uint32(data.number(Cursor::u32)?),
+            5 => Kind::Int32(data.number(Cursor::i32)?),
+            8 => Kind::Float(Float {
+                value: data.i32()?,
+                min: data.i32()?,
+                max: data.i32()?,
+                default: data.i32()?,
+                precision: data.u8()?,
+                step: data.u32()?,
+                units: data.c_string()?,
+            }),
+            9 => Kind::TextSelection(TextSelection {
+                options: data.c_string()?,
+                value: data.u8()?,
+                min: data.u8()?,
+                max: data.u8()?,
+                default: data.u8()?,
+                units: data.c_string()?,
+            }),
+            10 => Kind::String {
+                value: data.c_string()?,
+                max_length: data.u8().ok(),
+            },
+            11 => {
+                let children = data.0.split(|&b| b == END_OF_CHILDREN).next();
+                Kind::Folder {
+                    children: children.unwrap_or_default().to_vec(),
+                }
+            }
+            12 => Kind::Info(data.c_string()?),
+            13 => Kind::Command(Command {
+                status: CommandStatus::from_raw(data.u8()?).ok_or(Malformed)?,
+                timeout: data.u8()?,
+                info: data.c_string()?,
+            }),
+            127 => Kind::OutOfRange,
+            kind => return Err(Error::UnknownKind(kind)),
+        };
+
+        Ok(Self {
+            index,
+            parent,
+            hidden: raw_kind & HIDDEN != 0,
+            name,
+            kind,
+        })
+    }
+}
+
+impl Kind {
+    const fn raw(&self) -> u8 {
+        match self {
+            Self::Uint8(_) => 0,
+            Self::Int8(_) => 1,
+            Self::Uint16(_) => 2,
+            Self::Int16(_) => 3,
+            Self::Uint32(_) => 4,
+            Self::Int32(_) => 5,
+            Self::Float(_) => 8,
+            Self::TextSelection(_) => 9,
+            Self::String { .. } => 10,
+            Self::Folder { .. } => 11,
+            Self::Info(_) => 12,
+            Self::Command(_) => 13,
+            Self::OutOfRange => 127,
+        }
+    }
+}
+
+fn encode_number<T: Copy, const N: usize>(
+    data: &mut Vec<u8>,
+    number: &Number<T>,
+    to_bytes: fn(T) -> [u8; N],
+) {
+    for x in [number.value, number.min, number.max, number.default] {
+        data.extend_from_slice(&to_bytes(x));
+    }
+    data.extend_from_slice(number.units.to_bytes_with_nul());
+}
+
+struct Cursor<'a>(&'a [u8]);
+
+impl Cursor<'_> {
+    fn number<T>(
+        &mut self,
+        read: fn(&mut Self) -> Result<T, Malformed>,
+    ) -> Result<Number<T>, Malformed> {
+        Ok(Number {
+            value: read(self)?,
+            min: read(self)?,
+            max: read(self)?,
+            default: read(self)?,
+            units: self.c_string()?,
+        })
+    }
+
+    fn c_string(&mut self) -> Result<CString, Malformed> {
+        let string = CStr::from_bytes_until_nul(self.0).map_err(|_| Malformed)?;
+        self.0 = self.0.get(string.count_bytes() + 1..).unwrap_or_default();
+        Ok(string.into())
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Malformed> {
+        let (bytes, rest) = self.0.split_first_chunk().ok_or(Malformed)?;
+        self.0 = rest;
+        Ok(*bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Malformed> {
+        let [x] = self.array()?;
+        Ok(x)
+    }
+
+    fn i8(&mut self) -> Result<i8, Malformed> {
+        self.u8().map(u8::cast_signed)
+    }
+
+    fn u16(&mut self) -> Result<u16, Malformed> {
+        self.array().map(u16::from_be_bytes)
+    }
+
+    fn i16(&mut self) -> Result<i16, Malformed> {
+        self.u16().map(u16::cast_signed)
+    }
+
+    fn u32(&mut self) -> Result<u32, Malformed> {
+        self.array().map(u32::from_be_bytes)
+    }
+
+    fn i32(&mut self) -> Result<i32, Malformed> {
+        self.u32().map(u32::cast_signed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    const TX: Address = Address::Transmitter;
+
+    fn client() -> Client {
+        Client::new(TX, Address::Handset)
+    }
+
+    fn entry(index: u8, chunks_remaining: u8, data: &[u8]) -> Packet {
+        Packet::ParameterSettingsEntry {
+            to: Address::Handset,
+            from: TX,
+            index,
+            chunks_remaining,
+            data: data.to_vec(),
+        }
+    }
+
+    fn read_request(index: u8, chunk: u8) -> Packet {
+        Packet::ParameterRead {
+            to: TX,
+            from: Address::Handset,
+            index,
+            chunk,
+        }
+    }
+
+    fn parse(data: &[u8]) -> Result<Parameter, Error> {
+        let mut client = client();
+        client.read(1);
+        match client.receive(&entry(1, 0, data))? {
+            Response::Complete(parameter) => Ok(parameter),
+            response => panic!("unexpected response: {response:?}"),
+        }
+    }
+
+    fn c(s: &str) -> CString {
+        CString::new(s).unwrap()
+    }
+
+    #[test]
+    fn chunked_text_selection() {
+        let data =
+            b"\x00\x09Packet Rate\x0050Hz;100Hz;150Hz;250Hz;333Hz;500Hz\x00\x03\x00\x05\x03Hz\x00";
+        let (first, second) = data.split_at(40);
+
+        let mut client = client();
+        assert_eq!(client.read(1), read_request(1, 0));
+        assert_eq!(
+            client.receive(&entry(1, 1, first)),
+            Ok(Response::Request(read_request(1, 1)))
+        );
+        assert_eq!(client.retry(), Some(read_request(1, 1)));
+
+        let Ok(Response::Complete(parameter)) = client.receive(&entry(1, 0, second)) else {
+            panic!();
+        };
+        assert_eq!(client.retry(), None);
+
+        let expected = Parameter {
+            index: 1,
+            parent: 0,
+            hidden: false,
+            name: c("Packet Rate"),
+            kind: Kind::TextSelection(TextSelection {
+                options: c("50Hz;100Hz;150Hz;250Hz;333Hz;500Hz"),
+                value: 3,
+                min: 0,
+                max: 5,
+                default: 3,
+                units: c("Hz"),
+            }),
+        };
+        assert_eq!(parameter, expected);
+
+        let Kind::TextSelection(selection) = &parameter.kind else {
+            unreachable!()
+        };
+        assert_eq!(selection.options().count(), 6);
+        assert_eq!(selection.selected(), Some(&b"250Hz"[..]));
+    }
+
+    #[test]
+    fn out_of_order_chunk_restarts() {
+        let mut client = client();
+        client.read(5);
+        client.receive(&entry(5, 2, b"\x00")).unwrap();
+
+        assert_eq!(
+            client.receive(&entry(5, 0, b"\x0C")),
+            Ok(Response::Request(read_request(5, 0)))
+        );
+        assert_eq!(client.retry(), Some(read_request(5, 0)));
+    }
+
+    #[test]
+    fn ignores_unrelated_packets() {
+        let mut client = client();
+        assert_eq!(client.receive(&entry(1, 0, b"")), Ok(Response::Ignored));
+
+        client.read(2);
+        assert_eq!(client.receive(&entry(1, 0, b"")), Ok(Response::Ignored));
+
+        let other = Packet::ParameterSettingsEntry {
+            to: Address::Handset,
+            from: Address::Receiver,
+            index: 2,
+            chunks_remaining: 0,
+            data: vec![],
+        };
+        assert_eq!(client.receive(&other), Ok(Response::Ignored));
+        assert_eq!(client.retry(), Some(read_request(2, 0)));
+    }
+
+    #[test]
+    fn numbers() {
+        let parameter = parse(b"\x00\x81Offset\x00\xFE\x80\x7F\x00dB\x00").unwrap();
+        assert!(parameter.hidden);
+        assert_eq!(
+            parameter.kind,
+            Kind::Int8(Number {
+                value: -2,
+                min: -128,
+                max: 127,
+                default: 0,
+                units: c("dB"),
+            })
+        );
+
+        let parameter = parse(b"\x02\x02Rate\x00\x01\xF4\x00\x00\x03\xE8\x00\x32ms\x00").unwrap();
+        assert_eq!(parameter.parent, 2);
+        assert_eq!(
+            parameter.kind,
+            Kind::Uint16(Number {
+                value: 500,
+                min: 0,
+                max: 1000,
+                default: 50,
+                units: c("ms"),
+            })
+        );
+    }
+
+    #[test]
+    fn float() {
+        let data = b"\x00\x08Gain\x00\
+            \x00\x00\x00\x96\xFF\xFF\xFF\x9C\x00\x00\x01\x2C\x00\x00\x00\x64\
+            \x02\x00\x00\x00\x05\x00";
+
+        assert_eq!(
+            parse(data).unwrap().kind,
+            Kind::Float(Float {
+                value: 150,
+                min: -100,
+                max: 300,
+                default: 100,
+                precision: 2,
+                step: 5,
+                units: c(""),
+            })
+        );
+    }
+
+    #[test]
+    fn strings() {
+        let parameter = parse(b"\x00\x0APhrase\x00hunter2\x00\x20").unwrap();
+        assert_eq!(
+            parameter.kind,
+            Kind::String {
+                value: c("hunter2"),
+                max_length: Some(32),
+            }
+        );
+
+        let parameter = parse(b"\x00\x0CBad/Good\x000/100\x00").unwrap();
+        assert_eq!(parameter.kind, Kind::Info(c("0/100")));
+    }
+
+    #[test]
+    fn folder() {
+        let parameter = parse(b"\x00\x0BTX Power\x00\x08\x09\x0A\xFF").unwrap();
+        assert_eq!(parameter.name, c("TX Power"));
+        assert_eq!(
+            parameter.kind,
+            Kind::Folder {
+                children: vec![8, 9, 10]
+            }
+        );
+
+        let parameter = parse(b"\x00\x0BOther\x00").unwrap();
+        assert_eq!(parameter.kind, Kind::Folder { children: vec![] });
+    }
+
+    #[test]
+    fn command() {
+        let parameter = parse(b"\x00\x0DBind\x00\x00\xC8\x00").unwrap();
+        assert_eq!(
+            parameter.kind,
+            Kind::Command(Command {
+                status: CommandStatus::Ready,
+                timeout: 200,
+                info: c(""),
+            })
+        );
+
+        assert_eq!(
+            parse(b"\x00\x0DBind\x00\x07\xC8\x00"),
+            Err(Error::Malformed)
+        );
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert_eq!(parse(b"\x00\x7F\x00").unwrap().kind, Kind::OutOfRange);
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(parse(b""), Err(Error::Malformed));
+        assert_eq!(parse(b"\x00\x00Missing nul"), Err(Error::Malformed));
+        assert_eq!(parse(b"\x00\x00Short\x00\x01\x02"), Err(Error::Malformed));
+        assert_eq!(parse(b"\x00\x06U64\x00"), Err(Error::UnknownKind(6)));
+    }
+
+    #[test]
+    fn encode() {
+        let entries: [&[u8]; 10] = [
+            b"\x00\x09Packet Rate\x0050Hz;100Hz\x00\x01\x00\x01\x01Hz\x00",
+            b"\x00\x81Offset\x00\xFE\x80\x7F\x00dB\x00",
+            b"\x02\x02Rate\x00\x01\xF4\x00\x00\x03\xE8\x00\x32ms\x00",
+            b"\x00\x08Gain\x00\
+              \x00\x00\x00\x96\xFF\xFF\xFF\x9C\x00\x00\x01\x2C\x00\x00\x00\x64\
+              \x02\x00\x00\x00\x05\x00",
+            b"\x00\x0APhrase\x00hunter2\x00\x20",
+            b"\x00\x0APhrase\x00hunter2\x00",
+            b"\x00\x0CBad/Good\x000/100\x00",
+            b"\x00\x0BTX Power\x00\x08\x09\x0A\xFF",
+            b"\x00\x0DBind\x00\x03\xC8Confirm?\x00",
+            b"\x00\x7F\x00",
+        ];
+
+        for data in entries {
+            assert_eq!(parse(data).unwrap().encode(), data);
+        }
+    }
+
+    #[test]
+    fn write() {
+        let client = client();
+        let cases = [
+            (Value::Uint8(3), &b"\x03"[..]),
+            (Value::Int16(-2), b"\xFF\xFE"),
+            (Value::Uint32(0x0102_0304), b"\x01\x02\x03\x04"),
+            (Value::Float(-100), b"\xFF\xFF\xFF\x9C"),
+            (Value::TextSelection(1), b"\x01"),
+            (Value::String(c("abc")), b"abc\x00"),
+            (Value::Command(CommandStatus::Start), b"\x01"),
+        ];
+
+        for (value, expected) in cases {
+            let Packet::ParameterWrite {
+                to,
+                from,
+                index,
+                value,
+            } = client.write(7, &value)
+            else {
+                panic!();
+            };
+
+            assert_eq!((to, from, index), (TX, Address::Handset, 7));
+            assert_eq!(value, expected);
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use core::convert::Infallible;
+
+use embedded_io_async::{Read, Write};
+
+use crate::{FrameDecoder, MAX_FRAME_BYTES, Packet, PacketError, WriteError};
+
+/// Whether the UART transmits and receives on the same wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Duplex {
+    /// Separate TX and RX lines
+    Full,
+    /// Single wire, so every transmitted byte is also received
+    Half,
+}
+
+/// Async frame reader & writer on top of a UART
+///
+/// [`read`](Self::read) is cancel safe as long as the UART's own `read` is, so
+/// it can be raced against timers or outgoing packets.
+#[derive(Debug)]
+pub struct Transport<U> {
+    uart: U,
+    duplex: Duplex,
+    decoder: FrameDecoder,
+    /// Bytes read from the UART but not yet passed to the decoder
+    rx: [u8; MAX_FRAME_BYTES],
+    rx_start: usize,
+    rx_end: usize,
+    /// Transmitted bytes that have not been echoed back yet
+    echo: Echo,
+}
+
+impl<U> Transport<U> {
+    pub fn new(uart: U, duplex: Duplex) -> Self {
+        Self {
+            uart,
+            duplex,
+            decoder: FrameDecoder::new(),
+            rx: [0; MAX_FRAME_BYTES],
+            rx_start: 0,
+            rx_end: 0,
+            echo: Echo::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> U {
+        self.uart
+    }
+
+    /// Statistics of the underlying decoder
+    pub fn decoder(&self) -> &FrameDecoder {
+        &self.decoder
+    }
+}
+
+impl<U: Read> Transport<U> {
+    /// Wait for the next valid frame and decode it
+    ///
+    /// Frames that fail the crc are skipped, but frames that pass and then fail
+    /// to decode are returned as errors so the caller can decide whether to
+    /// log them.
+    pub async fn read(&mut self) -> Result<Packet, PacketError<U::Error>> {
+        loop {
+            // A rescan after a bad crc may have uncovered more than one frame
+            if let Some(mut frame) = self.decoder.next_frame() {
+                return Packet::read(&mut frame).map_err(from_slice_error);
+            }
+
+            if self.rx_start == self.rx_end {
+                let len = self.uart.read(&mut self.rx).await;
+                let len = len.map_err(PacketError::ReadError)?;
+                if len == 0 {
+                    return Err(PacketError::UnexpectedEof);
+                }
+
+                self.rx_start = 0;
+                self.rx_end = len;
+            }
+
+            while self.rx_start < self.rx_end {
+                let byte = self.rx[self.rx_start];
+                self.rx_start += 1;
+
+                if self.echo.filter(byte) {
+                    continue;
+                }
+
+                if let Some(mut frame) = self.decoder.push(byte) {
+                    return Packet::read(&mut frame).map_err(from_slice_error);
+                }
+            }
+        }
+    }
+}
+
+impl<U: Write> Transport<U> {
+    /// Encode and send `packet`, waiting until the UART has flushed it
+    pub async fn write(&mut self, packet: &Packet) -> Result<(), WriteError<U::Error>> {
+        let mut buffer = [0; MAX_FRAME_BYTES];
+        let mut remaining = &mut buffer[..];
+        packet.write(&mut remaining).map_err(|err| match err {
+            // The buffer is exactly large enough for any valid frame
+            WriteError::Io(_) | WriteError::PayloadTooLong => WriteError::PayloadTooLong,
+            WriteError::Unsupported => WriteError::Unsupported,
+        })?;
+        let len = MAX_FRAME_BYTES - remaining.len();
+        let frame = &buffer[0..len];
+
+        if self.duplex == Duplex::Half {
+            self.echo.extend(frame);
+        }
+
+        self.uart.write_all(frame).await.map_err(WriteError::Io)?;
+        self.uart.flush().await.map_err(WriteError::Io)
+    }
+}
+
+fn from_slice_error<E>(err: PacketError<Infallible>) -> PacketError<E> {
+    match err {
+        PacketError::ReadError(err) => match err {},
+        PacketError::UnexpectedEof => PacketError::UnexpectedEof,
+        PacketError::InvalidSyncByte(byte) => PacketError::InvalidSyncByte(byte),
+        PacketError::BadCrc => PacketError::BadCrc,
+        PacketError::BadCommandCrc => PacketError::BadCommandCrc,
+        PacketError::MalformedPayload => PacketError::MalformedPayload,
+        PacketError::UnknownAddress(address) => PacketError::UnknownAddress(address),
+    }
+}
+
+/// Queue of bytes expected to be echoed back on a half-duplex line
+#[derive(Debug)]
+struct Echo {
+    buffer: [u8; Self::CAPACITY],
+    start: usize,
+    len: usize,
+}
+
+impl Echo {
+    /// Enough for a few frames written back to back without reading in between
+    const CAPACITY: usize = MAX_FRAME_BYTES * 4;
+
+    const fn new() -> Self {
+        Self {
+            buffer: [0; Self::CAPACITY],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// Queue bytes that were just written. If the queue is full, the oldest
+    /// bytes are forgotten.
+    fn extend(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if self.len == Self::CAPACITY {
+                self.start = (self.start + 1) % Self::CAPACITY;
+                self.len -= 1;
+            }
+
+            self.buffer[(self.start + self.len) % Self::CAPACITY] = byte;
+            self.len += 1;
+        }
+    }
+
+    /// Returns `true` if `byte` is the next expected echo byte. Any mismatch
+    /// means the echo was lost or corrupted, so the rest of it is dropped
+    /// rather than risk swallowing real data.
+    fn filter(&mut self, byte: u8) -> bool {
+        if self.len == 0 {
+            return false;
+        }
+
+        if self.buffer[self.start] == byte {
+            self.start = (self.start + 1) % Self::CAPACITY;
+            self.len -= 1;
+            true
+        } else {
+            self.len = 0;
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::VecDeque;
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::{Address, TxPower};
+
+    const PING: &[u8] = &[0xC8, 0x04, 0x28, 0x00, 0xEA, 0x54];
+    const LINK_STATISTICS: &[u8] = &[
+        0xC8, 0x0C, 0x14, 0x24, 0x00, 0x64, 0x0A, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x39,
+    ];
+
+    fn ping() -> Packet {
+        Packet::DevicePing {
+            to: Address::Broadcast,
+            from: Address::Handset,
+        }
+    }
+
+    /// In-memory UART that returns at most `chunk` bytes per read. In half
+    /// duplex mode, writes are looped back into the receive queue.
+    #[derive(Debug, Default)]
+    struct MockUart {
+        rx: VecDeque<u8>,
+        tx: Vec<u8>,
+        chunk: usize,
+        loopback: bool,
+    }
+
+    impl MockUart {
+        fn new(rx: &[u8], chunk: usize) -> Self {
+            Self {
+                rx: rx.iter().copied().collect(),
+                chunk,
+                ..Self::default()
+            }
+        }
+    }
+
+    impl embedded_io_async::ErrorType for MockUart {
+        type Error = Infallible;
+    }
+
+    impl Read for MockUart {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let len = buf.len().min(self.chunk).min(self.rx.len());
+            for (out, byte) in buf.iter_mut().zip(self.rx.drain(0..len)) {
+                *out = byte;
+            }
+            Ok(len)
+        }
+    }
+
+    impl Write for MockUart {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.tx.extend_from_slice(buf);
+            if self.loopback {
+                self.rx.extend(buf);
+            }
+            Ok(buf.len())
+        }
+    }
+
+    #[tokio::test]
+    async fn read_frames() {
+        for chunk in [1, 5, MAX_FRAME_BYTES] {
+            let input = [&[0x00, 0xFF][..], PING, LINK_STATISTICS].concat();
+            let mut transport = Transport::new(MockUart::new(&input, chunk), Duplex::Full);
+
+            assert_eq!(transport.read().await.unwrap(), ping());
+            let Packet::LinkStatistics { tx_power, .. } = transport.read().await.unwrap() else {
+                panic!();
+            };
+            assert_eq!(tx_power, TxPower::mW_0);
+
+            assert!(matches!(
+                transport.read().await,
+                Err(PacketError::UnexpectedEof)
+            ));
+            assert_eq!(transport.decoder().dropped_bytes(), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn buffered_frames_without_more_input() {
+        // A bogus header that swallows two complete frames and ends exactly
+        // where the input does
+        let input = [&[0xC8, 0x15, 0x00][..], PING, PING, &LINK_STATISTICS[0..8]].concat();
+        let mut transport = Transport::new(MockUart::new(&input, MAX_FRAME_BYTES), Duplex::Full);
+
+        assert_eq!(transport.read().await.unwrap(), ping());
+        assert_eq!(transport.read().await.unwrap(), ping());
+        assert!(matches!(
+            transport.read().await,
+            Err(PacketError::UnexpectedEof)
+        ));
+        assert_eq!(transport.decoder().crc_errors(), 1);
+    }
+
+    #[tokio::test]
+    async fn write_frames() {
+        let mut transport = Transport::new(MockUart::default(), Duplex::Full);
+        transport.write(&ping()).await.unwrap();
+        assert_eq!(transport.into_inner().tx, PING);
+    }
+
+    #[tokio::test]
+    async fn half_duplex_echo() {
+        let uart = MockUart {
+            loopback: true,
+            chunk: 3,
+            ..MockUart::default()
+        };
+        let mut transport = Transport::new(uart, Duplex::Half);
+
+        transport.write(&ping()).await.unwrap();
+        transport.write(&ping()).await.unwrap();
+        transport.uart.rx.extend(LINK_STATISTICS);
+
+        assert!(matches!(
+            transport.read().await,
+            Ok(Packet::LinkStatistics { .. })
+        ));
+        assert_eq!(transport.decoder().dropped_bytes(), 0);
+    }
+
+    #[tokio::test]
+    async fn corrupted_echo() {
+        let mut transport = Transport::new(MockUart::new(&[], 1), Duplex::Half);
+        transport.write(&ping()).await.unwrap();
+
+        // The echo is cut short, and a real frame follows immediately
+        transport.uart.rx.extend(&PING[0..2]);
+        transport.uart.rx.extend(LINK_STATISTICS);
+
+        assert!(matches!(
+            transport.read().await,
+            Ok(Packet::LinkStatistics { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn full_duplex_does_not_filter() {
+        let uart = MockUart {
+            loopback: true,
+            chunk: MAX_FRAME_BYTES,
+            ..MockUart::default()
+        };
+        let mut transport = Transport::new(uart, Duplex::Full);
+
+        transport.write(&ping()).await.unwrap();
+        assert_eq!(transport.read().await.unwrap(), ping());
+    }
+}
@@ -0,0 +1,264 @@
+//! Inspect and prepare VerTX filesystem images from a PC
+//!
+//! ```sh
+//! cargo run -p vertx-filesystem --example image -- <image> <command> [args...]
+//! ```
+//!
+//! `<image>` can be either a raw image file or an SD card's block device.
+
+use std::io::{self, Read as _, Seek as _, SeekFrom, Write as _};
+use std::process::ExitCode;
+use std::{fmt, fs};
+
+use aligned::{A1, Aligned};
+use block_device_driver::BlockDevice;
+use embedded_io_async::{Read as _, Write as _};
+use vertx_filesystem::{BLOCK_BYTES, Buffers, Filesystem, InitError, MAX_CONFIG_BYTES};
+
+const USAGE: &str = "\
+usage: image <image> <command> [args...]
+
+commands:
+  format [MiB]                 create an empty filesystem, resizing the image
+                               file if a size is given (required if it is
+                               empty)
+  check                        validate both header copies
+  fsck                         rebuild the header from the models that survive
+  migrate                      upgrade a filesystem from an older format
+  list                         list models, in order
+  config-dump <file>           save the config to <file>
+  config-import <file>         replace the config with <file>
+  model-dump <id> <file>       save the data of model <id> to <file>
+  model-import <name> <file>   add a model named <name> holding <file>";
+
+/// A raw image file or block device
+#[derive(Debug)]
+struct Image {
+    file: fs::File,
+    /// Writes fail rather than change the image
+    read_only: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    ReadOnly,
+    ReadWrite,
+    Create,
+}
+
+impl Image {
+    fn open(path: &str, mode: Mode) -> Result<Self, String> {
+        let read_only = mode == Mode::ReadOnly;
+        fs::OpenOptions::new()
+            .read(true)
+            .write(!read_only)
+            .create(mode == Mode::Create)
+            .truncate(false)
+            .open(path)
+            .map(|file| Self { file, read_only })
+            .map_err(|err| format!("failed to open {path}: {err}"))
+    }
+
+    fn seek_to(&mut self, block: u32) -> io::Result<()> {
+        let offset = u64::from(block) * BLOCK_BYTES as u64;
+        self.file.seek(SeekFrom::Start(offset))?;
+        Ok(())
+    }
+}
+
+impl BlockDevice<BLOCK_BYTES> for Image {
+    type Align = A1;
+    type Error = io::Error;
+
+    async fn read(
+        &mut self,
+        block_address: u32,
+        data: &mut [Aligned<A1, [u8; BLOCK_BYTES]>],
+    ) -> Result<(), Self::Error> {
+        self.seek_to(block_address)?;
+        for block in data {
+            self.file.read_exact(&mut block[..])?;
+        }
+        Ok(())
+    }
+
+    async fn write(
+        &mut self,
+        block_address: u32,
+        data: &[Aligned<A1, [u8; BLOCK_BYTES]>],
+    ) -> Result<(), Self::Error> {
+        if self.read_only {
+            return Err(io::ErrorKind::ReadOnlyFilesystem.into());
+        }
+
+        self.seek_to(block_address)?;
+        for block in data {
+            self.file.write_all(&block[..])?;
+        }
+        Ok(())
+    }
+
+    async fn size(&mut self) -> Result<u64, Self::Error> {
+        // The metadata of a block device has a length of 0, so seek instead
+        self.file.seek(SeekFrom::End(0))
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match run(&args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(args: &[&str]) -> Result<(), String> {
+    let [path, command, args @ ..] = args else {
+        return Err(USAGE.into());
+    };
+
+    let mut buffers = Buffers::new();
+    match (*command, args) {
+        ("format", []) => format(path, None, &mut buffers).await,
+        ("format", [size]) => {
+            let size = size.parse().map_err(|_| format!("invalid size: {size}"))?;
+            format(path, Some(size), &mut buffers).await
+        }
+        ("check", []) => check(path, &mut buffers).await,
+        ("fsck", []) => {
+            let (_, report) = Filesystem::fsck(Image::open(path, Mode::ReadWrite)?, &mut buffers)
+                .await
+                .map_err(debug)?;
+            println!(
+                "recovered {} models, dropped {}: {report:?}",
+                report.recovered,
+                report.dropped()
+            );
+            Ok(())
+        }
+        ("migrate", []) => {
+            mount(path, Mode::ReadWrite, &mut buffers).await?;
+            Ok(())
+        }
+        ("list", []) => {
+            let mut fs = mount(path, Mode::ReadOnly, &mut buffers).await?;
+            fs.model_names(|id, name| println!("{id:>2}  {name}"))
+                .await
+                .map_err(debug)
+        }
+        ("config-dump", [file]) => {
+            let mut fs = mount(path, Mode::ReadOnly, &mut buffers).await?;
+            let mut buf = [0; MAX_CONFIG_BYTES];
+            let config = fs.read_config(&mut buf).await.map_err(debug)?;
+            write_file(file, config)
+        }
+        ("config-import", [file]) => {
+            let config = read_file(file)?;
+            if config.len() > MAX_CONFIG_BYTES {
+                return Err(format!(
+                    "config is {} bytes, but the limit is {MAX_CONFIG_BYTES}",
+                    config.len()
+                ));
+            }
+
+            let mut fs = mount(path, Mode::ReadWrite, &mut buffers).await?;
+            fs.write_config(&config).await.map_err(debug)
+        }
+        ("model-dump", [id, file]) => {
+            let id = id.parse().map_err(|_| format!("invalid model id: {id}"))?;
+            let mut fs = mount(path, Mode::ReadOnly, &mut buffers).await?;
+            let Some(mut model) = fs.model(id).await.map_err(debug)? else {
+                return Err(format!("there is no model {id}"));
+            };
+
+            let mut data = vec![0; model.len() as usize];
+            model.read_exact(&mut data).await.map_err(debug)?;
+            model.close().await.map_err(debug)?;
+            write_file(file, &data)
+        }
+        ("model-import", [name, file]) => {
+            let data = read_file(file)?;
+            let mut fs = mount(path, Mode::ReadWrite, &mut buffers).await?;
+            let mut model = fs.new_model(name).await.map_err(debug)?;
+            model.write_all(&data).await.map_err(debug)?;
+            model.close().await.map_err(debug)
+        }
+        _ => Err(USAGE.into()),
+    }
+}
+
+async fn format(path: &str, mib: Option<u64>, buffers: &mut Buffers<A1>) -> Result<(), String> {
+    let mut image = Image::open(path, Mode::Create)?;
+    match mib {
+        Some(0) => return Err("the size must be at least 1 MiB".into()),
+        Some(mib) => image
+            .file
+            .set_len(mib << 20)
+            .map_err(|err| format!("failed to resize {path}: {err}"))?,
+        None => {
+            if image.size().await.map_err(debug)? == 0 {
+                return Err(format!("{path} is empty; give a size in MiB"));
+            }
+        }
+    }
+
+    Filesystem::new_empty(image, buffers).await.map_err(debug)?;
+    Ok(())
+}
+
+async fn check(path: &str, buffers: &mut Buffers<A1>) -> Result<(), String> {
+    let mut image = Image::open(path, Mode::ReadOnly)?;
+    let copies = Filesystem::check_headers(&mut image, buffers)
+        .await
+        .map_err(debug)?;
+
+    for (copy, result) in ["first", "second"].iter().zip(&copies) {
+        match result {
+            Ok(generation) => println!("{copy} header copy is valid (generation {generation})"),
+            Err(err) => println!("{copy} header copy is invalid: {err:?}"),
+        }
+    }
+
+    if copies.iter().all(Result::is_err) {
+        return Err("no intact header; `fsck` may be able to salvage the models".into());
+    }
+
+    Ok(())
+}
+
+/// Mount the filesystem in `path`. In [`Mode::ReadOnly`], this fails rather
+/// than upgrade a filesystem from an older format.
+async fn mount<'buf>(
+    path: &str,
+    mode: Mode,
+    buffers: &'buf mut Buffers<A1>,
+) -> Result<Filesystem<'buf, Image>, String> {
+    match Filesystem::new(Image::open(path, mode)?, buffers).await {
+        Ok(fs) => Ok(fs),
+        Err(InitError::HeaderError { kind, .. }) => Err(format!(
+            "filesystem header is invalid ({kind:?}); see `check` and `fsck`"
+        )),
+        Err(InitError::Io(err)) if err.kind() == io::ErrorKind::ReadOnlyFilesystem => Err(format!(
+            "{path} is in an older format, which this command will not change; run `migrate` first"
+        )),
+        Err(InitError::Io(err)) => Err(format!("failed to read {path}: {err}")),
+    }
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|err| format!("failed to read {path}: {err}"))
+}
+
+fn write_file(path: &str, data: &[u8]) -> Result<(), String> {
+    fs::write(path, data).map_err(|err| format!("failed to write {path}: {err}"))
+}
+
+fn debug(err: impl fmt::Debug) -> String {
+    format!("{err:?}")
+}
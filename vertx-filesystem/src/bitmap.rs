@@ -0,0 +1,374 @@
+//! Allocation bitmap, kept in dedicated blocks at the end of the filesystem
+//!
+//! Bit `n` is set if block `n` is in use. Only the blocks from [`DATA_START`]
+//! up to the bitmap itself are ever allocated, so the bits for the rest are
+//! ignored.
+
+use aligned::Alignment;
+use block_device_driver::BlockDevice;
+
+use crate::{BLOCK_BYTES, Block, DATA_START};
+
+/// Number of blocks tracked by each bitmap block
+const BLOCK_BITS: u32 = BLOCK_BYTES as u32 * 8;
+
+/// End of the blocks available to files in a filesystem `blocks` long, which is
+/// where the bitmap starts
+pub(crate) fn data_end(blocks: u32) -> u32 {
+    blocks
+        .saturating_sub(blocks.div_ceil(BLOCK_BITS))
+        .max(DATA_START)
+}
+
+/// The bitmap of a mounted filesystem, one block of which is cached at a time
+pub(crate) struct Bitmap<'buf, A> {
+    cache: &'buf mut Block<A>,
+    /// Device block held in `cache`
+    cached: Option<u32>,
+    modified: bool,
+    /// First block of the bitmap
+    start: u32,
+    /// End of the filesystem, and so of the bitmap
+    end: u32,
+    /// Where to start looking for a free block
+    next: u32,
+}
+
+impl<'buf, A: Alignment> Bitmap<'buf, A> {
+    pub(crate) fn new(cache: &'buf mut Block<A>, blocks: u32) -> Self {
+        let start = data_end(blocks);
+        Self {
+            cache,
+            cached: None,
+            modified: false,
+            start,
+            end: blocks.max(start),
+            next: DATA_START,
+        }
+    }
+}
+
+impl<A: Alignment> Bitmap<'_, A> {
+    /// End of the blocks available to files
+    pub(crate) fn data_end(&self) -> u32 {
+        self.start
+    }
+
+    fn is_allocatable(&self, block: u32) -> bool {
+        (DATA_START..self.start).contains(&block)
+    }
+
+    /// Bitmap block, word, and bit of `block`
+    fn locate(&self, block: u32) -> (u32, usize, u32) {
+        let bit = block % BLOCK_BITS;
+        (
+            self.start + block / BLOCK_BITS,
+            (bit / u32::BITS) as usize,
+            bit % u32::BITS,
+        )
+    }
+
+    async fn load<D: BlockDevice<BLOCK_BYTES, Align = A>>(
+        &mut self,
+        device: &mut D,
+        block: u32,
+    ) -> Result<(), D::Error> {
+        if self.cached != Some(block) {
+            self.flush(device).await?;
+            self.cached = None;
+            device.read(block, self.cache.as_aligned_mut()).await?;
+            self.cached = Some(block);
+        }
+
+        Ok(())
+    }
+
+    /// Mark every block as free, writing the whole bitmap
+    pub(crate) async fn clear<D: BlockDevice<BLOCK_BYTES, Align = A>>(
+        &mut self,
+        device: &mut D,
+    ) -> Result<(), D::Error> {
+        self.cached = None;
+        self.modified = false;
+        self.next = DATA_START;
+
+        *self.cache = Block::new();
+        for block in self.start..self.end {
+            device.write(block, self.cache.as_aligned()).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Blocks outside of the allocatable range always count as allocated
+    pub(crate) async fn is_allocated<D: BlockDevice<BLOCK_BYTES, Align = A>>(
+        &mut self,
+        device: &mut D,
+        block: u32,
+    ) -> Result<bool, D::Error> {
+        if !self.is_allocatable(block) {
+            return Ok(true);
+        }
+
+        let (index, word, bit) = self.locate(block);
+        self.load(device, index).await?;
+        Ok(u32::from_le(self.cache.as_words()[word]) & (1 << bit) != 0)
+    }
+
+    async fn set<D: BlockDevice<BLOCK_BYTES, Align = A>>(
+        &mut self,
+        device: &mut D,
+        block: u32,
+        allocated: bool,
+    ) -> Result<(), D::Error> {
+        let (index, word, bit) = self.locate(block);
+        self.load(device, index).await?;
+
+        let word = &mut self.cache.as_words_mut()[word];
+        let bits = u32::from_le(*word);
+        let bits = if allocated {
+            bits | (1 << bit)
+        } else {
+            bits & !(1 << bit)
+        };
+        *word = bits.to_le();
+        self.modified = true;
+        Ok(())
+    }
+
+    /// Allocate `block` if it is free
+    pub(crate) async fn allocate_at<D: BlockDevice<BLOCK_BYTES, Align = A>>(
+        &mut self,
+        device: &mut D,
+        block: u32,
+    ) -> Result<bool, D::Error> {
+        if self.is_allocated(device, block).await? {
+            return Ok(false);
+        }
+
+        self.set(device, block, true).await?;
+        Ok(true)
+    }
+
+    /// Allocate the first free block after the previous allocation, wrapping
+    /// around to the start if needed
+    pub(crate) async fn allocate<D: BlockDevice<BLOCK_BYTES, Align = A>>(
+        &mut self,
+        device: &mut D,
+    ) -> Result<Option<u32>, D::Error> {
+        let next = self.next.clamp(DATA_START, self.start);
+        let mut block = self.find_free(device, next, self.start).await?;
+        if block.is_none() {
+            block = self.find_free(device, DATA_START, next).await?;
+        }
+
+        if let Some(block) = block {
+            self.set(device, block, true).await?;
+            self.next = block + 1;
+        }
+        Ok(block)
+    }
+
+    /// Allocate the last run of `len` free blocks, returning its start.
+    /// Searches back from the end, which [`Bitmap::allocate`] leaves free the
+    /// longest.
+    pub(crate) async fn allocate_run<D: BlockDevice<BLOCK_BYTES, Align = A>>(
+        &mut self,
+        device: &mut D,
+        len: u32,
+    ) -> Result<Option<u32>, D::Error> {
+        let mut end = self.start;
+        'search: while end >= DATA_START + len {
+            let start = end - len;
+            for block in (start..end).rev() {
+                if self.is_allocated(device, block).await? {
+                    end = block;
+                    continue 'search;
+                }
+            }
+
+            for block in start..end {
+                self.set(device, block, true).await?;
+            }
+            return Ok(Some(start));
+        }
+
+        Ok(None)
+    }
+
+    /// First free block in `from..to`
+    async fn find_free<D: BlockDevice<BLOCK_BYTES, Align = A>>(
+        &mut self,
+        device: &mut D,
+        from: u32,
+        to: u32,
+    ) -> Result<Option<u32>, D::Error> {
+        let mut block = from;
+        while block < to {
+            let (index, word, bit) = self.locate(block);
+            self.load(device, index).await?;
+
+            // Skip the blocks in this word before `block`
+            let bits = u32::from_le(self.cache.as_words()[word]) | ((1 << bit) - 1);
+            let base = block - bit;
+            if bits != u32::MAX {
+                let free = base + bits.trailing_ones();
+                return Ok((free < to).then_some(free));
+            }
+
+            block = base + u32::BITS;
+        }
+
+        Ok(None)
+    }
+
+    pub(crate) async fn free<D: BlockDevice<BLOCK_BYTES, Align = A>>(
+        &mut self,
+        device: &mut D,
+        start: u32,
+        len: u32,
+    ) -> Result<(), D::Error> {
+        loog::trace!("freeing blocks {start=u32}..{=u32}", start + len);
+
+        for block in start..(start + len) {
+            if self.is_allocatable(block) {
+                self.set(device, block, false).await?;
+            } else {
+                loog::warn!("cannot free unallocatable block {block=u32}");
+            }
+        }
+
+        self.next = self.next.min(start);
+        Ok(())
+    }
+
+    /// Write back the cached bitmap block if it has changed
+    pub(crate) async fn flush<D: BlockDevice<BLOCK_BYTES, Align = A>>(
+        &mut self,
+        device: &mut D,
+    ) -> Result<(), D::Error> {
+        if self.modified
+            && let Some(block) = self.cached
+        {
+            device.write(block, self.cache.as_aligned()).await?;
+            self.modified = false;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aligned::A1;
+
+    use super::*;
+    use crate::Mock;
+
+    #[test]
+    fn layout() {
+        assert_eq!(data_end(0), DATA_START);
+        assert_eq!(data_end(DATA_START + 2), DATA_START + 1);
+        assert_eq!(data_end(BLOCK_BITS), BLOCK_BITS - 1);
+        assert_eq!(data_end(BLOCK_BITS + 1), BLOCK_BITS - 1);
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn allocate() {
+        let mut mock = Mock::<64>::new();
+        let mut cache = Block::<A1>::new();
+        let mut bitmap = Bitmap::new(&mut cache, 64);
+        bitmap.clear(&mut mock).await.unwrap();
+        assert_eq!(bitmap.data_end(), 63);
+
+        assert!(bitmap.allocate_at(&mut mock, DATA_START + 1).await.unwrap());
+        assert!(!bitmap.allocate_at(&mut mock, DATA_START + 1).await.unwrap());
+        assert!(!bitmap.allocate_at(&mut mock, 63).await.unwrap());
+        assert!(!bitmap.allocate_at(&mut mock, DATA_START - 1).await.unwrap());
+
+        assert_eq!(bitmap.allocate(&mut mock).await.unwrap(), Some(DATA_START));
+        assert_eq!(
+            bitmap.allocate(&mut mock).await.unwrap(),
+            Some(DATA_START + 2)
+        );
+        assert!(
+            bitmap
+                .allocate_at(&mut mock, DATA_START + 33)
+                .await
+                .unwrap()
+        );
+
+        bitmap.free(&mut mock, DATA_START, 2).await.unwrap();
+        assert!(!bitmap.is_allocated(&mut mock, DATA_START).await.unwrap());
+        assert!(
+            !bitmap
+                .is_allocated(&mut mock, DATA_START + 1)
+                .await
+                .unwrap()
+        );
+        assert!(
+            bitmap
+                .is_allocated(&mut mock, DATA_START + 2)
+                .await
+                .unwrap()
+        );
+        assert_eq!(bitmap.allocate(&mut mock).await.unwrap(), Some(DATA_START));
+
+        bitmap.flush(&mut mock).await.unwrap();
+        assert_eq!(
+            &mock.blocks()[63][..8],
+            &[0b0100_0000, 0b0000_0001, 0, 0, 0b1000_0000, 0, 0, 0]
+        );
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn allocate_full() {
+        let mut mock = Mock::<{ DATA_START as usize + 4 }>::new();
+        let mut cache = Block::<A1>::new();
+        let mut bitmap = Bitmap::new(&mut cache, DATA_START + 4);
+        bitmap.clear(&mut mock).await.unwrap();
+
+        for _ in 0..3 {
+            assert!(bitmap.allocate(&mut mock).await.unwrap().is_some());
+        }
+        assert_eq!(bitmap.allocate(&mut mock).await.unwrap(), None);
+
+        // Wraps around to find blocks freed behind the last allocation
+        bitmap.free(&mut mock, DATA_START + 1, 1).await.unwrap();
+        bitmap.next = DATA_START + 3;
+        assert_eq!(
+            bitmap.allocate(&mut mock).await.unwrap(),
+            Some(DATA_START + 1)
+        );
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn allocate_run() {
+        let mut mock = Mock::<{ DATA_START as usize + 21 }>::new();
+        let mut cache = Block::<A1>::new();
+        let mut bitmap = Bitmap::new(&mut cache, DATA_START + 21);
+        bitmap.clear(&mut mock).await.unwrap();
+        assert_eq!(bitmap.data_end(), DATA_START + 20);
+
+        for block in [DATA_START + 17, DATA_START + 10] {
+            assert!(bitmap.allocate_at(&mut mock, block).await.unwrap());
+        }
+
+        assert_eq!(
+            bitmap.allocate_run(&mut mock, 6).await.unwrap(),
+            Some(DATA_START + 11)
+        );
+        for block in DATA_START + 11..DATA_START + 17 {
+            assert!(bitmap.is_allocated(&mut mock, block).await.unwrap());
+        }
+        assert_eq!(
+            bitmap.allocate_run(&mut mock, 6).await.unwrap(),
+            Some(DATA_START + 4)
+        );
+        assert_eq!(bitmap.allocate_run(&mut mock, 6).await.unwrap(), None);
+        assert_eq!(bitmap.allocate(&mut mock).await.unwrap(), Some(DATA_START));
+    }
+}
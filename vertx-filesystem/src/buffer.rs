@@ -35,7 +35,7 @@ impl State {
 
     fn is_block(&self, block: u32) -> bool {
         debug_assert!(block & Self::MODIFIED_MASK == 0);
-        (self.0 & Self::MODIFIED_MASK) == block
+        !self.is_empty() && (self.0 & Self::BLOCK_MASK) == block
     }
 
     fn is_modified(&self) -> bool {
@@ -60,11 +60,6 @@ impl<'a, A, const LEN: usize> Buffer<'a, A, LEN> {
             state: [State::EMPTY; LEN],
         }
     }
-
-    /// Length in blocks
-    pub(crate) const fn len(&self) -> u32 {
-        LEN as u32
-    }
 }
 
 impl<A: Alignment, const LEN: usize> Buffer<'_, A, LEN> {
@@ -124,6 +119,32 @@ impl<A: Alignment, const LEN: usize> Buffer<'_, A, LEN> {
         View::new(device, self, start, index, index + len as usize).await
     }
 
+    /// Copy `len` blocks from `from` to `to`, returning a view of the copy so
+    /// it can be modified before it is written
+    pub(crate) async fn copy<'a, D: BlockDevice<BLOCK_BYTES, Align = A>>(
+        &'a mut self,
+        device: &'a mut D,
+        from: u32,
+        to: u32,
+        len: u32,
+    ) -> Result<View<'a, A, D>, D::Error> {
+        loog::trace!("copying {len=u32} blocks from {from=u32} to {to=u32}");
+
+        // Otherwise pending changes to `from` would only end up in `to`
+        self.flush(device).await?;
+        for state in &mut self.state {
+            if (to..(to + len)).any(|block| state.is_block(block)) {
+                state.set_empty();
+            }
+        }
+
+        let mut view = self.select_exact(device, from, len).await?;
+        view.read().await?;
+        view.start = to;
+        view.mark_modified(0, len as usize * BLOCK_BYTES);
+        Ok(view)
+    }
+
     pub(crate) async fn flush<D: BlockDevice<BLOCK_BYTES, Align = A>>(
         &mut self,
         dev: &mut D,
@@ -149,7 +170,7 @@ impl<A: Alignment, const LEN: usize> Buffer<'_, A, LEN> {
 
             // Find the chunk of modified buffers backed by a contiguous range of blocks
             let mut chunk: usize = 1;
-            while let state = &self.state[start + chunk]
+            while let Some(state) = self.state.get(start + chunk)
                 && state.is_modified()
                 && state.is_block(start_block + chunk as u32)
             {
@@ -210,8 +231,10 @@ impl<'a, A: Alignment, D: BlockDevice<BLOCK_BYTES, Align = A>> View<'a, A, D> {
             if let Some(block) = state.block()
                 && block != expected_block
             {
-                // TODO: chunk writes
-                device.write(block, buffers[i].as_aligned()).await?;
+                if state.is_modified() {
+                    // TODO: chunk writes
+                    device.write(block, buffers[i].as_aligned()).await?;
+                }
                 state.set_empty();
             }
         }
@@ -0,0 +1,148 @@
+use bytemuck::{Pod, Zeroable};
+
+use crate::BLOCK_BYTES;
+use crate::header::CRC;
+
+/// Bytes at the start of each config copy before the config itself
+pub(crate) const PREAMBLE_BYTES: usize = 12;
+/// Blocks in each of the two config copies
+pub(crate) const COPY_BLOCKS: u32 = 4;
+/// Blocks in the region holding both copies
+pub(crate) const REGION_BLOCKS: u32 = 2 * COPY_BLOCKS;
+/// Largest config that fits alongside its preamble
+pub const MAX_CONFIG_BYTES: usize = COPY_BLOCKS as usize * BLOCK_BYTES - PREAMBLE_BYTES;
+/// Largest config that fits in the single block copies at [`CONFIG_BLOCKS`],
+/// which are used until the config has a region of its own
+///
+/// [`CONFIG_BLOCKS`]: crate::CONFIG_BLOCKS
+pub(crate) const LEGACY_MAX_BYTES: usize = BLOCK_BYTES - PREAMBLE_BYTES;
+
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+pub(crate) struct Preamble {
+    len: u32,
+    /// Incremented on every write; the valid copy with the highest generation
+    /// is current
+    generation: u32,
+    /// Covers the generation and config
+    checksum: u32,
+}
+
+const _: () = assert!(size_of::<Preamble>() == PREAMBLE_BYTES);
+
+/// Checks a config against its preamble, one chunk at a time
+pub(crate) struct Checksum {
+    digest: crc::Digest<'static, u32>,
+    expected: u32,
+}
+
+impl Preamble {
+    pub(crate) fn new(generation: u32, config: &[u8]) -> Self {
+        let generation = generation.to_le();
+        let mut digest = digest(generation);
+        digest.update(config);
+
+        Self {
+            len: (config.len() as u32).to_le(),
+            generation,
+            checksum: digest.finalize().to_le(),
+        }
+    }
+
+    /// Read the preamble from the start of the first block of a copy
+    pub(crate) fn read(block: &[u8]) -> Self {
+        bytemuck::pod_read_unaligned(&block[..PREAMBLE_BYTES])
+    }
+
+    /// Write the preamble to the start of the first block of a copy
+    pub(crate) fn write(&self, block: &mut [u8]) {
+        block[..PREAMBLE_BYTES].copy_from_slice(bytemuck::bytes_of(self));
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        u32::from_le(self.len) as usize
+    }
+
+    pub(crate) fn generation(&self) -> u32 {
+        u32::from_le(self.generation)
+    }
+
+    pub(crate) fn checksum(&self) -> Checksum {
+        Checksum {
+            digest: digest(self.generation),
+            expected: u32::from_le(self.checksum),
+        }
+    }
+}
+
+impl Checksum {
+    pub(crate) fn update(&mut self, chunk: &[u8]) {
+        self.digest.update(chunk);
+    }
+
+    /// Whether the config passed to [`Checksum::update`] matches the preamble
+    pub(crate) fn matches(self) -> bool {
+        self.digest.finalize() == self.expected
+    }
+}
+
+/// `generation` must already be little endian
+fn digest(generation: u32) -> crc::Digest<'static, u32> {
+    let mut crc = CRC.digest();
+    crc.update(bytemuck::bytes_of(&generation));
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse a copy that fits in `block`
+    fn parse(block: &[u8]) -> Option<(u32, &[u8])> {
+        let preamble = Preamble::read(block);
+        let config = block[PREAMBLE_BYTES..].get(..preamble.len())?;
+        let mut checksum = preamble.checksum();
+        checksum.update(config);
+        checksum
+            .matches()
+            .then_some((preamble.generation(), config))
+    }
+
+    fn write(block: &mut [u8], generation: u32, config: &[u8]) {
+        Preamble::new(generation, config).write(block);
+        block[PREAMBLE_BYTES..][..config.len()].copy_from_slice(config);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut block = [0; BLOCK_BYTES];
+        write(&mut block, 7, b"config");
+        assert_eq!(parse(&block), Some((7, &b"config"[..])));
+    }
+
+    #[test]
+    fn chunked() {
+        let config = b"split across chunks";
+        let preamble = Preamble::new(3, config);
+        let mut checksum = preamble.checksum();
+        for chunk in config.chunks(4) {
+            checksum.update(chunk);
+        }
+        assert!(checksum.matches());
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(parse(&[0; BLOCK_BYTES]), None);
+
+        let mut block = [0; BLOCK_BYTES];
+        write(&mut block, 7, b"config");
+        block[PREAMBLE_BYTES] ^= 1;
+        assert_eq!(parse(&block), None);
+
+        let mut block = [0; BLOCK_BYTES];
+        write(&mut block, 7, b"config");
+        block[0] = 0xFF;
+        assert_eq!(parse(&block), None);
+    }
+}
@@ -0,0 +1,76 @@
+use crate::BLOCK_BYTES;
+use crate::config::PREAMBLE_BYTES;
+
+/// Blocks in each of the two directory copies
+pub(crate) const COPY_BLOCKS: u32 = 2;
+/// Blocks in the region holding both copies
+pub(crate) const REGION_BLOCKS: u32 = 2 * COPY_BLOCKS;
+pub const FILE_NAME_BYTES: usize = 28;
+/// Each entry is the first block of the file, followed by its name padded with
+/// zeros
+pub(crate) const ENTRY_BYTES: usize = 4 + FILE_NAME_BYTES;
+/// As many files as fit in a directory copy alongside its preamble
+pub const MAX_FILES: usize = (COPY_BLOCKS as usize * BLOCK_BYTES - PREAMBLE_BYTES) / ENTRY_BYTES;
+/// Largest directory, not counting the preamble
+pub(crate) const MAX_BYTES: usize = MAX_FILES * ENTRY_BYTES;
+
+/// First block and name of each file in `entries`
+pub(crate) fn iter(entries: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+    entries.chunks_exact(ENTRY_BYTES).map(|entry| {
+        let (start, name) = entry.split_at(4);
+        let start = u32::from_le(bytemuck::pod_read_unaligned(start));
+        let len = name
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(FILE_NAME_BYTES);
+        (start, &name[..len])
+    })
+}
+
+/// Index and first block of the file called `name`
+pub(crate) fn find(entries: &[u8], name: &[u8]) -> Option<(usize, u32)> {
+    iter(entries)
+        .enumerate()
+        .find(|(_, (_, entry))| *entry == name)
+        .map(|(index, (start, _))| (index, start))
+}
+
+/// Append an entry after the first `len` bytes of `entries` (which must have
+/// room for it), returning the new length
+pub(crate) fn push(entries: &mut [u8], len: usize, start: u32, name: &[u8]) -> usize {
+    let (entry_start, entry_name) = entries[len..(len + ENTRY_BYTES)].split_at_mut(4);
+    entry_start.copy_from_slice(bytemuck::bytes_of(&start.to_le()));
+    entry_name[..name.len()].copy_from_slice(name);
+    entry_name[name.len()..].fill(0);
+    len + ENTRY_BYTES
+}
+
+/// Remove entry `index` from the first `len` bytes of `entries`, returning the
+/// new length
+pub(crate) fn remove(entries: &mut [u8], len: usize, index: usize) -> usize {
+    let offset = index * ENTRY_BYTES;
+    entries.copy_within((offset + ENTRY_BYTES)..len, offset);
+    len - ENTRY_BYTES
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit() {
+        let mut entries = [0xFF; MAX_BYTES];
+        let mut len = 0;
+        for (start, name) in [(10, &b"log"[..]), (20, b"capture.bin"), (30, &[b'x'; 28])] {
+            len = push(&mut entries, len, start, name);
+        }
+
+        assert_eq!(find(&entries[..len], b"capture.bin"), Some((1, 20)));
+        assert_eq!(find(&entries[..len], &[b'x'; 28]), Some((2, 30)));
+        assert_eq!(find(&entries[..len], b"capture"), None);
+
+        len = remove(&mut entries, len, 0);
+        let files: std::vec::Vec<_> = iter(&entries[..len]).collect();
+        assert_eq!(files, [(20, &b"capture.bin"[..]), (30, &[b'x'; 28])]);
+    }
+}
@@ -0,0 +1,210 @@
+use bytemuck::{Pod, Zeroable};
+
+use crate::LEN_BYTES;
+
+/// Maximum number of extents a single file can be split across
+pub(crate) const MAX_EXTENTS: usize = 15;
+/// Bytes at the start of each file's first block holding its length and
+/// extents
+pub(crate) const PREAMBLE_BYTES: usize = LEN_BYTES + MAX_EXTENTS * 4;
+
+bitfield::bitfield! {
+    /// A contiguous run of blocks belonging to a file
+    #[derive(Clone, Copy, PartialEq, Pod, Zeroable)]
+    #[repr(transparent)]
+    pub(crate) struct Extent(u32);
+    impl Debug;
+    pub(crate) start, set_start: 19, 0;
+    pub(crate) len, set_len: 31, 20;
+}
+
+impl Extent {
+    const EMPTY: Self = Self(0);
+    const MAX_LEN: u32 = (1 << 12) - 1;
+
+    pub(crate) fn new(start: u32, len: u32) -> Self {
+        let mut extent = Self::EMPTY;
+        extent.set_start(start);
+        extent.set_len(len);
+        extent
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn end(&self) -> u32 {
+        self.start() + self.len()
+    }
+}
+
+/// The blocks backing a file, in order
+///
+/// The first extent always starts with the block holding the file preamble.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Extents([Extent; MAX_EXTENTS]);
+
+impl Extents {
+    /// A single block at `start`
+    pub(crate) fn new(start: u32) -> Self {
+        let mut extents = [Extent::EMPTY; MAX_EXTENTS];
+        extents[0] = Extent::new(start, 1);
+        Self(extents)
+    }
+
+    /// Parse the extents from a preamble, not including the length
+    pub(crate) fn from_words(words: &[u32]) -> Self {
+        let mut extents = [Extent::EMPTY; MAX_EXTENTS];
+        for (extent, word) in extents.iter_mut().zip(words) {
+            *extent = Extent(u32::from_le(*word));
+        }
+        Self(extents)
+    }
+
+    pub(crate) fn write_words(&self, words: &mut [u32]) {
+        for (word, extent) in words.iter_mut().zip(&self.0) {
+            *word = extent.0.to_le();
+        }
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Extent> {
+        self.0.iter().take_while(|extent| !extent.is_empty())
+    }
+
+    /// Every block, in order
+    pub(crate) fn iter_blocks(&self) -> impl Iterator<Item = u32> {
+        self.iter().flat_map(|extent| extent.start()..extent.end())
+    }
+
+    pub(crate) fn start(&self) -> u32 {
+        self.0[0].start()
+    }
+
+    /// Total number of blocks
+    pub(crate) fn blocks(&self) -> u32 {
+        self.iter().map(Extent::len).sum()
+    }
+
+    /// Find the device block backing block `index` of the file
+    pub(crate) fn block(&self, mut index: u32) -> Option<u32> {
+        for extent in self.iter() {
+            if index < extent.len() {
+                return Some(extent.start() + index);
+            }
+            index -= extent.len();
+        }
+
+        None
+    }
+
+    /// The block that would extend the final extent, if it has room to grow
+    pub(crate) fn next_contiguous(&self) -> Option<u32> {
+        let last = self.iter().last()?;
+        (last.len() < Extent::MAX_LEN).then(|| last.end())
+    }
+
+    /// Append `block`, which must either be
+    /// [`next_contiguous`](Self::next_contiguous) or go in a new extent.
+    /// Returns `false` if a new extent is needed but there is no room left.
+    pub(crate) fn push(&mut self, block: u32) -> bool {
+        let count = self.iter().count();
+        let last = &mut self.0[count - 1];
+        if last.end() == block && last.len() < Extent::MAX_LEN {
+            last.set_len(last.len() + 1);
+            true
+        } else if let Some(next) = self.0.get_mut(count) {
+            *next = Extent::new(block, 1);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        !self.0[MAX_EXTENTS - 1].is_empty()
+    }
+
+    /// Shrink to `blocks` blocks (minimum 1)
+    pub(crate) fn truncate(&mut self, blocks: u32) {
+        let mut keep = blocks.max(1);
+        for extent in &mut self.0 {
+            if keep >= extent.len() {
+                keep -= extent.len();
+            } else if keep == 0 {
+                *extent = Extent::EMPTY;
+            } else {
+                extent.set_len(keep);
+                keep = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn push() {
+        let mut extents = Extents::new(10);
+        assert_eq!(extents.next_contiguous(), Some(11));
+        assert!(extents.push(11));
+        assert!(extents.push(20));
+        assert_eq!(
+            extents.iter().copied().collect::<Vec<_>>(),
+            [Extent::new(10, 2), Extent::new(20, 1)]
+        );
+
+        assert_eq!(extents.blocks(), 3);
+        assert_eq!(extents.block(1), Some(11));
+        assert_eq!(extents.block(2), Some(20));
+        assert_eq!(extents.block(3), None);
+    }
+
+    #[test]
+    fn push_full() {
+        let mut extents = Extents::new(0);
+        for block in 1..MAX_EXTENTS as u32 {
+            assert!(extents.push(block * 2));
+        }
+
+        assert!(extents.is_full());
+        assert!(!extents.push(100));
+        assert!(extents.push(extents.next_contiguous().unwrap()));
+    }
+
+    #[test]
+    fn truncate() {
+        let mut extents = Extents::new(10);
+        for block in [11, 12, 20, 21, 30] {
+            extents.push(block);
+        }
+        let before = extents;
+
+        extents.truncate(2);
+        assert_eq!(
+            extents.iter().copied().collect::<Vec<_>>(),
+            [Extent::new(10, 2)]
+        );
+        assert_eq!(
+            before.iter_blocks().skip(2).collect::<Vec<_>>(),
+            [12, 20, 21, 30]
+        );
+
+        extents.truncate(0);
+        assert_eq!(extents, Extents::new(10));
+    }
+
+    #[test]
+    fn words() {
+        let mut extents = Extents::new(10);
+        extents.push(20);
+
+        let mut words = [0; MAX_EXTENTS];
+        extents.write_words(&mut words);
+        assert_eq!(words[..3], [0x0010_000A, 0x0010_0014, 0]);
+        assert_eq!(Extents::from_words(&words), extents);
+    }
+}
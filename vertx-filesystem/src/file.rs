@@ -1,20 +1,25 @@
 use core::fmt;
 
 use block_device_driver::BlockDevice;
-use embedded_io_async::{ReadExactError, SeekFrom};
+use embedded_io_async::SeekFrom;
 
-use crate::{BLOCK_BYTES, Block, Error, Filesystem, LEN_BYTES};
+use crate::extent::{Extents, MAX_EXTENTS, PREAMBLE_BYTES};
+use crate::{BLOCK_BYTES, Block, DATA_START, Error, Filesystem};
 
 pub struct File<'buf, 'fs, D: BlockDevice<BLOCK_BYTES>> {
     fs: &'fs mut Filesystem<'buf, D>,
-    /// First allocated block
-    start: u32,
-    /// Final allocated block + 1
-    end: u32,
-    /// Length of file data in bytes (including file length)
+    /// Blocks backing the file
+    extents: Extents,
+    /// Blocks owned by the file, which also includes any cut off by
+    /// [`truncate`](Self::truncate) since the last flush. They are only freed
+    /// once the preamble no longer refers to them.
+    reserved: Extents,
+    /// Length of file data in bytes (including the preamble)
     len: usize,
-    /// Current read/write offset in bytes (including file length)
+    /// Current read/write offset in bytes (including the preamble)
     cursor: usize,
+    /// The length or extents have changed, so the preamble needs to be
+    /// written back
     resized: bool,
     #[cfg(debug_assertions)]
     needs_flush: bool,
@@ -22,71 +27,222 @@ pub struct File<'buf, 'fs, D: BlockDevice<BLOCK_BYTES>> {
 
 #[expect(clippy::len_without_is_empty)]
 impl<'buf, 'fs, D: BlockDevice<BLOCK_BYTES>> File<'buf, 'fs, D> {
-    pub(crate) async fn create(
+    /// Buffer the preamble of an empty file in the already allocated block
+    /// `start`, without opening it
+    pub(crate) async fn init(fs: &mut Filesystem<'buf, D>, start: u32) -> Result<(), D::Error> {
+        loog::trace!("creating file at block {start=u32}");
+
+        let mut view = fs.buffer.select(&mut fs.device, start).await?;
+        write_preamble(view.data_mut(), 0, &Extents::new(start));
+        view.mark_modified(0, PREAMBLE_BYTES);
+        Ok(())
+    }
+
+    pub(crate) async fn open(
         fs: &'fs mut Filesystem<'buf, D>,
         start: u32,
-        end: u32,
     ) -> Result<Self, Error<D::Error>> {
-        loog::trace!("creating file at blocks {start=u32}..={end=u32}");
-
-        let mut view = fs.buffer.select(&mut fs.device, start).await?;
-        crate::write_len(view.data_mut(), 0);
-        view.mark_modified(0, LEN_BYTES);
+        let (len, extents) = Self::preamble(fs, start).await?;
+        loog::trace!("opening {len} byte file at block {start=u32}");
 
         Ok(Self {
             fs,
-            start,
-            end,
-            len: LEN_BYTES,
-            cursor: LEN_BYTES,
+            extents,
+            reserved: extents,
+            len: len + PREAMBLE_BYTES,
+            cursor: PREAMBLE_BYTES,
             resized: false,
             #[cfg(debug_assertions)]
             needs_flush: false,
         })
     }
 
-    pub(crate) async fn open(
-        fs: &'fs mut Filesystem<'buf, D>,
+    /// Read the length (excluding the preamble) and extents of the file
+    /// starting at block `start` without opening it. Fails with
+    /// [`Error::CorruptFile`] unless the preamble belongs in `start` and only
+    /// refers to blocks within the filesystem.
+    pub(crate) async fn preamble(
+        fs: &mut Filesystem<'buf, D>,
         start: u32,
-        end: u32,
-    ) -> Result<Self, Error<D::Error>> {
+    ) -> Result<(usize, Extents), Error<D::Error>> {
         let mut view = fs.buffer.select(&mut fs.device, start).await?;
         view.read().await?;
-        let len = crate::read_len(view.data());
-        loog::trace!("opening {len} byte file at blocks {start=u32}..={end=u32}");
+        let (len, extents) = read_preamble(view.data());
+
+        let end = fs.bitmap.data_end();
+        let in_range = extents.start() == start
+            && extents
+                .iter()
+                .all(|extent| extent.start() >= DATA_START && extent.end() <= end)
+            && len + PREAMBLE_BYTES <= extents.blocks() as usize * BLOCK_BYTES;
+        if in_range {
+            Ok((len, extents))
+        } else {
+            loog::warn!("file at block {start=u32} has a damaged preamble");
+            Err(Error::CorruptFile)
+        }
+    }
 
-        Ok(Self {
-            fs,
-            start,
-            end,
-            len: len + LEN_BYTES,
-            cursor: LEN_BYTES,
-            resized: false,
-            #[cfg(debug_assertions)]
-            needs_flush: false,
-        })
+    /// Move the cursor to the end of the file
+    pub(crate) fn seek_end(&mut self) {
+        self.cursor = self.len;
     }
 
     pub fn len(&mut self) -> u64 {
-        (self.len - LEN_BYTES) as u64
+        (self.len - PREAMBLE_BYTES) as u64
     }
 
     fn remaining(&self) -> usize {
         self.len - self.cursor
     }
 
+    /// Cut off the file at the current position. Blocks that are no longer
+    /// needed are freed on the next flush.
     pub fn truncate(&mut self) {
         if self.cursor < self.len {
             self.resized = true;
             self.len = self.cursor;
+            self.extents.truncate(self.len.div_ceil(BLOCK_BYTES) as u32);
+        }
+    }
+
+    /// Device block under the cursor, if it has been allocated yet
+    fn block(&self) -> Option<u32> {
+        self.extents.block((self.cursor / BLOCK_BYTES) as u32)
+    }
+
+    /// Add one more block to the end of the file, reusing one cut off by
+    /// [`truncate`](Self::truncate) if possible
+    async fn grow(&mut self) -> Result<u32, Error<D::Error>> {
+        let block = match self.reserved.block(self.extents.blocks()) {
+            Some(block) => block,
+            None => grow(self.fs, &mut self.reserved).await?,
+        };
+
+        let pushed = self.extents.push(block);
+        debug_assert!(pushed);
+        self.resized = true;
+        Ok(block)
+    }
+
+    /// Copy `len` bytes stored contiguously from `offset` bytes into block
+    /// `from` into newly allocated blocks, returning where the new file is. The
+    /// header is left for the caller to write.
+    pub(crate) async fn import(
+        fs: &mut Filesystem<'buf, D>,
+        from: u32,
+        offset: usize,
+        len: usize,
+    ) -> Result<Extents, Error<D::Error>> {
+        debug_assert!(offset <= PREAMBLE_BYTES);
+
+        let first = fs
+            .bitmap
+            .allocate(&mut fs.device)
+            .await?
+            .ok_or(Error::NoSpace)?;
+        let mut extents = Extents::new(first);
+        for _ in 1..(PREAMBLE_BYTES + len).div_ceil(BLOCK_BYTES) {
+            if let Err(err) = grow(fs, &mut extents).await {
+                if !matches!(err, Error::Io(_)) {
+                    fs.free_extents(&extents).await?;
+                }
+                return Err(err);
+            }
         }
+
+        loog::trace!("importing {len} byte file at block {from=u32} to block {first=u32}");
+
+        // The data moves `shift` bytes further into each block, so every block
+        // starts with the bytes pushed out of the end of the one before it
+        let shift = PREAMBLE_BYTES - offset;
+        let source_blocks = (offset + len).div_ceil(BLOCK_BYTES) as u32;
+        let mut carry = [0; PREAMBLE_BYTES];
+        for (index, to) in (0..).zip(extents.iter_blocks()) {
+            let mut view = if index < source_blocks {
+                fs.buffer.copy(&mut fs.device, from + index, to, 1).await?
+            } else {
+                fs.buffer.select(&mut fs.device, to).await?
+            };
+
+            let data = &mut Block::as_byte_slice_mut(view.data_mut())[..BLOCK_BYTES];
+            if index >= source_blocks {
+                data.fill(0);
+            }
+            data.rotate_right(shift);
+            data[..shift].swap_with_slice(&mut carry[..shift]);
+            if index == 0 {
+                write_preamble(view.data_mut(), len, &extents);
+            }
+            view.mark_modified(0, BLOCK_BYTES);
+        }
+
+        fs.flush().await?;
+        Ok(extents)
     }
 
-    fn block(&self) -> u32 {
-        self.start + (self.cursor / BLOCK_BYTES) as u32
+    /// Copy the file starting at block `start` into newly allocated blocks,
+    /// returning where the copy is. The header is left for the caller to write.
+    pub(crate) async fn copy(
+        fs: &mut Filesystem<'buf, D>,
+        start: u32,
+    ) -> Result<Extents, Error<D::Error>> {
+        let (len, source) = Self::preamble(fs, start).await?;
+
+        let first = fs
+            .bitmap
+            .allocate(&mut fs.device)
+            .await?
+            .ok_or(Error::NoSpace)?;
+        let mut extents = Extents::new(first);
+        for _ in 1..source.blocks() {
+            if let Err(err) = grow(fs, &mut extents).await {
+                if !matches!(err, Error::Io(_)) {
+                    fs.free_extents(&extents).await?;
+                }
+                return Err(err);
+            }
+        }
+
+        loog::trace!("copying {len} byte file at block {start=u32} to block {first=u32}");
+
+        for (from, to) in source.iter_blocks().zip(extents.iter_blocks()) {
+            let mut view = fs.buffer.copy(&mut fs.device, from, to, 1).await?;
+            if to == first {
+                write_preamble(view.data_mut(), len, &extents);
+            }
+        }
+
+        fs.flush().await?;
+        Ok(extents)
     }
 }
 
+/// Allocate one more block at the end of `extents`, preferably contiguous with
+/// the last one
+async fn grow<D: BlockDevice<BLOCK_BYTES>>(
+    fs: &mut Filesystem<'_, D>,
+    extents: &mut Extents,
+) -> Result<u32, Error<D::Error>> {
+    let block = if let Some(next) = extents.next_contiguous()
+        && fs.bitmap.allocate_at(&mut fs.device, next).await?
+    {
+        next
+    } else if extents.is_full() {
+        return Err(Error::FileFull);
+    } else {
+        fs.bitmap
+            .allocate(&mut fs.device)
+            .await?
+            .ok_or(Error::NoSpace)?
+    };
+
+    loog::trace!("allocated block {block=u32}");
+    let pushed = extents.push(block);
+    debug_assert!(pushed);
+    Ok(block)
+}
+
 impl<D: BlockDevice<BLOCK_BYTES>> File<'_, '_, D>
 where
     D::Error: embedded_io_async::Error,
@@ -110,23 +266,23 @@ where
 {
     async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
         let target = match pos {
-            SeekFrom::Start(offset) => Some(LEN_BYTES + offset as usize),
+            SeekFrom::Start(offset) => Some(PREAMBLE_BYTES + offset as usize),
             SeekFrom::End(offset) => self.len.checked_add_signed(offset as isize),
             SeekFrom::Current(offset) => self.cursor.checked_add_signed(offset as isize),
         };
 
         if let Some(new) = target
-            && (LEN_BYTES <= new && new <= self.len)
+            && (PREAMBLE_BYTES <= new && new <= self.len)
         {
             self.cursor = new;
-            Ok((new - LEN_BYTES) as u64)
+            Ok((new - PREAMBLE_BYTES) as u64)
         } else {
             Err(Error::SeekOutOfBounds)
         }
     }
 
     async fn stream_position(&mut self) -> Result<u64, Self::Error> {
-        Ok((self.cursor - LEN_BYTES) as u64)
+        Ok((self.cursor - PREAMBLE_BYTES) as u64)
     }
 }
 
@@ -136,61 +292,28 @@ where
 {
     async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
         loog::trace!(
-            "reading up to {} bytes from file at block {=u32}",
+            "reading up to {} bytes from file at offset {}",
             buf.len(),
-            self.block()
+            self.cursor
         );
 
-        let len = self.remaining().min(buf.len());
+        let offset = self.cursor % BLOCK_BYTES;
+        let len = self.remaining().min(buf.len()).min(BLOCK_BYTES - offset);
+        if len == 0 {
+            return Ok(0);
+        }
 
-        let start = self.block();
-        let mut view = self.fs.buffer.select(&mut self.fs.device, start).await?;
+        let block = self
+            .block()
+            .expect("blocks are always allocated up to the file length");
+        let mut view = self.fs.buffer.select(&mut self.fs.device, block).await?;
         view.read().await?;
         let data = Block::as_byte_slice(view.data());
 
-        let offset = self.cursor % BLOCK_BYTES;
-        let len = len.min(data.len() - offset);
-
         buf[0..len].copy_from_slice(&data[offset..(offset + len)]);
         self.cursor += len;
         Ok(len)
     }
-
-    async fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), ReadExactError<Self::Error>> {
-        loog::trace!(
-            "reading exactly {} bytes from file at block {=u32}",
-            buf.len(),
-            self.block()
-        );
-
-        let io_err = |err| ReadExactError::Other(Error::Io(err));
-
-        let end = self.cursor + buf.len();
-        if end > self.len {
-            return Err(ReadExactError::UnexpectedEof);
-        }
-
-        let start = self.block();
-        let end = (self.cursor + buf.len()).div_ceil(BLOCK_BYTES) as u32;
-        let len_blocks = end - start;
-        if len_blocks > self.fs.buffer.len() {
-            return Err(ReadExactError::UnexpectedEof);
-        }
-
-        let mut view = self
-            .fs
-            .buffer
-            .select_exact(&mut self.fs.device, start, len_blocks)
-            .await
-            .map_err(io_err)?;
-        view.read().await.map_err(io_err)?;
-        let data = Block::as_byte_slice(view.data());
-
-        let offset = self.cursor % BLOCK_BYTES;
-        buf.copy_from_slice(&data[offset..(offset + buf.len())]);
-        self.cursor += buf.len();
-        Ok(())
-    }
 }
 
 impl<D: BlockDevice<BLOCK_BYTES>> embedded_io_async::Write for File<'_, '_, D>
@@ -199,62 +322,77 @@ where
 {
     async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
         loog::trace!(
-            "writing up to {} bytes to file at block {=u32}",
+            "writing up to {} bytes to file at offset {}",
             buf.len(),
-            self.block()
+            self.cursor
         );
 
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
         #[cfg(debug_assertions)]
         {
             self.needs_flush = true;
         }
 
-        if buf.len() > self.remaining() {
-            let new_len = self.cursor + buf.len();
-            let new_end = new_len.div_ceil(BLOCK_BYTES) as u32;
-            if new_end > self.end {
-                return Err(Error::FileFull);
-            }
+        let offset = self.cursor % BLOCK_BYTES;
+        let len = buf.len().min(BLOCK_BYTES - offset);
 
+        let block = match self.block() {
+            Some(block) => block,
+            None => self.grow().await?,
+        };
+
+        let new_len = self.cursor + len;
+        if new_len > self.len {
             loog::trace!("growing from {} to {} bytes", self.len, new_len);
 
             self.len = new_len;
             self.resized = true;
         }
 
-        let start = self.block();
-        let offset = self.cursor % BLOCK_BYTES;
-
-        let mut view = self.fs.buffer.select(&mut self.fs.device, start).await?;
-        if offset > 0 || buf.len() < BLOCK_BYTES {
+        let mut view = self.fs.buffer.select(&mut self.fs.device, block).await?;
+        if len < BLOCK_BYTES {
             view.read().await?;
         }
         let data = Block::as_byte_slice_mut(view.data_mut());
 
-        let len = buf.len().min(data.len() - offset);
         data[offset..(offset + len)].copy_from_slice(&buf[0..len]);
+        view.mark_modified(offset, len);
         self.cursor += len;
         Ok(len)
     }
 
     async fn flush(&mut self) -> Result<(), Self::Error> {
-        loog::trace!("flushing file at block {=u32}", self.block());
+        loog::trace!("flushing file at block {=u32}", self.extents.start());
+
+        // Any new blocks have to be marked as allocated before the preamble
+        // refers to them
+        self.fs.flush().await?;
 
         if self.resized {
-            loog::trace!("file has been resized; updating len");
+            loog::trace!("file has been resized; updating preamble");
 
-            let mut view = self
-                .fs
-                .buffer
-                .select(&mut self.fs.device, self.start)
-                .await?;
+            let start = self.extents.start();
+            let mut view = self.fs.buffer.select(&mut self.fs.device, start).await?;
             view.read().await?;
-            crate::write_len(view.data_mut(), self.len - LEN_BYTES);
-            view.mark_modified(0, LEN_BYTES);
+            write_preamble(view.data_mut(), self.len - PREAMBLE_BYTES, &self.extents);
+            view.mark_modified(0, PREAMBLE_BYTES);
+            self.fs.buffer.flush(&mut self.fs.device).await?;
+
+            // Now that nothing refers to them, free any blocks cut off by
+            // truncate
+            let fs = &mut *self.fs;
+            let blocks = self.extents.blocks() as usize;
+            for block in self.reserved.iter_blocks().skip(blocks) {
+                fs.bitmap.free(&mut fs.device, block, 1).await?;
+            }
+            fs.bitmap.flush(&mut fs.device).await?;
+            self.reserved = self.extents;
+            self.resized = false;
         }
 
-        self.fs.flush().await?;
-
         #[cfg(debug_assertions)]
         {
             self.needs_flush = false;
@@ -276,14 +414,26 @@ impl<D: BlockDevice<BLOCK_BYTES>> Drop for File<'_, '_, D> {
 impl<D: BlockDevice<BLOCK_BYTES>> fmt::Debug for File<'_, '_, D> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("File")
-            .field("start", &self.start)
-            .field("end", &self.end)
+            .field("extents", &self.extents)
             .field("len", &self.len)
             .field("cursor", &self.cursor)
             .finish_non_exhaustive()
     }
 }
 
+fn read_preamble<A: aligned::Alignment>(buffer: &[Block<A>]) -> (usize, Extents) {
+    let words = Block::as_word_slice(buffer);
+    (
+        crate::read_len(buffer),
+        Extents::from_words(&words[1..=MAX_EXTENTS]),
+    )
+}
+
+fn write_preamble<A: aligned::Alignment>(buffer: &mut [Block<A>], len: usize, extents: &Extents) {
+    crate::write_len(buffer, len);
+    extents.write_words(&mut Block::as_word_slice_mut(buffer)[1..=MAX_EXTENTS]);
+}
+
 #[cfg(test)]
 mod tests {
     use std::array;
@@ -296,40 +446,49 @@ mod tests {
     #[tokio::test]
     #[test_log::test]
     async fn create() {
-        let mut mock = Mock::<2>::new();
+        let mut mock = Mock::<8>::new();
         let mut buffers = crate::Buffers::new();
-        let mut fs = Filesystem::new_empty(&mut mock, &mut buffers);
+        let mut fs = Filesystem::new_empty(&mut mock, &mut buffers)
+            .await
+            .unwrap();
 
-        let mut file = File::create(&mut fs, 1, 2).await.unwrap();
+        File::init(&mut fs, DATA_START).await.unwrap();
+        let mut file = File::open(&mut fs, DATA_START).await.unwrap();
         assert_eq!(file.len(), 0);
         assert_eq!(
             file.seek(SeekFrom::Start(1)).await.unwrap_err(),
             Error::SeekOutOfBounds
         );
-        assert_eq!(file.cursor, LEN_BYTES);
+        assert_eq!(file.cursor, PREAMBLE_BYTES);
         file.write_all(&[1, 2]).await.unwrap();
         assert_eq!(file.len(), 2);
         file.close().await.unwrap();
 
-        let blocks = mock.blocks();
-        assert_eq!(blocks[0].iter().find(|x| **x != 0), None);
-        assert_eq!(&blocks[1][0..8], &[2, 0, 0, 0, 1, 2, 0, 0]);
+        let block = &mock.blocks()[DATA_START as usize];
+        assert_eq!(&block[0..12], &[2, 0, 0, 0, 6, 0, 0x10, 0, 0, 0, 0, 0]);
+        assert_eq!(&block[PREAMBLE_BYTES..][..3], &[1, 2, 0]);
     }
 
     #[tokio::test]
     #[test_log::test]
     async fn open() {
-        let mut mock = Mock::<1>::new();
+        let mut mock = Mock::<8>::new();
+        let mut buffers = crate::Buffers::new();
+        let mut fs = Filesystem::new_empty(&mut mock, &mut buffers)
+            .await
+            .unwrap();
+
         {
-            let mock = mock.block_mut(0);
-            mock[0] = 9;
-            mock[4..13].copy_from_slice(&array::from_fn::<_, 9, _>(|x| x as u8 + 1)[..]);
+            let mut view = fs.buffer.select(&mut fs.device, DATA_START).await.unwrap();
+            let block = Block::as_byte_slice_mut(view.data_mut());
+            block[0] = 9;
+            block[4..8].copy_from_slice(&[6, 0, 0x10, 0]);
+            block[PREAMBLE_BYTES..][..9]
+                .copy_from_slice(&array::from_fn::<_, 9, _>(|x| x as u8 + 1)[..]);
+            view.mark_modified(0, BLOCK_BYTES);
         }
 
-        let mut buffers = crate::Buffers::new();
-        let mut fs = Filesystem::new_empty(&mut mock, &mut buffers);
-
-        let mut file = File::open(&mut fs, 0, 1).await.unwrap();
+        let mut file = File::open(&mut fs, DATA_START).await.unwrap();
         assert_eq!(file.len(), 9);
         let mut buf = [0; 9];
         assert_eq!(file.read(&mut buf).await.unwrap(), buf.len());
@@ -338,10 +497,38 @@ mod tests {
         file.write(&[buf.iter().sum()]).await.unwrap();
         file.close().await.unwrap();
 
-        let mut file = File::open(&mut fs, 0, 1).await.unwrap();
+        let mut file = File::open(&mut fs, DATA_START).await.unwrap();
         assert_eq!(file.len(), 1);
         let mut buf = [0];
         assert_eq!(file.read(&mut buf).await.unwrap(), 1);
         assert_eq!(buf, [45]);
     }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn open_damaged() {
+        let mut mock = Mock::<{ DATA_START as usize + 3 }>::new();
+        let mut buffers = crate::Buffers::new();
+        let mut fs = Filesystem::new_empty(&mut mock, &mut buffers)
+            .await
+            .unwrap();
+
+        // Longer than its single block, elsewhere, and over the bitmap
+        for (len, extent) in [
+            (BLOCK_BYTES, [6, 0, 0x10, 0]),
+            (0, [7, 0, 0x10, 0]),
+            (0, [6, 0, 0x30, 0]),
+        ] {
+            let mut view = fs.buffer.select(&mut fs.device, DATA_START).await.unwrap();
+            let block = Block::as_byte_slice_mut(view.data_mut());
+            block[..4].copy_from_slice(&(len as u32).to_le_bytes());
+            block[4..8].copy_from_slice(&extent);
+            view.mark_modified(0, 8);
+
+            assert_eq!(
+                File::open(&mut fs, DATA_START).await.unwrap_err(),
+                Error::CorruptFile
+            );
+        }
+    }
 }
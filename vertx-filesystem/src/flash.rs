@@ -0,0 +1,688 @@
+//! Block device on raw NOR flash, for boards without an SD card
+//!
+//! Flash can only be erased a whole sector at a time, so blocks are never
+//! rewritten in place. Each write goes to the next free slot of the active
+//! sector instead, and the old copy is reclaimed later by moving any live
+//! blocks out of its sector and erasing it. The first slot of each sector is a
+//! header recording which block each of the other slots holds, which is enough
+//! to rebuild the block map when mounting.
+//!
+//! Free sectors are reused least erased first. If a sector of cold data falls
+//! too far behind the most worn sector, its blocks are moved so it can rejoin
+//! the rotation.
+
+use core::fmt;
+use core::ops::Range;
+
+use aligned::{A4, Aligned};
+use block_device_driver::BlockDevice;
+use embedded_storage_async::nor_flash::NorFlash;
+#[cfg(feature = "defmt")]
+use loog::defmt;
+
+use crate::BLOCK_BYTES;
+
+/// Size of the unit this erases and tracks wear in
+pub const SECTOR_BYTES: u32 = 4096;
+/// Slots for blocks in each sector, after the header
+const SLOTS: usize = SECTOR_BYTES as usize / BLOCK_BYTES - 1;
+/// Sectors kept free so there is always somewhere to move live blocks to
+const RESERVE: usize = 2;
+/// How many more times the most worn sector can have been erased than one
+/// holding data before that data gets moved
+const WEAR_THRESHOLD: u32 = 32;
+
+const MAGIC: u32 = u32::from_le_bytes(*b"VxFl");
+const ERASED: u32 = u32::MAX;
+const UNMAPPED: u16 = u16::MAX;
+
+/// Magic, sequence, erase count, then one entry per slot
+const HEADER_WORDS: usize = 3 + SLOTS;
+const CHUNK_WORDS: usize = 16;
+
+/// How many blocks a [`FlashDevice`] can store in `sectors` sectors
+pub const fn blocks(sectors: usize) -> usize {
+    sectors.saturating_sub(RESERVE + 1) * SLOTS
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    OutOfBounds,
+    Flash(E),
+}
+
+impl<E: fmt::Debug> embedded_io_async::Error for Error<E> {
+    fn kind(&self) -> embedded_io_async::ErrorKind {
+        use embedded_io_async::ErrorKind;
+        match self {
+            Self::OutOfBounds => ErrorKind::InvalidInput,
+            Self::Flash(_) => ErrorKind::Other,
+        }
+    }
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(flash: E) -> Self {
+        Self::Flash(flash)
+    }
+}
+
+/// Emulates a block device on `SECTORS` sectors of flash, storing up to
+/// `BLOCKS` blocks, which should be [`blocks(SECTORS)`](blocks)
+pub struct FlashDevice<F, const SECTORS: usize, const BLOCKS: usize> {
+    flash: F,
+    /// Offset of the first sector within `flash`
+    start: u32,
+    /// Number of sectors in use, which may be fewer than `SECTORS` if the
+    /// region is small
+    sectors: usize,
+    /// Number of blocks exposed, which may be fewer than `BLOCKS` if the region
+    /// is small
+    blocks: usize,
+    state: [Sector; SECTORS],
+    /// Slot holding the current copy of each block
+    map: [u16; BLOCKS],
+    /// Sector new blocks are being written to
+    active: usize,
+    /// Next unused slot in the active sector
+    next_slot: usize,
+    /// Sequence number for the next sector to be opened
+    sequence: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sector {
+    erases: u32,
+    /// Order the sector was opened in, so the newest copy of a block wins
+    sequence: u32,
+    /// Number of blocks whose current copy is in this sector
+    live: u16,
+}
+
+impl Sector {
+    const EMPTY: Self = Self {
+        erases: 0,
+        sequence: 0,
+        live: 0,
+    };
+}
+
+impl<F: NorFlash, const SECTORS: usize, const BLOCKS: usize> FlashDevice<F, SECTORS, BLOCKS> {
+    /// Mount the sectors of `flash` within `region`, which must be sector
+    /// aligned
+    pub async fn new(flash: F, region: Range<u32>) -> Result<Self, F::Error> {
+        const {
+            assert!((SECTOR_BYTES as usize).is_multiple_of(F::ERASE_SIZE));
+            assert!(4usize.is_multiple_of(F::READ_SIZE) && 4usize.is_multiple_of(F::WRITE_SIZE));
+            assert!(BLOCKS <= blocks(SECTORS));
+            assert!(SECTORS * SLOTS < UNMAPPED as usize);
+        }
+
+        debug_assert!(region.start.is_multiple_of(SECTOR_BYTES));
+        debug_assert!(region.end as usize <= flash.capacity());
+
+        let sectors = (region.len() / SECTOR_BYTES as usize).min(SECTORS);
+        let mut device = Self {
+            flash,
+            start: region.start,
+            sectors,
+            blocks: blocks(sectors).min(BLOCKS),
+            state: [Sector::EMPTY; SECTORS],
+            map: [UNMAPPED; BLOCKS],
+            active: 0,
+            next_slot: SLOTS,
+            sequence: 0,
+        };
+
+        device.mount().await?;
+        Ok(device)
+    }
+
+    /// Rebuild the block map from the sector headers
+    async fn mount(&mut self) -> Result<(), F::Error> {
+        let mut newest: Option<(usize, [u32; HEADER_WORDS])> = None;
+        for sector in 0..self.sectors {
+            let header = self.read_header(sector).await?;
+            if u32::from_le(header[0]) != MAGIC {
+                continue;
+            }
+
+            let sequence = u32::from_le(header[1]);
+            self.state[sector] = Sector {
+                erases: u32::from_le(header[2]),
+                sequence,
+                live: 0,
+            };
+
+            for (slot, entry) in header[3..].iter().enumerate() {
+                let Some(block) = decode(u32::from_le(*entry)).filter(|&b| b < self.blocks) else {
+                    continue;
+                };
+
+                let slot = sector * SLOTS + slot;
+                let current = self.map[block];
+                if current == UNMAPPED || self.is_newer(slot, usize::from(current)) {
+                    self.map[block] = slot as u16;
+                }
+            }
+
+            if newest
+                .is_none_or(|(newest, _)| crate::is_newer(sequence, self.state[newest].sequence))
+            {
+                newest = Some((sector, header));
+            }
+        }
+
+        for &slot in &self.map[..self.blocks] {
+            if slot != UNMAPPED {
+                self.state[usize::from(slot) / SLOTS].live += 1;
+            }
+        }
+
+        if let Some((sector, header)) = newest {
+            // Carry on filling the newest sector, skipping over a slot that was
+            // only partly written
+            let mut next = header[3..]
+                .iter()
+                .rposition(|&entry| entry != ERASED)
+                .map_or(0, |slot| slot + 1);
+            while next < SLOTS && !self.is_blank(sector * SLOTS + next).await? {
+                next += 1;
+            }
+
+            self.active = sector;
+            self.next_slot = next;
+            self.sequence = self.state[sector].sequence.wrapping_add(1);
+        }
+
+        loog::debug!(
+            "mounted {} flash sectors, {} blocks in use",
+            self.sectors,
+            self.map.iter().filter(|&&slot| slot != UNMAPPED).count()
+        );
+
+        Ok(())
+    }
+
+    /// Whether `a` was written after `b`
+    fn is_newer(&self, a: usize, b: usize) -> bool {
+        let a_sequence = self.state[a / SLOTS].sequence;
+        let b_sequence = self.state[b / SLOTS].sequence;
+        if a_sequence == b_sequence {
+            a > b
+        } else {
+            crate::is_newer(a_sequence, b_sequence)
+        }
+    }
+
+    fn free_sectors(&self) -> impl Iterator<Item = (usize, &Sector)> {
+        self.state[..self.sectors]
+            .iter()
+            .enumerate()
+            .filter(|&(index, sector)| sector.live == 0 && index != self.active)
+    }
+
+    /// Sectors holding live blocks, other than the active sector
+    fn used_sectors(&self) -> impl Iterator<Item = (usize, &Sector)> {
+        self.state[..self.sectors]
+            .iter()
+            .enumerate()
+            .filter(|&(index, sector)| sector.live > 0 && index != self.active)
+    }
+
+    /// The least erased sector holding data, if it has fallen too far behind
+    fn worn_sector(&self) -> Option<usize> {
+        let most = self.state[..self.sectors]
+            .iter()
+            .map(|sector| sector.erases)
+            .max()?;
+        let (index, coldest) = self
+            .used_sectors()
+            .min_by_key(|(_, sector)| sector.erases)?;
+        (coldest.erases + WEAR_THRESHOLD < most).then_some(index)
+    }
+
+    /// Find a free slot, reclaiming space first if needed
+    async fn allocate(&mut self) -> Result<usize, F::Error> {
+        loop {
+            while self.free_sectors().count() < RESERVE || self.worn_sector().is_some() {
+                if !self.collect().await? {
+                    break;
+                }
+            }
+
+            if self.next_slot < SLOTS {
+                let slot = self.active * SLOTS + self.next_slot;
+                self.next_slot += 1;
+                return Ok(slot);
+            }
+
+            self.open_sector().await?;
+        }
+    }
+
+    /// Erase the least worn free sector and start writing to it
+    async fn open_sector(&mut self) -> Result<(), F::Error> {
+        let (sector, erases) = self
+            .free_sectors()
+            .min_by_key(|(_, sector)| sector.erases)
+            .map(|(index, sector)| (index, sector.erases))
+            .expect("reserved sectors are always free when the active sector fills");
+
+        loog::trace!("opening flash sector {sector}");
+
+        let offset = self.sector_offset(sector);
+        self.flash.erase(offset, offset + SECTOR_BYTES).await?;
+
+        let erases = erases.wrapping_add(1);
+        self.state[sector] = Sector {
+            erases,
+            sequence: self.sequence,
+            live: 0,
+        };
+
+        // Write the magic last, so a header that was cut short gets ignored
+        let info = [self.sequence.to_le(), erases.to_le()];
+        self.flash
+            .write(offset + 4, bytemuck::bytes_of(&info))
+            .await?;
+        self.flash
+            .write(offset, bytemuck::bytes_of(&MAGIC.to_le()))
+            .await?;
+
+        self.active = sector;
+        self.next_slot = 0;
+        self.sequence = self.sequence.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Move the live blocks out of one sector so it can be reused. Returns
+    /// `false` if there is nothing worth moving that fits in the active sector.
+    async fn collect(&mut self) -> Result<bool, F::Error> {
+        let room = SLOTS - self.next_slot;
+        let fits = |sector: usize| usize::from(self.state[sector].live) <= room;
+
+        let victim = if let Some(worn) = self.worn_sector()
+            && fits(worn)
+        {
+            worn
+        } else if self.free_sectors().count() < RESERVE
+            && let Some((fewest, _)) = self.used_sectors().min_by_key(|(_, sector)| sector.live)
+            && fits(fewest)
+        {
+            fewest
+        } else {
+            return Ok(false);
+        };
+
+        loog::trace!(
+            "moving {} blocks out of flash sector {victim}",
+            self.state[victim].live
+        );
+
+        for block in 0..self.blocks {
+            let from = usize::from(self.map[block]);
+            if self.map[block] == UNMAPPED || from / SLOTS != victim {
+                continue;
+            }
+
+            let to = self.active * SLOTS + self.next_slot;
+            self.next_slot += 1;
+
+            let mut chunk = [0u32; CHUNK_WORDS];
+            let chunk = bytemuck::bytes_of_mut(&mut chunk);
+            for offset in (0..BLOCK_BYTES as u32).step_by(chunk.len()) {
+                self.flash
+                    .read(self.slot_offset(from) + offset, chunk)
+                    .await?;
+                self.flash
+                    .write(self.slot_offset(to) + offset, chunk)
+                    .await?;
+            }
+
+            self.commit(block, to).await?;
+        }
+
+        Ok(true)
+    }
+
+    /// Point `block` at `slot`, which must already hold its data
+    async fn commit(&mut self, block: usize, slot: usize) -> Result<(), F::Error> {
+        let entry = encode(block).to_le();
+        self.flash
+            .write(self.entry_offset(slot), bytemuck::bytes_of(&entry))
+            .await?;
+
+        let old = core::mem::replace(&mut self.map[block], slot as u16);
+        if old != UNMAPPED {
+            self.state[usize::from(old) / SLOTS].live -= 1;
+        }
+        self.state[slot / SLOTS].live += 1;
+        Ok(())
+    }
+
+    /// Whether `data` is what reading `block` would return already
+    async fn is_unchanged(&mut self, block: usize, data: &[u8]) -> Result<bool, F::Error> {
+        let slot = self.map[block];
+        if slot == UNMAPPED {
+            return Ok(data.iter().all(|&byte| byte == 0));
+        }
+
+        let mut chunk = [0u32; CHUNK_WORDS];
+        let chunk = bytemuck::bytes_of_mut(&mut chunk);
+        let start = self.slot_offset(usize::from(slot));
+        for (offset, expected) in (0..).step_by(chunk.len()).zip(data.chunks(chunk.len())) {
+            self.flash.read(start + offset, chunk).await?;
+            if chunk != expected {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    async fn is_blank(&mut self, slot: usize) -> Result<bool, F::Error> {
+        let mut chunk = [0u32; CHUNK_WORDS];
+        let start = self.slot_offset(slot);
+        for offset in (0..BLOCK_BYTES as u32).step_by(CHUNK_WORDS * 4) {
+            self.flash
+                .read(start + offset, bytemuck::bytes_of_mut(&mut chunk))
+                .await?;
+            if chunk.iter().any(|&word| word != ERASED) {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    async fn read_header(&mut self, sector: usize) -> Result<[u32; HEADER_WORDS], F::Error> {
+        let mut header = [0; HEADER_WORDS];
+        self.flash
+            .read(
+                self.sector_offset(sector),
+                bytemuck::bytes_of_mut(&mut header),
+            )
+            .await?;
+        Ok(header)
+    }
+
+    fn sector_offset(&self, sector: usize) -> u32 {
+        self.start + sector as u32 * SECTOR_BYTES
+    }
+
+    fn slot_offset(&self, slot: usize) -> u32 {
+        self.sector_offset(slot / SLOTS) + ((slot % SLOTS + 1) * BLOCK_BYTES) as u32
+    }
+
+    fn entry_offset(&self, slot: usize) -> u32 {
+        self.sector_offset(slot / SLOTS) + ((3 + slot % SLOTS) * 4) as u32
+    }
+}
+
+impl<F: NorFlash, const SECTORS: usize, const BLOCKS: usize> BlockDevice<BLOCK_BYTES>
+    for FlashDevice<F, SECTORS, BLOCKS>
+{
+    type Align = A4;
+    type Error = Error<F::Error>;
+
+    async fn read(
+        &mut self,
+        block_address: u32,
+        data: &mut [Aligned<Self::Align, [u8; BLOCK_BYTES]>],
+    ) -> Result<(), Self::Error> {
+        for (block, data) in (block_address as usize..).zip(data) {
+            let slot = *self.map[..self.blocks]
+                .get(block)
+                .ok_or(Error::OutOfBounds)?;
+
+            if slot == UNMAPPED {
+                data.fill(0);
+            } else {
+                let offset = self.slot_offset(usize::from(slot));
+                self.flash.read(offset, &mut data[..]).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn write(
+        &mut self,
+        block_address: u32,
+        data: &[Aligned<Self::Align, [u8; BLOCK_BYTES]>],
+    ) -> Result<(), Self::Error> {
+        for (block, data) in (block_address as usize..).zip(data) {
+            if block >= self.blocks {
+                return Err(Error::OutOfBounds);
+            }
+
+            // Spare the flash from rewriting blocks that have not changed
+            if self.is_unchanged(block, &data[..]).await? {
+                continue;
+            }
+
+            let slot = self.allocate().await?;
+            self.flash.write(self.slot_offset(slot), &data[..]).await?;
+            self.commit(block, slot).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn size(&mut self) -> Result<u64, Self::Error> {
+        Ok((self.blocks * BLOCK_BYTES) as u64)
+    }
+}
+
+impl<F, const SECTORS: usize, const BLOCKS: usize> fmt::Debug for FlashDevice<F, SECTORS, BLOCKS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FlashDevice")
+            .field("sectors", &self.sectors)
+            .field("blocks", &self.blocks)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Header entries hold the block number alongside its complement, so a torn
+/// write cannot be mistaken for a valid entry
+fn encode(block: usize) -> u32 {
+    let block = block as u16;
+    u32::from(block) | (u32::from(!block) << 16)
+}
+
+fn decode(entry: u32) -> Option<usize> {
+    let block = entry as u16;
+    ((entry >> 16) as u16 == !block).then_some(usize::from(block))
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_io_async::{Read as _, Write as _};
+
+    use super::*;
+    use crate::{Buffers, Filesystem, MockFlash};
+
+    const SECTORS: usize = 8;
+    const BLOCKS: usize = blocks(SECTORS);
+
+    type Device<'a> = FlashDevice<&'a mut MockFlash, SECTORS, BLOCKS>;
+
+    async fn mount(flash: &mut MockFlash) -> Device<'_> {
+        let end = (SECTORS * MockFlash::SECTOR_BYTES) as u32;
+        FlashDevice::new(flash, 0..end).await.unwrap()
+    }
+
+    fn block(fill: u8) -> [Aligned<A4, [u8; BLOCK_BYTES]>; 1] {
+        [Aligned([fill; BLOCK_BYTES])]
+    }
+
+    async fn read(device: &mut Device<'_>, address: u32) -> u8 {
+        let mut data = block(0xAA);
+        device.read(address, &mut data).await.unwrap();
+        assert!(data[0].iter().all(|&byte| byte == data[0][0]));
+        data[0][0]
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn round_trip() {
+        let mut flash = MockFlash::new(SECTORS);
+        let mut device = mount(&mut flash).await;
+        assert_eq!(device.size().await, Ok((BLOCKS * BLOCK_BYTES) as u64));
+
+        assert_eq!(read(&mut device, 3).await, 0);
+        device.write(3, &block(1)).await.unwrap();
+        device.write(3, &block(2)).await.unwrap();
+        assert_eq!(read(&mut device, 3).await, 2);
+
+        let mut data = block(0);
+        assert_eq!(
+            device.read(BLOCKS as u32, &mut data).await,
+            Err(Error::OutOfBounds)
+        );
+        assert_eq!(
+            device.write(BLOCKS as u32, &data).await,
+            Err(Error::OutOfBounds)
+        );
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn remount() {
+        let mut flash = MockFlash::new(SECTORS);
+        let mut device = mount(&mut flash).await;
+        for round in 1..=20 {
+            for address in 0..BLOCKS as u32 / 2 {
+                device.write(address, &block(round)).await.unwrap();
+            }
+        }
+        device.write(0, &block(0xF0)).await.unwrap();
+
+        let mut device = mount(&mut flash).await;
+        assert_eq!(read(&mut device, 0).await, 0xF0);
+        for address in 1..BLOCKS as u32 / 2 {
+            assert_eq!(read(&mut device, address).await, 20);
+        }
+        assert_eq!(read(&mut device, BLOCKS as u32 - 1).await, 0);
+
+        // Writes resume where they left off
+        device.write(1, &block(21)).await.unwrap();
+        let mut device = mount(&mut flash).await;
+        assert_eq!(read(&mut device, 1).await, 21);
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn fills_every_block() {
+        let mut flash = MockFlash::new(SECTORS);
+        let mut device = mount(&mut flash).await;
+        for round in 1..=3 {
+            for address in 0..BLOCKS as u32 {
+                device
+                    .write(address, &block(round + address as u8))
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let mut device = mount(&mut flash).await;
+        for address in 0..BLOCKS as u32 {
+            assert_eq!(read(&mut device, address).await, 3 + address as u8);
+        }
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn levels_wear() {
+        let mut flash = MockFlash::new(SECTORS);
+        let mut device = mount(&mut flash).await;
+
+        // Fill most of the device with data that never changes, then hammer a
+        // single block
+        for address in 1..BLOCKS as u32 {
+            device.write(address, &block(address as u8)).await.unwrap();
+        }
+        for round in 0..5000u32 {
+            device.write(0, &block(round as u8)).await.unwrap();
+        }
+
+        let mut device = mount(&mut flash).await;
+        for address in 1..BLOCKS as u32 {
+            assert_eq!(read(&mut device, address).await, address as u8);
+        }
+
+        let erases = flash.erases();
+        let most = erases.iter().max().unwrap();
+        let least = erases.iter().min().unwrap();
+        assert!(most - least <= WEAR_THRESHOLD + 1, "{erases:?}");
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn skips_unchanged() {
+        let mut flash = MockFlash::new(SECTORS);
+        let mut device = mount(&mut flash).await;
+        device.write(0, &block(0)).await.unwrap();
+        device.write(1, &block(1)).await.unwrap();
+        device.write(1, &block(1)).await.unwrap();
+        assert_eq!(device.next_slot, 1);
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn power_loss() {
+        // Cut the power at every point in a write that has to reclaim a sector
+        for budget in (0..3 * BLOCK_BYTES).step_by(4).chain([2, 5, 515]) {
+            let mut flash = MockFlash::new(SECTORS);
+            let mut device = mount(&mut flash).await;
+            for round in 1..=5 {
+                for address in 0..BLOCKS as u32 - 2 {
+                    device.write(address, &block(round)).await.unwrap();
+                }
+            }
+
+            flash.lose_power_after(budget);
+            let mut device = mount(&mut flash).await;
+            let torn = device.write(0, &block(6)).await.is_err();
+            flash.restore_power();
+
+            // A write that was cut short may or may not have landed
+            let mut device = mount(&mut flash).await;
+            let value = read(&mut device, 0).await;
+            assert!(value == 6 || (torn && value == 5), "budget {budget}");
+            for address in 1..BLOCKS as u32 - 2 {
+                assert_eq!(read(&mut device, address).await, 5, "budget {budget}");
+            }
+
+            device.write(0, &block(7)).await.unwrap();
+            let mut device = mount(&mut flash).await;
+            assert_eq!(read(&mut device, 0).await, 7, "budget {budget}");
+        }
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn filesystem() {
+        let mut flash = MockFlash::new(SECTORS);
+        let device = mount(&mut flash).await;
+        let mut buffers = Buffers::new();
+        let mut fs = Filesystem::new_empty(device, &mut buffers).await.unwrap();
+        fs.write_config(b"config").await.unwrap();
+        let mut file = fs.new_model("model").await.unwrap();
+        file.write_all(&[0x55; 3 * BLOCK_BYTES]).await.unwrap();
+        file.close().await.unwrap();
+
+        let device = mount(&mut flash).await;
+        let mut buffers = Buffers::new();
+        let mut fs = Filesystem::new(device, &mut buffers).await.unwrap();
+        let mut config = [0; 16];
+        assert_eq!(fs.read_config(&mut config).await.unwrap(), b"config");
+
+        let mut file = fs.model(0).await.unwrap().unwrap();
+        let mut data = [0; 3 * BLOCK_BYTES];
+        file.read_exact(&mut data).await.unwrap();
+        assert!(data.iter().all(|&byte| byte == 0x55));
+    }
+}
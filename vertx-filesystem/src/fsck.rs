@@ -0,0 +1,527 @@
+use core::cmp::Ordering;
+
+use block_device_driver::BlockDevice;
+#[cfg(feature = "defmt")]
+use loog::defmt;
+
+use crate::extent::Extents;
+use crate::header::{Header, Model};
+use crate::{
+    BLOCK_BYTES, Block, Buffers, DATA_START, Error, File, Filesystem, HeaderError, MAX_FILES,
+    MODEL_NAME_BYTES, NAMES_OFFSET, config, directory,
+};
+
+/// Dropped files are tracked with one bit each
+const _: () = assert!(MAX_FILES <= u32::BITS as usize);
+
+/// What [`Filesystem::fsck`] salvaged from the model table
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Report {
+    pub recovered: u8,
+    /// Models dropped for having a damaged preamble, or one that starts or
+    /// extends outside the filesystem
+    pub out_of_range: u8,
+    /// Models dropped because an earlier entry already had the same ID
+    pub duplicate_ids: u8,
+    /// Models dropped for having no name
+    pub unnamed: u8,
+    /// Models dropped because an earlier entry already owned some of their
+    /// blocks
+    pub overlapping: u8,
+    /// Named files kept in the directory
+    pub recovered_files: u8,
+    /// Named files dropped for having a damaged preamble, or blocks that a
+    /// model or an earlier file already owned
+    pub dropped_files: u8,
+}
+
+impl Report {
+    /// Total number of models that could not be recovered
+    pub fn dropped(&self) -> u8 {
+        self.out_of_range + self.duplicate_ids + self.unnamed + self.overlapping
+    }
+}
+
+enum Problem {
+    OutOfRange,
+    DuplicateId,
+    Unnamed,
+    Overlapping,
+}
+
+impl<'buf, D: BlockDevice<BLOCK_BYTES>> Filesystem<'buf, D> {
+    /// Rebuild the header from whatever can be salvaged of the model table,
+    /// for use when [`Filesystem::new`] fails. Damaged models and ones that
+    /// conflict with an earlier entry are dropped, and the allocation bitmap is
+    /// rebuilt from the models that remain. The config and named files are kept
+    /// unless their blocks overlap one of them.
+    ///
+    /// Fails with [`Error::UnsupportedVersion`] without writing anything if
+    /// neither header copy is intact and either is from another format.
+    pub async fn fsck(
+        mut device: D,
+        buffers: &'buf mut Buffers<D::Align>,
+    ) -> Result<(Self, Report), Error<D::Error>> {
+        crate::read_headers(&mut device, buffers).await?;
+
+        let a = Header::from_block(&buffers.header);
+        let b = Header::from_block(&buffers.buffer[0]);
+        let results = [a.validate(), b.validate()];
+        if !results.contains(&Ok(())) && results.contains(&Err(HeaderError::Version)) {
+            return Err(Error::UnsupportedVersion);
+        }
+
+        // Prefer intact copies, then damaged copies of the current version, then
+        // the newest
+        let rank = |header: &Header| match header.validate() {
+            Ok(()) => Some(true),
+            Err(HeaderError::Checksum) => Some(false),
+            Err(_) => None,
+        };
+        let use_b = match rank(b).cmp(&rank(a)) {
+            Ordering::Greater => true,
+            Ordering::Less => false,
+            Ordering::Equal => crate::is_newer(b.generation(), a.generation()),
+        };
+        let old = if use_b { b } else { a };
+        let old = rank(old).map(|_| *old);
+
+        // A damaged size cannot be trusted beyond the device
+        let device_blocks = crate::filesystem_blocks(&mut device).await?;
+        let blocks = old
+            .map(|old| old.blocks())
+            .filter(|blocks| (DATA_START..=device_blocks).contains(blocks))
+            .unwrap_or(device_blocks);
+
+        // The rebuilt header goes over the other copy, keeping the one it was
+        // salvaged from until it is written
+        let mut generation = old.map_or(0, |old| old.generation());
+        if generation % 2 != u32::from(use_b) {
+            generation = generation.wrapping_add(1);
+        }
+        let header = Header::from_block_mut(&mut buffers.header);
+        header.init(blocks);
+        header.set_generation(generation);
+
+        let mut fs = Self::with_buffers(device, buffers, blocks);
+        fs.bitmap.clear(&mut fs.device).await?;
+
+        let mut report = Report::default();
+        for model in old.iter().flat_map(Header::all_models) {
+            let position = old.map_or(0, |old| old.position(model.id()));
+            let count = match fs.salvage(model, position).await? {
+                Ok(()) => &mut report.recovered,
+                Err(Problem::OutOfRange) => &mut report.out_of_range,
+                Err(Problem::DuplicateId) => &mut report.duplicate_ids,
+                Err(Problem::Unnamed) => &mut report.unnamed,
+                Err(Problem::Overlapping) => &mut report.overlapping,
+            };
+            *count += 1;
+        }
+
+        // Close any gaps left by dropped models
+        Header::from_block_mut(fs.header).normalize_positions();
+
+        // The models take priority over the config, which is dropped if they
+        // overlap its region. A legacy config is moved to a new region below.
+        match old.and_then(|old| old.config()) {
+            Some(start) if fs.claim_region(start, config::REGION_BLOCKS).await? => {
+                Header::from_block_mut(fs.header).set_config(start);
+                fs.config_generation = fs.read_config_generation().await?;
+            }
+            Some(_) => loog::warn!("dropping the config, as its region is damaged"),
+            None => fs.config_generation = fs.read_config_generation().await?,
+        }
+
+        // Then come the named files, which are all dropped if the directory
+        // region is taken
+        if let Some(start) = old.and_then(|old| old.directory()) {
+            if fs.claim_region(start, directory::REGION_BLOCKS).await? {
+                Header::from_block_mut(fs.header).set_directory(start);
+                fs.directory_generation = fs.read_directory_generation().await?;
+                fs.salvage_files(&mut report).await?;
+            } else {
+                loog::warn!("dropping the named files, as their directory is damaged");
+            }
+        }
+
+        // Only now is it known which blocks are free for a new config region
+        if Header::from_block(fs.header).config().is_none() {
+            fs.move_config().await?;
+        }
+
+        loog::debug!("fsck: {report:?}");
+
+        fs.bitmap.flush(&mut fs.device).await?;
+        fs.write_header().await?;
+        Ok((fs, report))
+    }
+
+    /// Allocate the `len` block region starting at `start`, if it is entirely
+    /// within the filesystem and free
+    async fn claim_region(&mut self, start: u32, len: u32) -> Result<bool, D::Error> {
+        let region = start..start.saturating_add(len);
+        if region.start < DATA_START || region.end > self.bitmap.data_end() {
+            return Ok(false);
+        }
+
+        for (claimed, block) in region.clone().enumerate() {
+            if !self.bitmap.allocate_at(&mut self.device, block).await? {
+                self.bitmap
+                    .free(&mut self.device, start, claimed as u32)
+                    .await?;
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Check `model` and add it to the header at `position` if it is intact
+    async fn salvage(
+        &mut self,
+        model: &Model,
+        position: u8,
+    ) -> Result<Result<(), Problem>, Error<D::Error>> {
+        let start = model.start();
+        let id = model.id();
+
+        if !(DATA_START..self.bitmap.data_end()).contains(&start) {
+            return Ok(Err(Problem::OutOfRange));
+        }
+
+        let header = Header::from_block(self.header);
+        if header.iter_models().any(|model| model.id() == id) {
+            return Ok(Err(Problem::DuplicateId));
+        }
+
+        let mut view = self
+            .buffer
+            .select_exact(&mut self.device, NAMES_OFFSET, 2)
+            .await?;
+        view.read().await?;
+        let name = Block::as_byte_slice(view.data())[usize::from(id) * MODEL_NAME_BYTES];
+        if !(b' '..=b'~').contains(&name) {
+            return Ok(Err(Problem::Unnamed));
+        }
+
+        let extents = match File::preamble(self, start).await {
+            Ok((_, extents)) => extents,
+            Err(Error::CorruptFile) => return Ok(Err(Problem::OutOfRange)),
+            Err(err) => return Err(err),
+        };
+
+        if !self.claim_extents(&extents).await? {
+            return Ok(Err(Problem::Overlapping));
+        }
+
+        let header = Header::from_block_mut(self.header);
+        header
+            .insert_model(start, id)
+            .expect("ids are unique, so there is a free slot");
+        header.set_position(id, position);
+        Ok(Ok(()))
+    }
+
+    /// Allocate every block of `extents`, unless some are already allocated
+    async fn claim_extents(&mut self, extents: &Extents) -> Result<bool, D::Error> {
+        for (claimed, block) in extents.iter_blocks().enumerate() {
+            if !self.bitmap.allocate_at(&mut self.device, block).await? {
+                // Leave the blocks with whichever file claimed them first
+                for block in extents.iter_blocks().take(claimed) {
+                    self.bitmap.free(&mut self.device, block, 1).await?;
+                }
+
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Claim the blocks of each named file, removing any that are damaged or
+    /// overlap an earlier file from the directory
+    async fn salvage_files(&mut self, report: &mut Report) -> Result<(), Error<D::Error>> {
+        let mut dropped: u32 = 0;
+        for index in 0..MAX_FILES {
+            let entry = self
+                .with_directory(|entries| {
+                    directory::iter(entries).nth(index).map(|(start, _)| start)
+                })
+                .await?;
+            let Some(start) = entry.flatten() else {
+                break;
+            };
+
+            let extents = if (DATA_START..self.bitmap.data_end()).contains(&start) {
+                match File::preamble(self, start).await {
+                    Ok((_, extents)) => Some(extents),
+                    Err(Error::CorruptFile) => None,
+                    Err(err) => return Err(err),
+                }
+            } else {
+                None
+            };
+
+            match extents {
+                Some(extents) if self.claim_extents(&extents).await? => {
+                    report.recovered_files += 1;
+                }
+                _ => {
+                    dropped |= 1 << index;
+                    report.dropped_files += 1;
+                }
+            }
+        }
+
+        if dropped != 0 {
+            self.edit_directory(|entries, mut len| {
+                for index in (0..MAX_FILES).rev() {
+                    if dropped & (1 << index) != 0 {
+                        len = directory::remove(entries, len, index);
+                    }
+                }
+                len
+            })
+            .await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_io_async::Write as _;
+
+    use super::*;
+    use crate::{HEADER_BLOCKS, Mock};
+
+    const WORDS: usize = BLOCK_BYTES / 4;
+
+    /// Format a filesystem with one model per name, each holding `blocks`
+    /// blocks of data
+    async fn populate<const LEN: usize>(names: &[&str], blocks: usize) -> Mock<LEN> {
+        let mut mock = Mock::new();
+        let mut buffers = Buffers::new();
+        let mut fs = Filesystem::new_empty(&mut mock, &mut buffers)
+            .await
+            .unwrap();
+
+        for name in names {
+            let mut file = fs.new_model(name).await.unwrap();
+            for _ in 0..blocks {
+                file.write_all(&[0xAA; BLOCK_BYTES]).await.unwrap();
+            }
+            file.close().await.unwrap();
+        }
+
+        mock
+    }
+
+    /// Edit the newest header copy, then break the checksums of both
+    fn corrupt<const LEN: usize>(mock: &mut Mock<LEN>, f: impl FnOnce(&mut [u32; WORDS])) {
+        let mut copies = HEADER_BLOCKS.map(|block| {
+            bytemuck::pod_read_unaligned::<[u32; WORDS]>(&mock.blocks()[block as usize])
+        });
+
+        // The generation follows the model table and size
+        let generation = |words: &[u32; WORDS]| u32::from_le(words[66]);
+        let [a, b] = &copies;
+        let newest = usize::from(crate::is_newer(generation(b), generation(a)));
+        f(&mut copies[newest]);
+
+        for (block, mut words) in HEADER_BLOCKS.into_iter().zip(copies) {
+            words[WORDS - 1] ^= 1;
+            mock.block_mut(block as usize)
+                .copy_from_slice(bytemuck::bytes_of(&words));
+        }
+    }
+
+    fn model(start: u32, id: u8) -> u32 {
+        (u32::from(id) << 24) | start
+    }
+
+    async fn allocated<D: BlockDevice<BLOCK_BYTES>>(
+        fs: &mut Filesystem<'_, D>,
+        block: u32,
+    ) -> bool {
+        fs.bitmap.is_allocated(&mut fs.device, block).await.unwrap()
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn recovers_intact_models() {
+        let mut mock = populate::<32>(&["A", "B", "C"], 2).await;
+        corrupt(&mut mock, |_| {});
+
+        let mut buffers = Buffers::new();
+        assert!(matches!(
+            Filesystem::new(&mut mock, &mut buffers).await,
+            Err(crate::InitError::HeaderError {
+                kind: HeaderError::Checksum,
+                ..
+            })
+        ));
+
+        let (mut fs, report) = Filesystem::fsck(&mut mock, &mut buffers).await.unwrap();
+        assert_eq!(report.recovered, 3);
+        assert_eq!(report.dropped(), 0);
+        assert!(fs.model(2).await.unwrap().is_some());
+
+        let mut buffers = Buffers::new();
+        Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn drops_bad_models() {
+        let mut mock = populate::<32>(&["A", "B", "C", "D"], 1).await;
+        // Each model takes two blocks, the preamble plus one full block of data:
+        // A at 6, B at 8, C at 10, and D at 12
+        corrupt(&mut mock, |words| {
+            let models = &mut words[1..];
+            models[0] = model(DATA_START, 0).to_le();
+            models[1] = model(1000, 1).to_le();
+            models[2] = model(DATA_START + 2, 0).to_le();
+            models[3] = model(DATA_START + 4, 2).to_le();
+            models[4] = model(DATA_START + 4, 3).to_le();
+            models[5] = model(DATA_START + 6, 9).to_le();
+        });
+
+        let mut buffers = Buffers::new();
+        let (mut fs, report) = Filesystem::fsck(&mut mock, &mut buffers).await.unwrap();
+        assert_eq!(
+            report,
+            Report {
+                recovered: 2,
+                out_of_range: 1,
+                duplicate_ids: 1,
+                unnamed: 1,
+                overlapping: 1,
+                ..Report::default()
+            }
+        );
+
+        let header = Header::from_block(fs.header);
+        let ids: std::vec::Vec<_> = header.iter_models().map(Model::id).collect();
+        assert_eq!(ids, [0, 2]);
+        assert!(allocated(&mut fs, DATA_START + 1).await);
+        assert!(!allocated(&mut fs, DATA_START + 2).await);
+        assert!(allocated(&mut fs, DATA_START + 5).await);
+        assert!(!allocated(&mut fs, DATA_START + 6).await);
+
+        let file = fs.new_model("E").await.unwrap();
+        file.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn config() {
+        // The config region pointer follows the model positions
+        const CONFIG_WORD: usize = 83;
+
+        let with_config = async || {
+            let mut mock = populate::<32>(&["A", "B"], 1).await;
+            let mut buffers = Buffers::new();
+            let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+            fs.write_config(b"config").await.unwrap();
+            mock
+        };
+
+        let mut buf = [0; 16];
+        let mut mock = with_config().await;
+        corrupt(&mut mock, |_| {});
+        {
+            let mut buffers = Buffers::new();
+            let (mut fs, _) = Filesystem::fsck(&mut mock, &mut buffers).await.unwrap();
+            assert_eq!(fs.read_config(&mut buf).await.unwrap(), b"config");
+        }
+
+        // A region overlapping a model is dropped along with the config
+        let mut mock = with_config().await;
+        corrupt(&mut mock, |words| words[CONFIG_WORD] = DATA_START.to_le());
+        let mut buffers = Buffers::new();
+        let (mut fs, report) = Filesystem::fsck(&mut mock, &mut buffers).await.unwrap();
+        assert_eq!(report.recovered, 2);
+        assert_eq!(fs.read_config(&mut buf).await.unwrap(), b"");
+        let config = Header::from_block(fs.header).config().unwrap();
+        assert!(config >= DATA_START + 4);
+        assert!(allocated(&mut fs, config).await);
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn named_files() {
+        let mut mock = populate::<32>(&["A", "B"], 1).await;
+        let damaged = {
+            let mut buffers = Buffers::new();
+            let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+            for name in ["a", "b", "c"] {
+                let mut file = fs.create_file(name).await.unwrap();
+                file.write_all(&[0x55; BLOCK_BYTES]).await.unwrap();
+                file.close().await.unwrap();
+            }
+
+            let (_, start) = fs.find_file(b"b").await.unwrap().unwrap();
+            start
+        };
+        mock.block_mut(damaged as usize).fill(0xFF);
+        corrupt(&mut mock, |_| {});
+
+        let mut buffers = Buffers::new();
+        let (mut fs, report) = Filesystem::fsck(&mut mock, &mut buffers).await.unwrap();
+        assert_eq!(report.recovered, 2);
+        assert_eq!(report.recovered_files, 2);
+        assert_eq!(report.dropped_files, 1);
+
+        let mut names = std::vec::Vec::new();
+        fs.file_names(|name| names.push(std::string::String::from(name)))
+            .await
+            .unwrap();
+        assert_eq!(names, ["a", "c"]);
+
+        let directory = Header::from_block(fs.header).directory().unwrap();
+        assert!(allocated(&mut fs, directory).await);
+        assert!(!allocated(&mut fs, damaged).await);
+        for name in ["a", "c"] {
+            let (_, start) = fs.find_file(name.as_bytes()).await.unwrap().unwrap();
+            assert!(allocated(&mut fs, start).await);
+            assert!(allocated(&mut fs, start + 1).await);
+        }
+
+        let mut file = fs.file("c").await.unwrap().unwrap();
+        assert_eq!(file.len(), BLOCK_BYTES as u64);
+        file.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn other_version() {
+        let mut mock = populate::<32>(&["A", "B"], 1).await;
+        corrupt(&mut mock, |words| words[0] = 0xFF);
+        let before = *mock.blocks();
+
+        let mut buffers = Buffers::new();
+        assert!(matches!(
+            Filesystem::fsck(&mut mock, &mut buffers).await,
+            Err(Error::UnsupportedVersion)
+        ));
+        assert!(mock.blocks() == &before);
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn nothing_to_salvage() {
+        let mut mock = Mock::<16>::new();
+
+        let mut buffers = Buffers::new();
+        let (_, report) = Filesystem::fsck(&mut mock, &mut buffers).await.unwrap();
+        assert_eq!(report, Report::default());
+
+        let mut buffers = Buffers::new();
+        Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+    }
+}
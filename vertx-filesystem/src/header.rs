@@ -6,9 +6,14 @@ use crc::Crc;
 #[cfg(feature = "defmt")]
 use loog::defmt;
 
-use crate::{BLOCK_BYTES, Block, MAX_MODELS, MODEL_BLOCKS, MODELS_START};
+use crate::{BLOCK_BYTES, Block, DATA_START, HEADER_BLOCKS, MAX_MODELS};
 
-static CRC: Crc<u32> = Crc::<u32>::new(&crc::CRC_32_CKSUM);
+pub(crate) static CRC: Crc<u32> = Crc::<u32>::new(&crc::CRC_32_CKSUM);
+
+const VERSION: u8 = 4;
+/// Headers from before the config had a region of its own, which are still
+/// mounted so the config can be moved
+const LEGACY_VERSION: u8 = 3;
 
 #[derive(Clone, Copy, Pod, Zeroable)]
 #[repr(C)]
@@ -16,7 +21,24 @@ pub(super) struct Header {
     version: u8,
     _padding0: [u8; 3],
     models: [Model; MAX_MODELS],
-    _padding1: [u8; 248],
+    /// Size of the filesystem in blocks, including the allocation bitmap at
+    /// the end
+    blocks: u32,
+    /// Incremented on every write; the valid copy with the highest generation
+    /// is current
+    generation: u32,
+    /// Where each model is listed relative to the others, indexed by ID. All
+    /// 0 in headers written before this existed, in which case slot order
+    /// breaks the tie.
+    positions: [u8; MAX_MODELS],
+    /// First block of the config region, or 0 if the config is still in the
+    /// single block copies at [`CONFIG_BLOCKS`](crate::CONFIG_BLOCKS)
+    config: u32,
+    /// First block of the directory region, or 0 if no named file has been
+    /// created yet. Only ever 0 in headers written before this existed.
+    directory: u32,
+    _padding1: [u8; 168],
+    /// Covers everything before it
     checksum: u32,
 }
 
@@ -25,8 +47,9 @@ bitfield::bitfield! {
     #[repr(transparent)]
     pub(crate) struct Model(u32);
     impl Debug;
+    /// First block of the model file
     pub(crate) start, set_start: 19, 0;
-    pub(crate) u8, len, set_len: 23, 20;
+    // unused: 23, 20;
     pub(crate) u8, id, set_id: 29, 24;
     // unused: 31, 30;
 }
@@ -52,16 +75,13 @@ impl Header {
     }
 
     pub(crate) fn checksum(&self) -> u32 {
-        let mut crc = CRC.digest();
-        crc.update(&[self.version]);
-        crc.update(bytemuck::must_cast_slice(&self.models));
-        crc.finalize()
+        CRC.checksum(&bytemuck::bytes_of(self)[..mem::offset_of!(Self, checksum)])
     }
 
     pub(crate) fn validate(&self) -> Result<(), Error> {
         if self.version == 0 {
             Err(Error::Missing)
-        } else if self.version != 1 {
+        } else if self.version != VERSION && self.version != LEGACY_VERSION {
             Err(Error::Version)
         } else if u32::from_le(self.checksum) != self.checksum() {
             Err(Error::Checksum)
@@ -70,28 +90,96 @@ impl Header {
         }
     }
 
-    pub(crate) fn init(&mut self) {
-        self.version = 1;
+    pub(crate) fn init(&mut self, blocks: u32) {
+        *self = Self::zeroed();
+        self.version = VERSION;
+        self.blocks = blocks.to_le();
         // NB: checksum will be updated before writing
     }
 
+    pub(crate) fn update_checksum(&mut self) {
+        self.checksum = self.checksum().to_le();
+    }
+
+    pub(crate) fn blocks(&self) -> u32 {
+        u32::from_le(self.blocks)
+    }
+
+    /// First block of the config region, if it has one
+    pub(crate) fn config(&self) -> Option<u32> {
+        let start = u32::from_le(self.config);
+        (start != 0).then_some(start)
+    }
+
+    /// Move the config to the region starting at `start`, upgrading a legacy
+    /// header to the current version
+    pub(crate) fn set_config(&mut self, start: u32) {
+        debug_assert!(start >= DATA_START);
+        self.version = VERSION;
+        self.config = start.to_le();
+    }
+
+    /// First block of the directory region, if it has one
+    pub(crate) fn directory(&self) -> Option<u32> {
+        let start = u32::from_le(self.directory);
+        (start != 0).then_some(start)
+    }
+
+    pub(crate) fn set_directory(&mut self, start: u32) {
+        debug_assert!(start >= DATA_START);
+        self.directory = start.to_le();
+    }
+
+    /// Turn this into a header from before the config had its own region
+    #[cfg(test)]
+    pub(crate) fn make_legacy(&mut self) {
+        self.version = LEGACY_VERSION;
+        self.config = 0;
+    }
+
+    pub(crate) fn generation(&self) -> u32 {
+        u32::from_le(self.generation)
+    }
+
+    pub(crate) fn set_generation(&mut self, generation: u32) {
+        self.generation = generation.to_le();
+    }
+
+    /// Which of [`HEADER_BLOCKS`] this copy belongs in
+    pub(crate) fn block(&self) -> u32 {
+        HEADER_BLOCKS[(self.generation() % 2) as usize]
+    }
+
     pub(crate) fn iter_models(&self) -> impl Iterator<Item = &Model> {
         self.models.iter().take_while(|model| !model.is_empty())
     }
 
-    #[expect(clippy::manual_inspect)]
-    pub(crate) fn new_model(&mut self) -> Option<&mut Model> {
+    /// Every non-empty model slot, even past a gap in a damaged table
+    pub(crate) fn all_models(&self) -> impl Iterator<Item = &Model> {
+        self.models.iter().filter(|model| !model.is_empty())
+    }
+
+    /// Add a model whose file starts at block `start`
+    pub(crate) fn new_model(&mut self, start: u32) -> Option<&mut Model> {
         let mut used: IdSet = 0;
         for model in self.iter_models() {
-            used &= 1 << IdSet::from(model.id());
+            used |= 1 << IdSet::from(model.id());
         }
 
         loog::trace!("used model ids: {used=u64:b}");
 
         let id = next_id(used)?;
+        self.positions[usize::from(id)] = self.iter_models().count() as u8;
+        self.insert_model(start, id)
+    }
+
+    /// Add a model with a specific `id`, which must not be in use already
+    #[expect(clippy::manual_inspect)]
+    pub(crate) fn insert_model(&mut self, start: u32, id: u8) -> Option<&mut Model> {
+        debug_assert!(self.iter_models().all(|model| model.id() != id));
+
         self.models.iter_mut().find(|m| m.is_empty()).map(|model| {
-            model.set_start(u32::from(id) * MODEL_BLOCKS + MODELS_START);
-            model.set_len(MODEL_BLOCKS as u8);
+            model.set_start(start);
             model.set_id(id);
             model
         })
@@ -112,6 +200,75 @@ impl Header {
 
         self.models.copy_within((index + 1)..end, index);
         self.models[end - 1] = Model::EMPTY;
+        self.normalize_positions();
+    }
+
+    pub(crate) fn position(&self, id: u8) -> u8 {
+        self.positions[usize::from(id)]
+    }
+
+    pub(crate) fn set_position(&mut self, id: u8, position: u8) {
+        self.positions[usize::from(id)] = position;
+    }
+
+    /// Models in the order they should be listed
+    pub(crate) fn ordered_models(&self) -> impl Iterator<Item = &Model> {
+        let (order, len) = self.order();
+        order
+            .into_iter()
+            .take(len)
+            .map(|index| &self.models[usize::from(index)])
+    }
+
+    /// Move model `id` to `position` in the listing order, shifting the models
+    /// in between. Returns `false` if there is no such model.
+    pub(crate) fn move_model(&mut self, id: u8, position: u8) -> bool {
+        let (mut order, len) = self.order();
+        let order = &mut order[..len];
+        let Some(from) = order
+            .iter()
+            .position(|&index| self.models[usize::from(index)].id() == id)
+        else {
+            return false;
+        };
+
+        let to = usize::from(position).min(len - 1);
+        if from < to {
+            order[from..=to].rotate_left(1);
+        } else {
+            order[to..=from].rotate_right(1);
+        }
+
+        self.set_positions(order);
+        true
+    }
+
+    /// Renumber the positions to `0..n`, keeping the current order
+    pub(crate) fn normalize_positions(&mut self) {
+        let (order, len) = self.order();
+        self.set_positions(&order[..len]);
+    }
+
+    /// Slots of the models sorted by position, and how many there are
+    fn order(&self) -> ([u8; MAX_MODELS], usize) {
+        let mut order = [0; MAX_MODELS];
+        for (slot, index) in order.iter_mut().enumerate() {
+            *index = slot as u8;
+        }
+
+        let len = self.iter_models().count();
+        order[..len].sort_unstable_by_key(|&index| {
+            let model = &self.models[usize::from(index)];
+            (self.position(model.id()), index)
+        });
+        (order, len)
+    }
+
+    fn set_positions(&mut self, order: &[u8]) {
+        for (position, &index) in order.iter().enumerate() {
+            let id = self.models[usize::from(index)].id();
+            self.set_position(id, position as u8);
+        }
     }
 }
 
@@ -121,10 +278,6 @@ impl Model {
     fn is_empty(&self) -> bool {
         self.start() == 0
     }
-
-    pub(crate) fn end(&self) -> u32 {
-        self.start() + u32::from(self.len())
-    }
 }
 
 type IdSet = u64;
@@ -150,10 +303,9 @@ mod tests {
         x.to_le()
     }
 
-    fn model(start: u32, len: u8, id: u8) -> Model {
+    fn model(start: u32, id: u8) -> Model {
         let mut model = Model::EMPTY;
         model.set_start(start);
-        model.set_len(len);
         model.set_id(id);
         model
     }
@@ -179,7 +331,7 @@ mod tests {
         let mut block = Block::<A1>::new();
         {
             let block = block.as_words_mut();
-            block[0] = le(1);
+            block[0] = le(3);
             block[127] = le(0xDEAD_BEEF);
         }
 
@@ -192,20 +344,87 @@ mod tests {
         let mut block = Block::<A1>::new();
         {
             let block = block.as_words_mut();
-            block[0] = le(1);
-            block[1] = le(0x2A20_000A);
-            block[2] = le(0x3F10_0014);
-            block[3] = le(0x0110_0000);
-            block[127] = le(0x8EB3_FB6B);
+            block[0] = le(3);
+            block[1] = le(0x2A00_000A);
+            block[2] = le(0x3F00_0014);
+            block[3] = le(0x0100_0000);
+            block[65] = le(64);
+            block[66] = le(5);
+            block[127] = le(0x5B86_74B6);
         }
 
         let header = Header::from_block(&block);
         header.validate().unwrap();
+        assert_eq!(header.generation(), 5);
+        assert_eq!(header.block(), HEADER_BLOCKS[1]);
 
         let mut models = header.iter_models();
-        assert_eq!(model(10, 2, 42), *models.next().unwrap());
-        assert_eq!(model(20, 1, 63), *models.next().unwrap());
+        assert_eq!(model(10, 42), *models.next().unwrap());
+        assert_eq!(model(20, 63), *models.next().unwrap());
         assert!(models.next().is_none());
+        assert_eq!(header.blocks(), 64);
+    }
+
+    #[test_log::test]
+    fn config_region() {
+        let mut block = Block::<A1>::new();
+        let header = Header::from_block_mut(&mut block);
+        header.init(64);
+        header.make_legacy();
+        header.update_checksum();
+        header.validate().unwrap();
+        assert_eq!(header.config(), None);
+
+        header.set_config(DATA_START + 8);
+        header.update_checksum();
+        header.validate().unwrap();
+        assert_eq!(header.version, VERSION);
+        assert_eq!(header.config(), Some(DATA_START + 8));
+
+        header.config ^= 1;
+        assert_eq!(header.validate(), Err(Error::Checksum));
+    }
+
+    #[test_log::test]
+    fn ordering() {
+        let mut block = Block::<A1>::new();
+        let header = Header::from_block_mut(&mut block);
+        for start in [10, 20, 30, 40] {
+            header.new_model(start).unwrap();
+        }
+
+        let ids = |header: &Header| {
+            header
+                .ordered_models()
+                .map(Model::id)
+                .collect::<std::vec::Vec<_>>()
+        };
+        assert_eq!(ids(header), [0, 1, 2, 3]);
+
+        assert!(header.move_model(3, 0));
+        assert_eq!(ids(header), [3, 0, 1, 2]);
+        assert!(header.move_model(0, 10));
+        assert_eq!(ids(header), [3, 1, 2, 0]);
+        assert!(!header.move_model(5, 0));
+
+        header.delete_model(1);
+        assert_eq!(ids(header), [3, 2, 0]);
+        assert_eq!(header.position(3), 0);
+        assert_eq!(header.position(0), 2);
+
+        header.new_model(50).unwrap();
+        assert_eq!(ids(header), [3, 2, 0, 4]);
+    }
+
+    #[test_log::test]
+    fn ordering_unset() {
+        let mut block = Block::<A1>::new();
+        let header = Header::from_block_mut(&mut block);
+        header.models[0] = model(10, 5);
+        header.models[1] = model(20, 2);
+
+        let ids: std::vec::Vec<_> = header.ordered_models().map(Model::id).collect();
+        assert_eq!(ids, [5, 2]);
     }
 
     #[test]
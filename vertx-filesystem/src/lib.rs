@@ -1,15 +1,21 @@
-#![expect(unused_variables)]
 #![no_std]
 
 #[cfg(test)]
 extern crate std;
 
+mod bitmap;
 mod block;
 mod buffer;
+mod config;
+mod directory;
+mod extent;
 mod file;
+pub mod flash;
+mod fsck;
 mod header;
 #[cfg(test)]
 mod mock;
+mod v1;
 
 use core::fmt;
 
@@ -18,25 +24,37 @@ use block_device_driver::BlockDevice;
 #[cfg(feature = "defmt")]
 use loog::defmt;
 
+use self::bitmap::Bitmap;
 pub(crate) use self::block::Block;
 pub(crate) use self::buffer::Buffer;
+pub use self::config::MAX_CONFIG_BYTES;
+pub use self::directory::{FILE_NAME_BYTES, MAX_FILES};
+use self::extent::Extents;
 pub use self::file::File;
+pub use self::fsck::Report as FsckReport;
 pub use self::header::Error as HeaderError;
 use self::header::Header;
 #[cfg(test)]
-pub(crate) use self::mock::Mock;
+pub(crate) use self::mock::{Mock, MockFlash};
 
 pub const BLOCK_BYTES: usize = 512;
 /// Number of bytes used to store file lengths, etc
 pub(crate) const LEN_BYTES: usize = 4;
 
-const HEADER_BLOCK: u32 = 0;
+/// Alternating copies of the header, so one is always intact. The second copy
+/// follows the names and config so that those stay where the v1 layout kept
+/// them.
+const HEADER_BLOCKS: [u32; 2] = [0, 4];
 const NAMES_OFFSET: u32 = 1;
-const CONFIG_BLOCK: u32 = 3;
-const MODELS_START: u32 = 4;
+/// Alternating copies of the config, so one is always intact. Only used until
+/// the config is moved to a larger region allocated from the data blocks.
+const CONFIG_BLOCKS: [u32; 2] = [3, 5];
+/// First block available to the allocator
+const DATA_START: u32 = 6;
+/// Extents address blocks with 20 bits, so only the first 512 MiB of larger
+/// devices is used
+const MAX_BLOCKS: u32 = 1 << 20;
 
-/// Fixed number of blocks to allocate for each model file
-pub(crate) const MODEL_BLOCKS: u32 = 4;
 pub(crate) const MODEL_NAME_BYTES: usize = 16;
 /// Chosen to fit 64 * 16 byte model names in blocks 1 & 2
 pub(crate) const MAX_MODELS: usize = 64;
@@ -46,11 +64,21 @@ pub(crate) const MAX_MODELS: usize = 64;
 #[non_exhaustive]
 pub enum Error<I> {
     SeekOutOfBounds,
-    /// The operation would require allocating more backing blocks for the file,
-    /// which is (yet) supported.
+    /// The file is too fragmented to grow any further
     FileFull,
+    /// There are no free blocks left
+    NoSpace,
     TooManyModels,
     ModelNameOverflow,
+    /// The file's length or blocks are damaged
+    CorruptFile,
+    /// The filesystem is in a format this version cannot read
+    UnsupportedVersion,
+    /// The config does not fit in the buffer or the space reserved for it
+    ConfigOverflow,
+    /// The directory has no room for another file
+    TooManyFiles,
+    FileNameOverflow,
     Io(I),
 }
 
@@ -61,8 +89,14 @@ impl<I: fmt::Debug + embedded_io_async::Error> embedded_io_async::Error for Erro
         match self {
             Self::SeekOutOfBounds => ErrorKind::InvalidInput,
             Self::FileFull => ErrorKind::Unsupported,
+            Self::NoSpace => ErrorKind::OutOfMemory,
             Self::TooManyModels => ErrorKind::InvalidData,
             Self::ModelNameOverflow => ErrorKind::InvalidInput,
+            Self::CorruptFile => ErrorKind::InvalidData,
+            Self::UnsupportedVersion => ErrorKind::Unsupported,
+            Self::ConfigOverflow => ErrorKind::InvalidInput,
+            Self::TooManyFiles => ErrorKind::OutOfMemory,
+            Self::FileNameOverflow => ErrorKind::InvalidInput,
             Self::Io(err) => err.kind(),
         }
     }
@@ -87,10 +121,16 @@ pub struct Filesystem<'buf, D: BlockDevice<BLOCK_BYTES>> {
     device: D,
     header: &'buf mut Block<D::Align>,
     buffer: Buffer<'buf, D::Align, 2>,
+    bitmap: Bitmap<'buf, D::Align>,
+    /// Generation of the current config copy, or 0 if there is none
+    config_generation: u32,
+    /// Generation of the current directory copy, or 0 if there is none
+    directory_generation: u32,
 }
 
 pub struct Buffers<A> {
     header: Block<A>,
+    bitmap: Block<A>,
     buffer: [Block<A>; 2],
 }
 
@@ -98,6 +138,7 @@ impl<A: aligned::Alignment> Buffers<A> {
     pub const fn new() -> Self {
         Self {
             header: Block::new(),
+            bitmap: Block::new(),
             buffer: [const { Block::new() }; 2],
         }
     }
@@ -110,71 +151,312 @@ impl<A: aligned::Alignment> Default for Buffers<A> {
 }
 
 impl<'buf, D: BlockDevice<BLOCK_BYTES>> Filesystem<'buf, D> {
+    /// Mount the filesystem on `device`. Filesystems in the original v1 layout
+    /// are imported first, and a config still in the single block copies is
+    /// moved to a region of its own.
     pub async fn new(
         mut device: D,
         buffers: &'buf mut Buffers<D::Align>,
     ) -> Result<Self, InitError<'buf, D>> {
-        device
-            .read(HEADER_BLOCK, buffers.header.as_aligned_mut())
+        read_headers(&mut device, buffers)
             .await
             .map_err(InitError::Io)?;
 
-        let header = Header::from_block(&buffers.header);
-        if let Err(error) = header.validate() {
-            return Err(InitError::HeaderError {
-                kind: error,
-                device,
-                buffers,
-            });
+        // After an import, the v1 header stays in the first copy until the
+        // header is next written
+        let v1 = v1::Header::from_block(&buffers.header).is_valid();
+        let a = Header::from_block(&buffers.header);
+        let b = Header::from_block(&buffers.buffer[0]);
+        let use_b = match (a.validate(), b.validate()) {
+            (Ok(()), Ok(())) => is_newer(b.generation(), a.generation()),
+            (Ok(()), Err(err)) => {
+                loog::warn!("Second header copy is invalid ({err:?})");
+                false
+            }
+            (Err(err), Ok(())) => {
+                if err != HeaderError::Missing && !v1 {
+                    loog::warn!("First header copy is invalid ({err:?})");
+                }
+                true
+            }
+            (Err(_), Err(_)) if v1 => return Self::import_v1(device, buffers).await,
+            (Err(a), Err(b)) => {
+                // A copy from another format takes priority, since repairing
+                // it would throw away whatever that format holds
+                let kind = match (a, b) {
+                    (HeaderError::Version, _) | (_, HeaderError::Version) => HeaderError::Version,
+                    (HeaderError::Missing, b) => b,
+                    (a, _) => a,
+                };
+                return Err(InitError::HeaderError {
+                    kind,
+                    device,
+                    buffers,
+                });
+            }
+        };
+
+        if use_b {
+            *buffers.header.as_words_mut() = *buffers.buffer[0].as_words();
         }
 
-        let Buffers { header, buffer } = buffers;
-        Ok(Self {
-            device,
-            header,
-            buffer: Buffer::new(buffer),
-        })
+        let blocks = Header::from_block(&buffers.header).blocks();
+        let mut fs = Self::with_buffers(device, buffers, blocks);
+        fs.config_generation = fs.read_config_generation().await.map_err(InitError::Io)?;
+        if v1 && fs.config_generation == 0 {
+            loog::info!("finishing v1 import");
+            fs.import_v1_config().await.map_err(InitError::Io)?;
+        }
+
+        fs.upgrade_config().await.map_err(InitError::Io)?;
+        fs.directory_generation = fs
+            .read_directory_generation()
+            .await
+            .map_err(InitError::Io)?;
+        Ok(fs)
+    }
+
+    /// Format `device` with an empty filesystem
+    pub async fn new_empty(
+        mut device: D,
+        buffers: &'buf mut Buffers<D::Align>,
+    ) -> Result<Self, D::Error> {
+        // Wipe the metadata, including both header and config copies, so stale
+        // copies cannot outrank the new ones
+        let blank = &mut buffers.buffer[0];
+        *blank = Block::new();
+        for block in HEADER_BLOCKS[0]..DATA_START {
+            device.write(block, blank.as_aligned()).await?;
+        }
+
+        let blocks = filesystem_blocks(&mut device).await?;
+        Header::from_block_mut(&mut buffers.header).init(blocks);
+
+        let mut fs = Self::with_buffers(device, buffers, blocks);
+        fs.bitmap.clear(&mut fs.device).await?;
+        fs.move_config().await?;
+        fs.write_header().await?;
+        Ok(fs)
     }
 
-    pub fn new_empty(device: D, buffers: &'buf mut Buffers<D::Align>) -> Self {
-        let header = Header::from_block_mut(&mut buffers.header);
-        header.init();
+    fn with_buffers(device: D, buffers: &'buf mut Buffers<D::Align>, blocks: u32) -> Self {
+        let Buffers {
+            header,
+            bitmap,
+            buffer,
+        } = buffers;
 
-        let Buffers { header, buffer } = buffers;
         Self {
             device,
             header,
             buffer: Buffer::new(buffer),
+            bitmap: Bitmap::new(bitmap, blocks),
+            config_generation: 0,
+            directory_generation: 0,
         }
     }
 
+    /// Validate both header copies without mounting, returning the generation
+    /// of each intact copy
+    pub async fn check_headers(
+        device: &mut D,
+        buffers: &mut Buffers<D::Align>,
+    ) -> Result<[Result<u32, HeaderError>; 2], D::Error> {
+        read_headers(device, buffers).await?;
+        Ok([&buffers.header, &buffers.buffer[0]].map(|block| {
+            let header = Header::from_block(block);
+            header.validate().map(|()| header.generation())
+        }))
+    }
+
+    /// Read the config into `buf`, failing with [`Error::ConfigOverflow`] if it
+    /// does not fit. A damaged config reads as empty.
     pub async fn read_config<'a>(
         &mut self,
         buf: &'a mut [u8],
     ) -> Result<&'a [u8], Error<D::Error>> {
-        debug_assert!(buf.len() <= (BLOCK_BYTES - LEN_BYTES));
+        if self.config_generation == 0 {
+            return Ok(&[]);
+        }
 
-        let mut view = self.buffer.select(&mut self.device, CONFIG_BLOCK).await?;
-        view.read().await?;
-        let data = read_slice(view.data());
+        let copy = self.config_generation % 2;
+        let preamble = self.config_preamble(copy).await?;
+        let Some(out) = buf.get_mut(..preamble.len()) else {
+            return Err(Error::ConfigOverflow);
+        };
 
-        let out = &mut buf[0..data.len()];
-        out.copy_from_slice(data);
-        Ok(out)
+        let intact = self
+            .read_config_copy(copy, &preamble, |offset, chunk| {
+                out[offset..][..chunk.len()].copy_from_slice(chunk);
+            })
+            .await?;
+        Ok(if intact { out } else { &[] })
     }
 
+    /// Write `config` over the older copy, leaving the current one intact until
+    /// this succeeds. Fails with [`Error::ConfigOverflow`] if it is longer than
+    /// [`MAX_CONFIG_BYTES`], or than the single block copies allow if there was
+    /// no room to move the config out of them.
     pub async fn write_config(&mut self, config: &[u8]) -> Result<(), Error<D::Error>> {
-        debug_assert!(config.len() <= (BLOCK_BYTES - LEN_BYTES));
+        let (_, max) = self.config_copy(0);
+        if config.len() > max {
+            return Err(Error::ConfigOverflow);
+        }
+
+        Ok(self.write_config_copy(config).await?)
+    }
 
-        let total_len = config.len() + LEN_BYTES;
-        let mut view = self.buffer.select(&mut self.device, CONFIG_BLOCK).await?;
-        write_slice(view.data_mut(), config);
-        view.mark_modified(0, total_len);
+    async fn write_config_copy(&mut self, config: &[u8]) -> Result<(), D::Error> {
+        let generation = self.config_generation.wrapping_add(1);
+        let (start, _) = self.config_copy(generation % 2);
+        let preamble = config::Preamble::new(generation, config);
 
+        let mut block = start;
+        let mut offset = config::PREAMBLE_BYTES;
+        let mut rest = config;
+        loop {
+            let mut view = self.buffer.select(&mut self.device, block).await?;
+            let data = &mut Block::as_byte_slice_mut(view.data_mut())[..BLOCK_BYTES];
+            if block == start {
+                preamble.write(data);
+            }
+
+            let (chunk, tail) = rest.split_at(rest.len().min(BLOCK_BYTES - offset));
+            data[offset..][..chunk.len()].copy_from_slice(chunk);
+            view.mark_modified(0, offset + chunk.len());
+
+            rest = tail;
+            if rest.is_empty() {
+                break;
+            }
+            block += 1;
+            offset = 0;
+        }
+
+        // Only move on to the next copy once this one is safely written
+        self.buffer.flush(&mut self.device).await?;
+        self.config_generation = generation;
         Ok(())
     }
 
-    pub async fn model_names<F>(&mut self, f: F) -> Result<(), Error<D::Error>>
+    /// First block of config copy `copy`, and the most it can hold
+    fn config_copy(&self, copy: u32) -> (u32, usize) {
+        match Header::from_block(self.header).config() {
+            Some(start) => (start + copy * config::COPY_BLOCKS, config::MAX_CONFIG_BYTES),
+            None => (CONFIG_BLOCKS[copy as usize], config::LEGACY_MAX_BYTES),
+        }
+    }
+
+    async fn config_preamble(&mut self, copy: u32) -> Result<config::Preamble, D::Error> {
+        let (start, _) = self.config_copy(copy);
+        let mut view = self.buffer.select(&mut self.device, start).await?;
+        view.read().await?;
+        Ok(config::Preamble::read(Block::as_byte_slice(view.data())))
+    }
+
+    /// Pass config copy `copy` to `f` one chunk at a time, along with the
+    /// offset of each, and check it against `preamble`. Returns whether it is
+    /// intact.
+    async fn read_config_copy(
+        &mut self,
+        copy: u32,
+        preamble: &config::Preamble,
+        mut f: impl FnMut(usize, &[u8]),
+    ) -> Result<bool, D::Error> {
+        let (mut block, max) = self.config_copy(copy);
+        let len = preamble.len();
+        if len > max {
+            return Ok(false);
+        }
+
+        let mut checksum = preamble.checksum();
+        let mut skip = config::PREAMBLE_BYTES;
+        let mut offset = 0;
+        loop {
+            let mut view = self.buffer.select(&mut self.device, block).await?;
+            view.read().await?;
+            let data = &Block::as_byte_slice(view.data())[skip..BLOCK_BYTES];
+            let chunk = &data[..data.len().min(len - offset)];
+            checksum.update(chunk);
+            f(offset, chunk);
+
+            offset += chunk.len();
+            if offset == len {
+                return Ok(checksum.matches());
+            }
+            block += 1;
+            skip = 0;
+        }
+    }
+
+    /// Generation of the current config copy, or 0 if there is none
+    pub(crate) async fn read_config_generation(&mut self) -> Result<u32, D::Error> {
+        let mut current = None;
+        for copy in 0..2 {
+            let preamble = self.config_preamble(copy).await?;
+            if self.read_config_copy(copy, &preamble, |_, _| {}).await? {
+                current = newest(current, Some((preamble.generation(), ())));
+            }
+        }
+
+        Ok(current.map_or(0, |(generation, ())| generation))
+    }
+
+    /// Move the config out of the single block copies, if it is still in them
+    /// and there is room
+    pub(crate) async fn upgrade_config(&mut self) -> Result<(), D::Error> {
+        if Header::from_block(self.header).config().is_none() && self.move_config().await? {
+            self.write_header().await?;
+        }
+        Ok(())
+    }
+
+    /// Give the config a region of its own, copying over the current config
+    /// from the single block copies. The header needs to be written for this to
+    /// take effect, so an interrupted move only leaks the region. Returns
+    /// `false` without changing anything if there is no room.
+    async fn move_config(&mut self) -> Result<bool, D::Error> {
+        let mut config = [0; config::LEGACY_MAX_BYTES];
+        let len = match self.read_config(&mut config).await {
+            Ok(config) => config.len(),
+            Err(Error::Io(err)) => return Err(err),
+            Err(_) => {
+                loog::warn!("dropping config that is too long to move");
+                0
+            }
+        };
+
+        let region = self
+            .bitmap
+            .allocate_run(&mut self.device, config::REGION_BLOCKS)
+            .await?;
+        let Some(start) = region else {
+            loog::warn!(
+                "no room for a config region; the config is limited to {=usize} bytes",
+                config::LEGACY_MAX_BYTES
+            );
+            return Ok(false);
+        };
+        loog::info!(
+            "moving config to blocks {start=u32}..{=u32}",
+            start + config::REGION_BLOCKS
+        );
+        self.bitmap.flush(&mut self.device).await?;
+
+        Header::from_block_mut(self.header).set_config(start);
+
+        // Whatever the region held before must not be mistaken for a newer copy
+        let (other, _) = self.config_copy(self.config_generation % 2);
+        let mut view = self.buffer.select(&mut self.device, other).await?;
+        view.data_mut()[0] = Block::new();
+        view.mark_modified(0, BLOCK_BYTES);
+
+        self.write_config_copy(&config[..len]).await?;
+        Ok(true)
+    }
+
+    /// Call `f` with the ID and name of each model, in listing order
+    pub async fn model_names<F>(&mut self, mut f: F) -> Result<(), Error<D::Error>>
     where
         F: FnMut(u8, &str),
     {
@@ -185,11 +467,7 @@ impl<'buf, D: BlockDevice<BLOCK_BYTES>> Filesystem<'buf, D> {
         view.read().await?;
         let names = Block::as_byte_slice(view.data());
 
-        for model in Header::from_block(self.header).iter_models() {
-            if model.start() == 0 {
-                break;
-            }
-
+        for model in Header::from_block(self.header).ordered_models() {
             let offset = usize::from(model.id()) * MODEL_NAME_BYTES;
             let name = &names[offset..(offset + MODEL_NAME_BYTES)];
             let len = name
@@ -199,6 +477,7 @@ impl<'buf, D: BlockDevice<BLOCK_BYTES>> Filesystem<'buf, D> {
             // SAFETY: name[0..len] is validated to be a subset of ascii, so must be valid
             // UTF-8, too
             let name = unsafe { str::from_utf8_unchecked(&name[0..len]) };
+            f(model.id(), name);
         }
 
         Ok(())
@@ -213,9 +492,7 @@ impl<'buf, D: BlockDevice<BLOCK_BYTES>> Filesystem<'buf, D> {
             return Ok(None);
         };
 
-        debug_assert!(model.start() >= MODELS_START);
-
-        let file = File::open(self, model.start(), model.end()).await?;
+        let file = File::open(self, model.start()).await?;
         Ok(Some(file))
     }
 
@@ -230,50 +507,431 @@ impl<'buf, D: BlockDevice<BLOCK_BYTES>> Filesystem<'buf, D> {
             return Err(Error::ModelNameOverflow);
         }
 
-        let header = Header::from_block_mut(self.header);
-        let Some(model) = header.new_model() else {
+        if Header::from_block(self.header).iter_models().count() == MAX_MODELS {
             return Err(Error::TooManyModels);
-        };
+        }
+
+        let start = self
+            .bitmap
+            .allocate(&mut self.device)
+            .await?
+            .ok_or(Error::NoSpace)?;
+        let header = Header::from_block_mut(self.header);
+        let model = header
+            .new_model(start)
+            .expect("there is a free slot, so there is a free id");
 
         loog::trace!(
-            "allocated new model with id {=u8} at blocks {=u32}..={=u32}",
+            "allocated new model with id {=u8} at block {=u32}",
             model.id(),
-            model.start(),
-            model.end()
+            model.start()
         );
+        let id = model.id();
+        self.write_name(id, name).await?;
+
+        // The block, name, and preamble all need to be on disk before the
+        // header refers to them
+        File::init(self, start).await?;
+        self.flush().await?;
+        self.write_header().await?;
+        File::open(self, start).await
+    }
+
+    pub async fn rename_model(&mut self, id: u8, name: &str) -> Result<(), Error<D::Error>> {
+        loog::trace!("renaming model {id=u8} to {name=str:?}");
+
+        let name = name.as_bytes();
+        if name.len() > MODEL_NAME_BYTES {
+            return Err(Error::ModelNameOverflow);
+        }
+
+        let header = Header::from_block(self.header);
+        if !header.iter_models().any(|model| model.id() == id) {
+            loog::warn!("there is no model with id {id=u8}");
+            return Ok(());
+        }
+
+        self.write_name(id, name).await?;
+        self.flush().await
+    }
+
+    /// Copy the data of model `id` into a new model called `name`, listed
+    /// last. Returns the ID of the copy, or `None` if there is no model `id`.
+    pub async fn copy_model(&mut self, id: u8, name: &str) -> Result<Option<u8>, Error<D::Error>> {
+        loog::trace!("copying model {id=u8} to {name=str:?}");
+
+        let name = name.as_bytes();
+        if name.len() > MODEL_NAME_BYTES {
+            return Err(Error::ModelNameOverflow);
+        }
+
+        let header = Header::from_block(self.header);
+        let Some(model) = header.iter_models().find(|model| model.id() == id) else {
+            loog::warn!("there is no model with id {id=u8}");
+            return Ok(None);
+        };
+        if header.iter_models().count() == MAX_MODELS {
+            return Err(Error::TooManyModels);
+        }
+
+        let extents = File::copy(self, model.start()).await?;
+        let copy = Header::from_block_mut(self.header)
+            .new_model(extents.start())
+            .expect("there is a free slot, so there is a free id")
+            .id();
+        self.write_name(copy, name).await?;
+
+        // The copy and its name need to be on disk before the header refers to
+        // them
+        self.flush().await?;
+        self.write_header().await?;
+        Ok(Some(copy))
+    }
+
+    /// Move model `id` to `position` in the listing order, or to the end if
+    /// `position` is past it
+    pub async fn move_model(&mut self, id: u8, position: u8) -> Result<(), Error<D::Error>> {
+        let header = Header::from_block_mut(self.header);
+        if !header.move_model(id, position) {
+            loog::warn!("there is no model with id {id=u8}");
+            return Ok(());
+        }
+
+        self.write_header().await?;
+        Ok(())
+    }
+
+    pub async fn delete_model(&mut self, id: u8) -> Result<(), Error<D::Error>> {
+        let header = Header::from_block(self.header);
+        let Some(model) = header.iter_models().find(|model| model.id() == id) else {
+            loog::warn!("there is no model with id {id=u8}");
+            return Ok(());
+        };
+
+        let extents = self.extents_to_free(model.start()).await?;
+
+        // Remove the model first, so a power loss can only leak its blocks
+        Header::from_block_mut(self.header).delete_model(id);
+        self.write_header().await?;
+        if let Some(extents) = extents {
+            self.free_extents(&extents).await?;
+            self.bitmap.flush(&mut self.device).await?;
+        }
+        Ok(())
+    }
+
+    /// Call `f` with the name of each file, in the order they were created
+    pub async fn file_names<F>(&mut self, mut f: F) -> Result<(), Error<D::Error>>
+    where
+        F: FnMut(&str),
+    {
+        self.with_directory(|entries| {
+            for (_, name) in directory::iter(entries) {
+                // Names are only ever written from a `&str`, so this can only
+                // fail if the directory is damaged
+                if let Ok(name) = str::from_utf8(name) {
+                    f(name);
+                }
+            }
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn file<'fs>(
+        &'fs mut self,
+        name: &str,
+    ) -> Result<Option<File<'buf, 'fs, D>>, Error<D::Error>> {
+        let Some((_, start)) = self.find_file(name.as_bytes()).await? else {
+            return Ok(None);
+        };
+
+        let file = File::open(self, start).await?;
+        Ok(Some(file))
+    }
+
+    /// Open the file called `name` with the cursor at its end
+    pub async fn append_file<'fs>(
+        &'fs mut self,
+        name: &str,
+    ) -> Result<Option<File<'buf, 'fs, D>>, Error<D::Error>> {
+        let Some((_, start)) = self.find_file(name.as_bytes()).await? else {
+            return Ok(None);
+        };
+
+        let mut file = File::open(self, start).await?;
+        file.seek_end();
+        Ok(Some(file))
+    }
+
+    /// Create an empty file called `name`, truncating the existing file if
+    /// there is one. The directory is allocated along with the first file.
+    pub async fn create_file<'fs>(
+        &'fs mut self,
+        name: &str,
+    ) -> Result<File<'buf, 'fs, D>, Error<D::Error>> {
+        loog::trace!("creating file {name=str:?}");
+
+        let name = name.as_bytes();
+        if name.len() > FILE_NAME_BYTES {
+            return Err(Error::FileNameOverflow);
+        }
+
+        if let Some((_, start)) = self.find_file(name).await? {
+            let mut file = File::open(self, start).await?;
+            file.truncate();
+            return Ok(file);
+        }
+
+        let len = self.with_directory(<[u8]>::len).await?.unwrap_or(0);
+        if len == directory::MAX_BYTES {
+            return Err(Error::TooManyFiles);
+        }
+
+        self.reserve_directory().await?;
+        let start = self
+            .bitmap
+            .allocate(&mut self.device)
+            .await?
+            .ok_or(Error::NoSpace)?;
+        loog::trace!("allocated new file at block {start=u32}");
+
+        // The file needs to be on disk before the directory refers to it, so a
+        // power loss can only leak its block
+        File::init(self, start).await?;
+        self.flush().await?;
+        self.edit_directory(|entries, len| directory::push(entries, len, start, name))
+            .await?;
+        File::open(self, start).await
+    }
+
+    pub async fn delete_file(&mut self, name: &str) -> Result<(), Error<D::Error>> {
+        let Some((index, start)) = self.find_file(name.as_bytes()).await? else {
+            loog::warn!("there is no file called {name=str:?}");
+            return Ok(());
+        };
+
+        let extents = self.extents_to_free(start).await?;
+
+        // Remove the entry first, so a power loss can only leak its blocks
+        self.edit_directory(|entries, len| directory::remove(entries, len, index))
+            .await?;
+        if let Some(extents) = extents {
+            self.free_extents(&extents).await?;
+            self.bitmap.flush(&mut self.device).await?;
+        }
+        Ok(())
+    }
+
+    /// First block of directory copy `copy`, if there is a directory yet
+    fn directory_copy(&self, copy: u32) -> Option<u32> {
+        let start = Header::from_block(self.header).directory()?;
+        Some(start + copy * directory::COPY_BLOCKS)
+    }
+
+    /// Pass the generation and entries of directory copy `copy` to `f`, if it
+    /// is intact
+    async fn read_directory_copy<R>(
+        &mut self,
+        copy: u32,
+        f: impl FnOnce(u32, &[u8]) -> R,
+    ) -> Result<Option<R>, D::Error> {
+        let Some(start) = self.directory_copy(copy) else {
+            return Ok(None);
+        };
 
         let mut view = self
             .buffer
-            .select_exact(&mut self.device, NAMES_OFFSET, 2)
+            .select_exact(&mut self.device, start, directory::COPY_BLOCKS)
             .await?;
         view.read().await?;
-        let names = Block::as_byte_slice_mut(view.data_mut());
-        let name_start = usize::from(model.id()) * MODEL_NAME_BYTES;
-        let name_end = name_start + name.len();
-        names[name_start..name_end].copy_from_slice(name);
-        names[name_end..].fill(0);
+        let data = Block::as_byte_slice(view.data());
+        let preamble = config::Preamble::read(data);
+        let len = preamble.len();
+        if len > directory::MAX_BYTES || !len.is_multiple_of(directory::ENTRY_BYTES) {
+            return Ok(None);
+        }
 
-        let start = model.start();
-        let end = model.end();
-        let file = File::create(self, start, end).await?;
-        Ok(file)
+        let entries = &data[config::PREAMBLE_BYTES..][..len];
+        let mut checksum = preamble.checksum();
+        checksum.update(entries);
+        Ok(checksum
+            .matches()
+            .then(|| f(preamble.generation(), entries)))
     }
 
-    pub async fn delete_model(&mut self, id: u8) -> Result<(), Error<D::Error>> {
-        let header = Header::from_block_mut(self.header);
-        header.delete_model(id);
-        self.write_header().await
+    /// Generation of the current directory copy, or 0 if there is none
+    pub(crate) async fn read_directory_generation(&mut self) -> Result<u32, D::Error> {
+        let mut current = None;
+        for copy in 0..2 {
+            let generation = self
+                .read_directory_copy(copy, |generation, _| (generation, ()))
+                .await?;
+            current = newest(current, generation);
+        }
+
+        Ok(current.map_or(0, |(generation, ())| generation))
+    }
+
+    /// Call `f` with the entries of the current directory copy, if there is one
+    async fn with_directory<R>(
+        &mut self,
+        f: impl FnOnce(&[u8]) -> R,
+    ) -> Result<Option<R>, D::Error> {
+        if self.directory_generation == 0 {
+            return Ok(None);
+        }
+
+        let copy = self.directory_generation % 2;
+        self.read_directory_copy(copy, |_, entries| f(entries))
+            .await
+    }
+
+    /// Index and first block of the file called `name`
+    async fn find_file(&mut self, name: &[u8]) -> Result<Option<(usize, u32)>, D::Error> {
+        let found = self
+            .with_directory(|entries| directory::find(entries, name))
+            .await?;
+        Ok(found.flatten())
+    }
+
+    /// Allocate the directory region if there is none yet. The header is
+    /// written before any file is added, so an interrupted allocation only
+    /// leaks the region.
+    async fn reserve_directory(&mut self) -> Result<(), Error<D::Error>> {
+        if Header::from_block(self.header).directory().is_some() {
+            return Ok(());
+        }
+
+        let start = self
+            .bitmap
+            .allocate_run(&mut self.device, directory::REGION_BLOCKS)
+            .await?
+            .ok_or(Error::NoSpace)?;
+        loog::info!(
+            "allocated directory at blocks {start=u32}..{=u32}",
+            start + directory::REGION_BLOCKS
+        );
+
+        // Whatever the region held before must not be mistaken for a copy
+        for copy in 0..2 {
+            let block = start + copy * directory::COPY_BLOCKS;
+            let mut view = self.buffer.select(&mut self.device, block).await?;
+            view.data_mut()[0] = Block::new();
+            view.mark_modified(0, BLOCK_BYTES);
+        }
+
+        self.flush().await?;
+        Header::from_block_mut(self.header).set_directory(start);
+        self.write_header().await?;
+        self.directory_generation = 0;
+        Ok(())
+    }
+
+    /// Write the directory, with its entries edited by `f`, over the older
+    /// copy. `f` is given the entries and their length, and returns the new
+    /// length.
+    async fn edit_directory<F>(&mut self, f: F) -> Result<(), D::Error>
+    where
+        F: FnOnce(&mut [u8], usize) -> usize,
+    {
+        let start = Header::from_block(self.header)
+            .directory()
+            .expect("the directory is allocated before it is edited");
+        let copy = |generation: u32| start + (generation % 2) * directory::COPY_BLOCKS;
+        let generation = self.directory_generation.wrapping_add(1);
+        let target = copy(generation);
+
+        let current = self.with_directory(<[u8]>::len).await?;
+        let (mut view, len) = match current {
+            Some(len) => {
+                let from = copy(self.directory_generation);
+                let view = self
+                    .buffer
+                    .copy(&mut self.device, from, target, directory::COPY_BLOCKS)
+                    .await?;
+                (view, len)
+            }
+            None => {
+                let mut view = self
+                    .buffer
+                    .select_exact(&mut self.device, target, directory::COPY_BLOCKS)
+                    .await?;
+                view.mark_modified(0, directory::COPY_BLOCKS as usize * BLOCK_BYTES);
+                (view, 0)
+            }
+        };
+
+        let (preamble, entries) =
+            Block::as_byte_slice_mut(view.data_mut()).split_at_mut(config::PREAMBLE_BYTES);
+        let len = f(entries, len);
+        config::Preamble::new(generation, &entries[..len]).write(preamble);
+
+        // Only move on to the next copy once this one is safely written
+        self.buffer.flush(&mut self.device).await?;
+        self.directory_generation = generation;
+        Ok(())
     }
 
     pub async fn flush(&mut self) -> Result<(), Error<D::Error>> {
         self.buffer.flush(&mut self.device).await?;
+        self.bitmap.flush(&mut self.device).await?;
         Ok(())
     }
 
-    async fn write_header(&mut self) -> Result<(), Error<D::Error>> {
-        self.device
-            .write(HEADER_BLOCK, self.header.as_aligned())
+    /// Blocks of the file starting at `start`, for deleting it. `None` if its
+    /// preamble is damaged, in which case the blocks are leaked rather than
+    /// risk freeing ones that belong to another file.
+    async fn extents_to_free(&mut self, start: u32) -> Result<Option<Extents>, Error<D::Error>> {
+        match File::preamble(self, start).await {
+            Ok((_, extents)) => Ok(Some(extents)),
+            Err(Error::CorruptFile) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub(crate) async fn free_extents(&mut self, extents: &Extents) -> Result<(), D::Error> {
+        for extent in extents.iter() {
+            self.bitmap
+                .free(&mut self.device, extent.start(), extent.len())
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Buffer `name` (which must fit) as the name of model `id`
+    async fn write_name(&mut self, id: u8, name: &[u8]) -> Result<(), D::Error> {
+        let mut view = self
+            .buffer
+            .select_exact(&mut self.device, NAMES_OFFSET, 2)
             .await?;
+        view.read().await?;
+        let names = Block::as_byte_slice_mut(view.data_mut());
+        let name_start = usize::from(id) * MODEL_NAME_BYTES;
+        let name_end = name_start + name.len();
+        names[name_start..name_end].copy_from_slice(name);
+        names[name_end..(name_start + MODEL_NAME_BYTES)].fill(0);
+        view.mark_modified(name_start, MODEL_NAME_BYTES);
+        Ok(())
+    }
+
+    /// Write the header over the older copy, leaving the current one intact
+    /// until this succeeds
+    async fn write_header(&mut self) -> Result<(), D::Error> {
+        let header = Header::from_block_mut(self.header);
+        let generation = header.generation();
+        header.set_generation(generation.wrapping_add(1));
+        header.update_checksum();
+        let block = header.block();
+
+        if let Err(err) = self.device.write(block, self.header.as_aligned()).await {
+            // Retry the same copy next time rather than overwriting the current
+            // one
+            Header::from_block_mut(self.header).set_generation(generation);
+            return Err(err);
+        }
+
         Ok(())
     }
 }
@@ -309,37 +967,747 @@ impl<D: BlockDevice<BLOCK_BYTES>> fmt::Debug for Filesystem<'_, D> {
     }
 }
 
-fn read_len<A: Alignment>(buffer: &[Block<A>]) -> usize {
-    u32::from_le(Block::as_word_slice(buffer)[0]) as usize
+/// Read both header copies, into `buffers.header` and `buffers.buffer[0]`
+async fn read_headers<D: BlockDevice<BLOCK_BYTES>>(
+    device: &mut D,
+    buffers: &mut Buffers<D::Align>,
+) -> Result<(), D::Error> {
+    let [copy, _] = &mut buffers.buffer;
+    for (block, buffer) in HEADER_BLOCKS.into_iter().zip([&mut buffers.header, copy]) {
+        device.read(block, buffer.as_aligned_mut()).await?;
+    }
+    Ok(())
 }
 
-fn read_slice<A: Alignment>(buffer: &[Block<A>]) -> &[u8] {
-    let len = read_len(buffer);
-    &Block::as_byte_slice(buffer)[LEN_BYTES..(LEN_BYTES + len)]
+/// Size of a new filesystem on `device`, in blocks
+async fn filesystem_blocks<D: BlockDevice<BLOCK_BYTES>>(device: &mut D) -> Result<u32, D::Error> {
+    let size = device.size().await? / BLOCK_BYTES as u64;
+    Ok(u32::try_from(size).map_or(MAX_BLOCKS, |size| size.min(MAX_BLOCKS)))
+}
+
+fn read_len<A: Alignment>(buffer: &[Block<A>]) -> usize {
+    u32::from_le(Block::as_word_slice(buffer)[0]) as usize
 }
 
 fn write_len<A: Alignment>(buffer: &mut [Block<A>], len: usize) {
     Block::as_word_slice_mut(buffer)[0] = (len as u32).to_le();
 }
 
-fn write_slice<A: Alignment>(buffer: &mut [Block<A>], data: &[u8]) {
-    write_len(buffer, data.len());
-    Block::as_byte_slice_mut(buffer)[LEN_BYTES..(data.len() + LEN_BYTES)].copy_from_slice(data);
+/// Whether generation `a` was written after `b`, allowing for wrapping
+fn is_newer(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+/// Pick the newer of two `(generation, T)` copies, if either is valid
+fn newest<T>(a: Option<(u32, T)>, b: Option<(u32, T)>) -> Option<(u32, T)> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if is_newer(b.0, a.0) { b } else { a }),
+        (a, b) => a.or(b),
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use embedded_io_async::{Read as _, Seek as _, SeekFrom, Write as _};
+
     use super::*;
+    use crate::mock::MockError;
+
+    fn pattern(len: usize, seed: u8) -> std::vec::Vec<u8> {
+        (0..len).map(|i| (i as u8).wrapping_mul(7) ^ seed).collect()
+    }
+
+    async fn allocated<D: BlockDevice<BLOCK_BYTES>>(
+        fs: &mut Filesystem<'_, D>,
+        blocks: core::ops::Range<u32>,
+    ) -> std::vec::Vec<bool> {
+        let mut allocated = std::vec::Vec::new();
+        for block in blocks {
+            allocated.push(fs.bitmap.is_allocated(&mut fs.device, block).await.unwrap());
+        }
+        allocated
+    }
 
     #[tokio::test]
     #[test_log::test]
     async fn model_name() {
-        let mut mock = Mock::<5>::new();
+        let mut mock = Mock::<8>::new();
         let mut buffers = crate::Buffers::new();
-        let mut fs = Filesystem::new_empty(&mut mock, &mut buffers);
+        let mut fs = Filesystem::new_empty(&mut mock, &mut buffers)
+            .await
+            .unwrap();
 
         let mut file = fs.new_model("Test model").await.unwrap();
         assert_eq!(file.len(), 0);
         file.close().await.unwrap();
+
+        let names = &mock.blocks()[NAMES_OFFSET as usize];
+        assert_eq!(&names[..MODEL_NAME_BYTES], b"Test model\0\0\0\0\0\0");
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn format() {
+        let mut mock = Mock::<24>::new();
+        mock.block_mut(23).fill(0xFF);
+        {
+            let mut buffers = crate::Buffers::new();
+            let fs = Filesystem::new_empty(&mut mock, &mut buffers)
+                .await
+                .unwrap();
+            assert_eq!(fs.bitmap.data_end(), 23);
+        }
+
+        // The bitmap is cleared apart from the config region in blocks 15..23,
+        // and the header is written straight away
+        let bitmap = &mock.blocks()[23];
+        assert_eq!(bitmap[..3], [0, 0x80, 0x7F]);
+        assert!(bitmap[3..].iter().all(|&byte| byte == 0));
+        let mut buffers = crate::Buffers::new();
+        let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+        assert_eq!(fs.bitmap.data_end(), 23);
+
+        // The config region is allocated at the end
+        let config = Header::from_block(fs.header).config();
+        assert_eq!(config, Some(23 - config::REGION_BLOCKS));
+        assert_eq!(allocated(&mut fs, DATA_START..15).await, [false; 9]);
+        assert_eq!(allocated(&mut fs, 15..23).await, [true; 8]);
+    }
+
+    async fn names<D: BlockDevice<BLOCK_BYTES>>(
+        fs: &mut Filesystem<'_, D>,
+    ) -> std::vec::Vec<(u8, std::string::String)> {
+        let mut names = std::vec::Vec::new();
+        fs.model_names(|id, name| names.push((id, name.into())))
+            .await
+            .unwrap();
+        names
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn rename_copy_and_move() {
+        let data = pattern(700, 3);
+
+        let mut mock = Mock::<24>::new();
+        {
+            let mut buffers = crate::Buffers::new();
+            let mut fs = Filesystem::new_empty(&mut mock, &mut buffers)
+                .await
+                .unwrap();
+
+            for name in ["A", "B"] {
+                let mut file = fs.new_model(name).await.unwrap();
+                file.write_all(&data).await.unwrap();
+                file.close().await.unwrap();
+            }
+
+            fs.rename_model(0, "Renamed").await.unwrap();
+            assert_eq!(
+                fs.rename_model(0, "Far too long a name").await,
+                Err(Error::ModelNameOverflow)
+            );
+            assert_eq!(fs.copy_model(0, "Copy").await.unwrap(), Some(2));
+            assert_eq!(fs.copy_model(5, "Missing").await.unwrap(), None);
+            fs.move_model(2, 0).await.unwrap();
+        }
+
+        let mut buffers = crate::Buffers::new();
+        let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+        assert_eq!(
+            names(&mut fs).await,
+            [(2, "Copy".into()), (0, "Renamed".into()), (1, "B".into())]
+        );
+
+        // The copy is independent of the original
+        let mut file = fs.model(2).await.unwrap().unwrap();
+        let mut buf = std::vec![0; data.len()];
+        file.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, data);
+        file.rewind().await.unwrap();
+        file.truncate();
+        file.close().await.unwrap();
+
+        let mut file = fs.model(0).await.unwrap().unwrap();
+        assert_eq!(file.len(), data.len() as u64);
+        file.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn multi_block_model() {
+        let data = pattern(3000, 0x5A);
+
+        let mut mock = Mock::<24>::new();
+        {
+            let mut buffers = crate::Buffers::new();
+            let mut fs = Filesystem::new_empty(&mut mock, &mut buffers)
+                .await
+                .unwrap();
+
+            let mut file = fs.new_model("Big").await.unwrap();
+            file.write_all(&data).await.unwrap();
+            file.close().await.unwrap();
+        }
+
+        let mut buffers = crate::Buffers::new();
+        let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+        let mut expected = [true; 7];
+        expected[6] = false;
+        assert_eq!(
+            allocated(&mut fs, DATA_START..(DATA_START + 7)).await,
+            expected
+        );
+
+        let mut file = fs.model(0).await.unwrap().unwrap();
+        assert_eq!(file.len(), data.len() as u64);
+
+        let mut buf = std::vec![0; data.len()];
+        file.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, data);
+        file.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn fragmented_model() {
+        let a = pattern(1000, 1);
+        let b = pattern(600, 2);
+
+        let mut mock = Mock::<24>::new();
+        let mut buffers = crate::Buffers::new();
+        let mut fs = Filesystem::new_empty(&mut mock, &mut buffers)
+            .await
+            .unwrap();
+
+        let mut file = fs.new_model("A").await.unwrap();
+        file.write_all(&a[..400]).await.unwrap();
+        file.close().await.unwrap();
+
+        let mut file = fs.new_model("B").await.unwrap();
+        file.write_all(&b).await.unwrap();
+        file.close().await.unwrap();
+
+        let mut file = fs.model(0).await.unwrap().unwrap();
+        file.seek(SeekFrom::End(0)).await.unwrap();
+        file.write_all(&a[400..]).await.unwrap();
+        file.close().await.unwrap();
+
+        for (id, expected) in [(0, &a), (1, &b)] {
+            let mut file = fs.model(id).await.unwrap().unwrap();
+            let mut buf = std::vec![0; expected.len()];
+            file.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, expected);
+            file.close().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn truncate_frees_blocks() {
+        let mut mock = Mock::<24>::new();
+        let mut buffers = crate::Buffers::new();
+        let mut fs = Filesystem::new_empty(&mut mock, &mut buffers)
+            .await
+            .unwrap();
+
+        let mut file = fs.new_model("Shrinking").await.unwrap();
+        file.write_all(&pattern(2000, 0)).await.unwrap();
+        file.flush().await.unwrap();
+        file.seek(SeekFrom::Start(100)).await.unwrap();
+        file.truncate();
+        assert_eq!(file.len(), 100);
+
+        // Rewriting reuses the blocks that were cut off
+        file.write_all(&pattern(600, 1)).await.unwrap();
+        file.seek(SeekFrom::Start(100)).await.unwrap();
+        file.truncate();
+        file.close().await.unwrap();
+        assert_eq!(
+            allocated(&mut fs, DATA_START..(DATA_START + 5)).await,
+            [true, false, false, false, false]
+        );
+
+        fs.delete_model(0).await.unwrap();
+        assert_eq!(
+            allocated(&mut fs, DATA_START..(DATA_START + 1)).await,
+            [false]
+        );
+        assert_eq!(Header::from_block(fs.header).iter_models().count(), 0);
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn no_space() {
+        let mut mock = Mock::<{ DATA_START as usize + 3 }>::new();
+        let mut buffers = crate::Buffers::new();
+        let mut fs = Filesystem::new_empty(&mut mock, &mut buffers)
+            .await
+            .unwrap();
+
+        let mut file = fs.new_model("Too big").await.unwrap();
+        assert_eq!(
+            file.write_all(&pattern(BLOCK_BYTES * 2, 0)).await,
+            Err(Error::NoSpace)
+        );
+        file.close().await.unwrap();
+
+        assert!(matches!(fs.new_model("Another").await, Err(Error::NoSpace)));
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn reformat() {
+        let mut mock = Mock::<24>::new();
+        {
+            let mut buffers = crate::Buffers::new();
+            let mut fs = Filesystem::new_empty(&mut mock, &mut buffers)
+                .await
+                .unwrap();
+            for name in ["A", "B", "C"] {
+                let file = fs.new_model(name).await.unwrap();
+                file.close().await.unwrap();
+            }
+            fs.write_config(b"config").await.unwrap();
+        }
+
+        // Both copies of the old header must go, whichever is newer
+        {
+            let mut buffers = crate::Buffers::new();
+            Filesystem::new_empty(&mut mock, &mut buffers)
+                .await
+                .unwrap();
+        }
+
+        let mut buffers = crate::Buffers::new();
+        let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+        assert_eq!(Header::from_block(fs.header).iter_models().count(), 0);
+        assert_eq!(fs.read_config(&mut [0; 16]).await.unwrap(), b"");
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn other_version() {
+        let mut mock = Mock::<16>::new();
+        {
+            let mut buffers = crate::Buffers::new();
+            let mut fs = Filesystem::new_empty(&mut mock, &mut buffers)
+                .await
+                .unwrap();
+            let file = fs.new_model("A").await.unwrap();
+            file.close().await.unwrap();
+        }
+
+        // A damaged copy must not be repaired over one from another format
+        mock.block_mut(HEADER_BLOCKS[0] as usize)[BLOCK_BYTES - 1] ^= 1;
+        mock.block_mut(HEADER_BLOCKS[1] as usize)[0] = 0xFF;
+
+        let mut buffers = crate::Buffers::new();
+        assert!(matches!(
+            Filesystem::new(&mut mock, &mut buffers).await,
+            Err(InitError::HeaderError {
+                kind: HeaderError::Version,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn torn_header_write() {
+        let mut mock = Mock::<16>::new();
+        {
+            let mut buffers = crate::Buffers::new();
+            let mut fs = Filesystem::new_empty(&mut mock, &mut buffers)
+                .await
+                .unwrap();
+            let file = fs.new_model("Survivor").await.unwrap();
+            file.close().await.unwrap();
+        }
+
+        mock.lose_power_after(100);
+        {
+            let mut buffers = crate::Buffers::new();
+            let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+            assert_eq!(
+                fs.delete_model(0).await,
+                Err(Error::Io(MockError::PowerLoss))
+            );
+        }
+        mock.restore_power();
+
+        {
+            let mut buffers = crate::Buffers::new();
+            let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+            assert!(fs.model(0).await.unwrap().is_some());
+
+            // Retrying must not clobber the intact copy
+            fs.delete_model(0).await.unwrap();
+        }
+
+        let mut buffers = crate::Buffers::new();
+        let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+        assert!(fs.model(0).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn torn_config_write() {
+        let mut mock = Mock::<16>::new();
+        let mut buf = [0; 16];
+        {
+            let mut buffers = crate::Buffers::new();
+            let mut fs = Filesystem::new_empty(&mut mock, &mut buffers)
+                .await
+                .unwrap();
+            assert_eq!(fs.read_config(&mut buf).await.unwrap(), b"");
+            fs.write_config(b"first").await.unwrap();
+        }
+
+        mock.lose_power_after(10);
+        {
+            let mut buffers = crate::Buffers::new();
+            let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+            assert_eq!(
+                fs.write_config(b"second").await,
+                Err(Error::Io(MockError::PowerLoss))
+            );
+        }
+        mock.restore_power();
+
+        {
+            let mut buffers = crate::Buffers::new();
+            let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+            assert_eq!(fs.read_config(&mut buf).await.unwrap(), b"first");
+            fs.write_config(b"third").await.unwrap();
+        }
+
+        let mut buffers = crate::Buffers::new();
+        let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+        assert_eq!(fs.read_config(&mut buf).await.unwrap(), b"third");
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn large_config() {
+        let config = pattern(MAX_CONFIG_BYTES, 5);
+        let mut mock = Mock::<24>::new();
+        {
+            let mut buffers = crate::Buffers::new();
+            let mut fs = Filesystem::new_empty(&mut mock, &mut buffers)
+                .await
+                .unwrap();
+            fs.write_config(&config).await.unwrap();
+            assert_eq!(
+                fs.write_config(&[0; MAX_CONFIG_BYTES + 1]).await,
+                Err(Error::ConfigOverflow)
+            );
+        }
+
+        // Power is lost partway through the second block of the new copy
+        mock.lose_power_after(BLOCK_BYTES + 10);
+        {
+            let mut buffers = crate::Buffers::new();
+            let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+            assert_eq!(
+                fs.write_config(&pattern(MAX_CONFIG_BYTES, 6)).await,
+                Err(Error::Io(MockError::PowerLoss))
+            );
+        }
+        mock.restore_power();
+
+        let mut buffers = crate::Buffers::new();
+        let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+        let mut buf = [0; MAX_CONFIG_BYTES];
+        assert_eq!(fs.read_config(&mut buf).await.unwrap(), config);
+        assert_eq!(
+            fs.read_config(&mut buf[..100]).await,
+            Err(Error::ConfigOverflow)
+        );
+    }
+
+    /// Format a filesystem as it was before the config had a region of its
+    /// own, holding `config`
+    async fn legacy<const LEN: usize>(config: &[u8]) -> Mock<LEN> {
+        let mut mock = Mock::new();
+        let mut buffers = crate::Buffers::new();
+        let mut fs = Filesystem::new_empty(&mut mock, &mut buffers)
+            .await
+            .unwrap();
+
+        if let Some(start) = Header::from_block(fs.header).config() {
+            fs.bitmap
+                .free(&mut fs.device, start, config::REGION_BLOCKS)
+                .await
+                .unwrap();
+            fs.bitmap.flush(&mut fs.device).await.unwrap();
+        }
+        Header::from_block_mut(fs.header).make_legacy();
+        fs.config_generation = 0;
+        fs.write_config_copy(config).await.unwrap();
+        fs.write_header().await.unwrap();
+        mock
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn legacy_config_moved() {
+        let config = pattern(config::LEGACY_MAX_BYTES, 7);
+
+        // Cut the power after each write in turn, until the move finishes
+        for writes in 0.. {
+            let mut mock = legacy::<24>(&config).await;
+            mock.lose_power_after(writes * BLOCK_BYTES);
+            let finished = {
+                let mut buffers = crate::Buffers::new();
+                Filesystem::new(&mut mock, &mut buffers).await.is_ok()
+            };
+            mock.restore_power();
+
+            let mut buffers = crate::Buffers::new();
+            let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+            assert!(Header::from_block(fs.header).config().is_some());
+            let mut buf = [0; MAX_CONFIG_BYTES];
+            assert_eq!(fs.read_config(&mut buf).await.unwrap(), config);
+
+            let larger = pattern(MAX_CONFIG_BYTES, 8);
+            fs.write_config(&larger).await.unwrap();
+            assert_eq!(fs.read_config(&mut buf).await.unwrap(), larger);
+
+            if finished {
+                break;
+            }
+        }
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn legacy_config_no_room() {
+        let mut mock = legacy::<12>(b"config").await;
+        let mut buffers = crate::Buffers::new();
+        let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+        assert_eq!(Header::from_block(fs.header).config(), None);
+
+        let mut buf = [0; 16];
+        assert_eq!(fs.read_config(&mut buf).await.unwrap(), b"config");
+        assert_eq!(
+            fs.write_config(&[0; config::LEGACY_MAX_BYTES + 1]).await,
+            Err(Error::ConfigOverflow)
+        );
+        fs.write_config(b"still fits").await.unwrap();
+        assert_eq!(fs.read_config(&mut buf).await.unwrap(), b"still fits");
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn torn_growth() {
+        let old = pattern(400, 1);
+        let new = pattern(1500, 2);
+
+        // Cut the power after each write in turn, until growing succeeds
+        for writes in 0.. {
+            let mut mock = Mock::<24>::new();
+            {
+                let mut buffers = crate::Buffers::new();
+                let mut fs = Filesystem::new_empty(&mut mock, &mut buffers)
+                    .await
+                    .unwrap();
+                let mut file = fs.new_model("Growing").await.unwrap();
+                file.write_all(&old).await.unwrap();
+                file.close().await.unwrap();
+            }
+
+            mock.lose_power_after(writes * BLOCK_BYTES);
+            let grown = {
+                let mut buffers = crate::Buffers::new();
+                let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+                let mut file = fs.model(0).await.unwrap().unwrap();
+                file.seek(SeekFrom::End(0)).await.unwrap();
+                let result = match file.write_all(&new).await {
+                    Ok(()) => file.flush().await,
+                    Err(err) => Err(err),
+                };
+                match result {
+                    Ok(()) => {
+                        file.close().await.unwrap();
+                        true
+                    }
+                    Err(err) => {
+                        assert_eq!(err, Error::Io(MockError::PowerLoss));
+                        // Nothing can be flushed without power
+                        core::mem::forget(file);
+                        false
+                    }
+                }
+            };
+            mock.restore_power();
+
+            let mut buffers = crate::Buffers::new();
+            let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+            let start = Header::from_block(fs.header)
+                .iter_models()
+                .next()
+                .unwrap()
+                .start();
+
+            // Every block the file refers to must still be allocated
+            let (_, extents) = File::preamble(&mut fs, start).await.unwrap();
+            for block in extents.iter_blocks() {
+                assert!(fs.bitmap.is_allocated(&mut fs.device, block).await.unwrap());
+            }
+
+            let mut file = fs.model(0).await.unwrap().unwrap();
+            let mut buf = std::vec![0; file.len() as usize];
+            file.read_exact(&mut buf).await.unwrap();
+            if buf.len() == old.len() {
+                assert_eq!(buf, old);
+            } else {
+                assert_eq!(buf, [&old[..], &new[..]].concat());
+            }
+            file.close().await.unwrap();
+
+            if grown {
+                assert_eq!(buf.len(), old.len() + new.len());
+                break;
+            }
+        }
+    }
+
+    async fn file_names<D: BlockDevice<BLOCK_BYTES>>(
+        fs: &mut Filesystem<'_, D>,
+    ) -> std::vec::Vec<std::string::String> {
+        let mut names = std::vec::Vec::new();
+        fs.file_names(|name| names.push(name.into())).await.unwrap();
+        names
+    }
+
+    async fn read_file<D>(fs: &mut Filesystem<'_, D>, name: &str) -> std::vec::Vec<u8>
+    where
+        D: BlockDevice<BLOCK_BYTES>,
+        D::Error: embedded_io_async::Error,
+    {
+        let mut file = fs.file(name).await.unwrap().unwrap();
+        let mut buf = std::vec![0; file.len() as usize];
+        file.read_exact(&mut buf).await.unwrap();
+        file.close().await.unwrap();
+        buf
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn named_files() {
+        let log = pattern(900, 9);
+        let capture = pattern(1200, 10);
+
+        let mut mock = Mock::<32>::new();
+        {
+            let mut buffers = crate::Buffers::new();
+            let mut fs = Filesystem::new_empty(&mut mock, &mut buffers)
+                .await
+                .unwrap();
+            assert!(file_names(&mut fs).await.is_empty());
+            assert_eq!(Header::from_block(fs.header).directory(), None);
+
+            let mut file = fs.create_file("log").await.unwrap();
+            file.write_all(&log[..300]).await.unwrap();
+            file.close().await.unwrap();
+
+            let mut file = fs.create_file("capture.bin").await.unwrap();
+            file.write_all(&capture).await.unwrap();
+            file.close().await.unwrap();
+
+            let mut file = fs.append_file("log").await.unwrap().unwrap();
+            file.write_all(&log[300..]).await.unwrap();
+            file.close().await.unwrap();
+        }
+
+        let mut buffers = crate::Buffers::new();
+        let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+        // The directory is allocated below the config region
+        assert_eq!(Header::from_block(fs.header).directory(), Some(19));
+        assert_eq!(file_names(&mut fs).await, ["log", "capture.bin"]);
+        assert_eq!(read_file(&mut fs, "log").await, log);
+        assert_eq!(read_file(&mut fs, "capture.bin").await, capture);
+        assert!(fs.file("missing").await.unwrap().is_none());
+        assert!(fs.append_file("missing").await.unwrap().is_none());
+
+        let (_, start) = fs.find_file(b"capture.bin").await.unwrap().unwrap();
+        let (_, extents) = File::preamble(&mut fs, start).await.unwrap();
+        fs.delete_file("capture.bin").await.unwrap();
+        assert_eq!(file_names(&mut fs).await, ["log"]);
+        for block in extents.iter_blocks() {
+            assert!(!fs.bitmap.is_allocated(&mut fs.device, block).await.unwrap());
+        }
+
+        let mut file = fs.create_file("log").await.unwrap();
+        assert_eq!(file.len(), 0);
+        file.close().await.unwrap();
+        assert_eq!(read_file(&mut fs, "log").await, b"");
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn file_limits() {
+        // Room for the config and directory regions, the bitmap, and one block
+        // per file
+        const LEN: usize = DATA_START as usize + 8 + 4 + MAX_FILES + 1;
+
+        let mut mock = Mock::<LEN>::new();
+        let mut buffers = crate::Buffers::new();
+        let mut fs = Filesystem::new_empty(&mut mock, &mut buffers)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            fs.create_file(&"x".repeat(FILE_NAME_BYTES + 1)).await,
+            Err(Error::FileNameOverflow)
+        ));
+
+        for i in 0..MAX_FILES {
+            let name = std::format!("{i:0>FILE_NAME_BYTES$}");
+            let file = fs.create_file(&name).await.unwrap();
+            file.close().await.unwrap();
+        }
+
+        assert_eq!(file_names(&mut fs).await.len(), MAX_FILES);
+        assert!(matches!(
+            fs.create_file("another").await,
+            Err(Error::TooManyFiles)
+        ));
+        assert!(matches!(fs.new_model("Model").await, Err(Error::NoSpace)));
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn torn_directory_write() {
+        let mut mock = Mock::<32>::new();
+        {
+            let mut buffers = crate::Buffers::new();
+            let mut fs = Filesystem::new_empty(&mut mock, &mut buffers)
+                .await
+                .unwrap();
+            let file = fs.create_file("first").await.unwrap();
+            file.close().await.unwrap();
+        }
+
+        // Enough for the new file and the bitmap, but not the directory
+        // checksum
+        mock.lose_power_after(2 * BLOCK_BYTES + 8);
+        {
+            let mut buffers = crate::Buffers::new();
+            let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+            assert!(matches!(
+                fs.create_file("second").await,
+                Err(Error::Io(MockError::PowerLoss))
+            ));
+        }
+        mock.restore_power();
+
+        let mut buffers = crate::Buffers::new();
+        let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+        assert_eq!(file_names(&mut fs).await, ["first"]);
+        let file = fs.create_file("second").await.unwrap();
+        file.close().await.unwrap();
+        assert_eq!(file_names(&mut fs).await, ["first", "second"]);
     }
 }
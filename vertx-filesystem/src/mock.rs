@@ -1,23 +1,31 @@
+use core::ops::Range;
 use std::fmt;
 
 use aligned::Aligned;
 use block_device_driver::BlockDevice;
 use embedded_io_async::ErrorKind;
+use embedded_storage_async::nor_flash::{NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash};
 
 use crate::BLOCK_BYTES;
 
 #[derive(Debug)]
-pub(crate) struct Mock<const LEN: usize>([[u8; BLOCK_BYTES]; LEN]);
+pub(crate) struct Mock<const LEN: usize> {
+    blocks: [[u8; BLOCK_BYTES]; LEN],
+    /// Bytes that can be written before simulating a power cut
+    write_budget: Option<usize>,
+}
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub(crate) enum MockError {
     OutOfBounds,
+    PowerLoss,
 }
 
 impl fmt::Display for MockError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::OutOfBounds => f.write_str("out of bounds"),
+            Self::PowerLoss => f.write_str("power lost mid-write"),
         }
     }
 }
@@ -28,21 +36,36 @@ impl embedded_io_async::Error for MockError {
     fn kind(&self) -> ErrorKind {
         match self {
             Self::OutOfBounds => ErrorKind::OutOfMemory,
+            Self::PowerLoss => ErrorKind::Interrupted,
         }
     }
 }
 
 impl<const LEN: usize> Mock<LEN> {
     pub(crate) const fn new() -> Self {
-        Self([[0; BLOCK_BYTES]; LEN])
+        Self {
+            blocks: [[0; BLOCK_BYTES]; LEN],
+            write_budget: None,
+        }
     }
 
     pub(crate) fn blocks(&self) -> &[[u8; BLOCK_BYTES]; LEN] {
-        &self.0
+        &self.blocks
     }
 
     pub(crate) fn block_mut(&mut self, i: usize) -> &mut [u8; BLOCK_BYTES] {
-        &mut self.0[i]
+        &mut self.blocks[i]
+    }
+
+    /// Simulate losing power after another `bytes` bytes have been written. The
+    /// write that crosses the limit is torn, and all writes after it fail until
+    /// [`restore_power`](Self::restore_power) is called.
+    pub(crate) fn lose_power_after(&mut self, bytes: usize) {
+        self.write_budget = Some(bytes);
+    }
+
+    pub(crate) fn restore_power(&mut self) {
+        self.write_budget = None;
     }
 }
 
@@ -61,7 +84,7 @@ impl<const LEN: usize> BlockDevice<BLOCK_BYTES> for Mock<LEN> {
         );
 
         let mut data = data.iter_mut();
-        let blocks = self.0.iter().skip(block_address as usize);
+        let blocks = self.blocks.iter().skip(block_address as usize);
         for (block, data) in blocks.zip(data.by_ref()) {
             data.copy_from_slice(block);
         }
@@ -83,8 +106,19 @@ impl<const LEN: usize> BlockDevice<BLOCK_BYTES> for Mock<LEN> {
         );
 
         let mut data = data.iter();
-        let blocks = self.0.iter_mut().skip(block_address as usize);
+        let blocks = self.blocks.iter_mut().skip(block_address as usize);
         for (block, data) in blocks.zip(data.by_ref()) {
+            if let Some(budget) = &mut self.write_budget {
+                if *budget < BLOCK_BYTES {
+                    loog::debug!("tearing write after {budget} bytes");
+                    block[..*budget].copy_from_slice(&data[..*budget]);
+                    *budget = 0;
+                    return Err(MockError::PowerLoss);
+                }
+
+                *budget -= BLOCK_BYTES;
+            }
+
             block.copy_from_slice(data.as_slice());
         }
         if data.next().is_some() {
@@ -98,3 +132,142 @@ impl<const LEN: usize> BlockDevice<BLOCK_BYTES> for Mock<LEN> {
         Ok((BLOCK_BYTES * LEN) as u64)
     }
 }
+
+/// NOR flash that panics if a word is written twice without an erase in
+/// between, with 4 KiB sectors
+#[derive(Debug)]
+pub(crate) struct MockFlash {
+    data: std::vec::Vec<u8>,
+    erases: std::vec::Vec<u32>,
+    /// Bytes that can be written before simulating a power cut
+    write_budget: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum MockFlashError {
+    OutOfBounds,
+    NotAligned,
+    PowerLoss,
+}
+
+impl NorFlashError for MockFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Self::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            Self::NotAligned => NorFlashErrorKind::NotAligned,
+            Self::PowerLoss => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+impl MockFlash {
+    pub(crate) const SECTOR_BYTES: usize = 4096;
+
+    pub(crate) fn new(sectors: usize) -> Self {
+        Self {
+            data: std::vec![0xFF; sectors * Self::SECTOR_BYTES],
+            erases: std::vec![0; sectors],
+            write_budget: None,
+        }
+    }
+
+    pub(crate) fn erases(&self) -> &[u32] {
+        &self.erases
+    }
+
+    /// Simulate losing power after another `bytes` bytes have been written. The
+    /// write that crosses the limit is torn, and all writes and erases after it
+    /// fail until [`restore_power`](Self::restore_power) is called.
+    pub(crate) fn lose_power_after(&mut self, bytes: usize) {
+        self.write_budget = Some(bytes);
+    }
+
+    pub(crate) fn restore_power(&mut self) {
+        self.write_budget = None;
+    }
+
+    fn range(&self, offset: u32, len: usize, align: usize) -> Result<Range<usize>, MockFlashError> {
+        let start = offset as usize;
+        if !start.is_multiple_of(align) || !len.is_multiple_of(align) {
+            Err(MockFlashError::NotAligned)
+        } else if start + len > self.data.len() {
+            Err(MockFlashError::OutOfBounds)
+        } else {
+            Ok(start..start + len)
+        }
+    }
+}
+
+impl embedded_storage_async::nor_flash::ErrorType for MockFlash {
+    type Error = MockFlashError;
+}
+
+impl ReadNorFlash for MockFlash {
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let range = self.range(offset, bytes.len(), Self::READ_SIZE)?;
+        bytes.copy_from_slice(&self.data[range]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl NorFlash for MockFlash {
+    const ERASE_SIZE: usize = Self::SECTOR_BYTES;
+    const WRITE_SIZE: usize = 4;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        let range = self.range(from, (to - from) as usize, Self::ERASE_SIZE)?;
+        if self.write_budget == Some(0) {
+            return Err(MockFlashError::PowerLoss);
+        }
+
+        for sector in range.clone().step_by(Self::ERASE_SIZE) {
+            self.erases[sector / Self::ERASE_SIZE] += 1;
+        }
+        self.data[range].fill(0xFF);
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let range = self.range(offset, bytes.len(), Self::WRITE_SIZE)?;
+        if self.write_budget == Some(0) {
+            return Err(MockFlashError::PowerLoss);
+        }
+
+        let target = &mut self.data[range];
+
+        let words = target.chunks_exact(4).zip(bytes.chunks_exact(4));
+        for (i, (old, new)) in words.enumerate() {
+            assert!(
+                old == [0xFF; 4] || new == [0xFF; 4],
+                "rewrote flash word at {:#x}",
+                offset as usize + i * 4
+            );
+        }
+
+        let mut len = bytes.len();
+        if let Some(budget) = &mut self.write_budget {
+            if *budget < len {
+                loog::debug!("tearing flash write after {budget} bytes");
+                len = *budget;
+            }
+            *budget -= len;
+        }
+
+        target[..len].copy_from_slice(&bytes[..len]);
+        if len < bytes.len() {
+            // The word being written when power was lost ends up half written
+            if let Some(torn) = target.get_mut(len) {
+                *torn &= bytes[len] | 0x0F;
+            }
+            Err(MockFlashError::PowerLoss)
+        } else {
+            Ok(())
+        }
+    }
+}
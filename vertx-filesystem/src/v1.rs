@@ -0,0 +1,377 @@
+//! The original layout, with a fixed number of blocks for each model and no
+//! allocation bitmap, which is imported into the current one on mount
+
+use core::mem;
+
+use aligned::Alignment;
+use block_device_driver::BlockDevice;
+use bytemuck::{Pod, Zeroable};
+
+use crate::header::{self, CRC};
+use crate::{
+    BLOCK_BYTES, Block, Buffers, CONFIG_BLOCKS, DATA_START, Error, File, Filesystem, HeaderError,
+    InitError, LEN_BYTES, MAX_MODELS, bitmap, config,
+};
+
+const VERSION: u8 = 1;
+/// The length of the config followed by the config itself, where the first of
+/// the current config copies now goes
+const CONFIG_BLOCK: u32 = CONFIG_BLOCKS[0];
+const MODELS_START: u32 = 4;
+
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+pub(crate) struct Header {
+    version: u8,
+    _padding0: [u8; 3],
+    models: [Model; MAX_MODELS],
+    _padding1: [u8; 248],
+    checksum: u32,
+}
+
+bitfield::bitfield! {
+    #[derive(Clone, Copy, PartialEq, Pod, Zeroable)]
+    #[repr(transparent)]
+    struct Model(u32);
+    impl Debug;
+    start, _: 19, 0;
+    u8, len, _: 23, 20;
+    u8, id, _: 29, 24;
+}
+
+const _: () = assert!(mem::size_of::<Header>() == BLOCK_BYTES);
+
+impl Header {
+    pub(crate) fn from_block<A: Alignment>(block: &Block<A>) -> &Self {
+        bytemuck::must_cast_ref(block.as_words())
+    }
+
+    pub(crate) fn is_valid(&self) -> bool {
+        let mut crc = CRC.digest();
+        crc.update(&[self.version]);
+        crc.update(bytemuck::must_cast_slice(&self.models));
+        self.version == VERSION && u32::from_le(self.checksum) == crc.finalize()
+    }
+
+    fn models(&self) -> impl Iterator<Item = &Model> {
+        self.models.iter().take_while(|model| model.start() != 0)
+    }
+
+    /// Models that can be imported into a filesystem whose data ends at
+    /// `end`, which excludes any with a duplicate id
+    fn importable(&self, end: u32) -> impl Iterator<Item = Model> {
+        let mut used: u64 = 0;
+        self.models()
+            .filter(move |model| {
+                let fresh = used & (1 << model.id()) == 0;
+                used |= 1 << model.id();
+                fresh && model.start() >= MODELS_START && model.len() > 0 && model.end() <= end
+            })
+            .copied()
+    }
+
+    /// Number of blocks available to the allocator that are in use by the
+    /// importable models
+    fn used_blocks(&self, end: u32) -> u32 {
+        let mut runs = [(0, 0); MAX_MODELS];
+        let mut count = 0;
+        for (run, model) in runs.iter_mut().zip(self.importable(end)) {
+            *run = (model.start().max(DATA_START), model.end());
+            count += 1;
+        }
+
+        let runs = &mut runs[..count];
+        runs.sort_unstable();
+
+        // Models may overlap if the header was damaged
+        let mut used = 0;
+        let mut covered = 0;
+        for &(start, end) in &*runs {
+            let start = start.max(covered);
+            if end > start {
+                used += end - start;
+                covered = end;
+            }
+        }
+        used
+    }
+}
+
+impl Model {
+    fn end(&self) -> u32 {
+        self.start() + u32::from(self.len())
+    }
+}
+
+impl<'buf, D: BlockDevice<BLOCK_BYTES>> Filesystem<'buf, D> {
+    /// Convert the original layout, whose header has been read into
+    /// `buffers.header`, to the current one. The names are already where they
+    /// belong, so only the models and config need to be copied.
+    ///
+    /// The old header and models are left intact until the new header is
+    /// written, so an interrupted import starts over on the next mount. The new
+    /// header goes in the second copy, over the start of the first old model,
+    /// leaving the old header in the first copy until the config is converted.
+    ///
+    /// Fails with [`HeaderError::Version`] without writing anything if there is
+    /// not enough free space to copy every model.
+    pub(crate) async fn import_v1(
+        mut device: D,
+        buffers: &'buf mut Buffers<D::Align>,
+    ) -> Result<Self, InitError<'buf, D>> {
+        loog::info!("importing v1 filesystem");
+
+        let old = *Header::from_block(&buffers.header);
+        let blocks = crate::filesystem_blocks(&mut device)
+            .await
+            .map_err(InitError::Io)?;
+        let end = bitmap::data_end(blocks);
+
+        // Each copy needs at most one more block than the original, for the
+        // longer preamble
+        let needed: u32 = old
+            .importable(end)
+            .map(|model| u32::from(model.len()) + 1)
+            .sum();
+        let free = end.saturating_sub(DATA_START) - old.used_blocks(end);
+        if free < needed {
+            loog::warn!("not enough free space to import the v1 filesystem");
+            return Err(InitError::HeaderError {
+                kind: HeaderError::Version,
+                device,
+                buffers,
+            });
+        }
+
+        header::Header::from_block_mut(&mut buffers.header).init(blocks);
+        let mut fs = Self::with_buffers(device, buffers, blocks);
+        fs.import_v1_models(&old, end)
+            .await
+            .map_err(InitError::Io)?;
+        Ok(fs)
+    }
+
+    async fn import_v1_models(&mut self, old: &Header, end: u32) -> Result<(), D::Error> {
+        let dropped = old.models().count() - old.importable(end).count();
+        if dropped > 0 {
+            loog::warn!("dropping {dropped} out of range or duplicate v1 models");
+        }
+
+        // Keep the old models out of the way until they have been copied
+        self.bitmap.clear(&mut self.device).await?;
+        for model in old.importable(end) {
+            for block in model.start()..model.end() {
+                self.bitmap.allocate_at(&mut self.device, block).await?;
+            }
+        }
+
+        for model in old.importable(end) {
+            let id = model.id();
+            let mut view = self.buffer.select(&mut self.device, model.start()).await?;
+            view.read().await?;
+            let len = crate::read_len(view.data());
+            if len + LEN_BYTES > usize::from(model.len()) * BLOCK_BYTES {
+                loog::warn!("dropping v1 model {id=u8} with a damaged length");
+                continue;
+            }
+
+            let extents = match File::import(self, model.start(), LEN_BYTES, len).await {
+                Ok(extents) => extents,
+                Err(Error::Io(err)) => return Err(err),
+                Err(_) => {
+                    loog::warn!("dropping v1 model {id=u8} that is too fragmented to copy");
+                    continue;
+                }
+            };
+
+            header::Header::from_block_mut(self.header)
+                .insert_model(extents.start(), id)
+                .expect("ids are unique, so there is a free slot");
+        }
+
+        for model in old.importable(end) {
+            let start = model.start().max(DATA_START);
+            if start < model.end() {
+                self.bitmap
+                    .free(&mut self.device, start, model.end() - start)
+                    .await?;
+            }
+        }
+        self.bitmap.flush(&mut self.device).await?;
+
+        self.write_header().await?;
+        self.import_v1_config().await?;
+        self.upgrade_config().await
+    }
+
+    /// Copy the v1 config into the second config copy, leaving the original in
+    /// place of the first until the config is next written
+    pub(crate) async fn import_v1_config(&mut self) -> Result<(), D::Error> {
+        let mut view = self.buffer.select(&mut self.device, CONFIG_BLOCK).await?;
+        view.read().await?;
+        let mut len = crate::read_len(view.data());
+        if len > config::LEGACY_MAX_BYTES {
+            loog::warn!("dropping v1 config that is too long to import");
+            len = 0;
+        }
+
+        let mut config = [0; config::LEGACY_MAX_BYTES];
+        config[..len].copy_from_slice(&Block::as_byte_slice(view.data())[LEN_BYTES..][..len]);
+        self.write_config_copy(&config[..len]).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_io_async::Read as _;
+
+    use super::*;
+    use crate::{Mock, NAMES_OFFSET};
+
+    const CONFIG: &[u8] = &[0xC0; 100];
+
+    fn pattern(len: usize, seed: u8) -> std::vec::Vec<u8> {
+        (0..len).map(|i| (i as u8).wrapping_mul(7) ^ seed).collect()
+    }
+
+    /// Models as (id, start, len, name, data)
+    type V1Model<'a> = (u8, u32, u8, &'a str, &'a [u8]);
+
+    fn image<const N: usize>(models: &[V1Model<'_>]) -> Mock<N> {
+        let mut mock = Mock::<N>::new();
+
+        let mut table = [0u32; MAX_MODELS];
+        for (word, &(id, start, len, name, data)) in table.iter_mut().zip(models) {
+            *word = start | (u32::from(len) << 20) | (u32::from(id) << 24);
+
+            let names = mock.block_mut((NAMES_OFFSET + u32::from(id) / 32) as usize);
+            let offset = usize::from(id % 32) * 16;
+            names[offset..(offset + name.len())].copy_from_slice(name.as_bytes());
+
+            let mut bytes = (data.len() as u32).to_le_bytes().to_vec();
+            bytes.extend_from_slice(data);
+            for (i, chunk) in bytes.chunks(BLOCK_BYTES).enumerate() {
+                mock.block_mut(start as usize + i)[..chunk.len()].copy_from_slice(chunk);
+            }
+        }
+
+        let mut crc = CRC.digest();
+        crc.update(&[VERSION]);
+        crc.update(bytemuck::cast_slice(&table));
+        let header = mock.block_mut(0);
+        header[0] = VERSION;
+        header[4..260].copy_from_slice(bytemuck::cast_slice(&table));
+        header[508..].copy_from_slice(&crc.finalize().to_le_bytes());
+
+        let block = mock.block_mut(CONFIG_BLOCK as usize);
+        block[..LEN_BYTES].copy_from_slice(&(CONFIG.len() as u32).to_le_bytes());
+        block[LEN_BYTES..][..CONFIG.len()].copy_from_slice(CONFIG);
+        mock
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn import() {
+        let a = pattern(700, 1);
+        // Fills the old model, so the copy needs an extra block
+        let b = pattern(4 * BLOCK_BYTES - LEN_BYTES, 2);
+        let models = [
+            (0, 4, 4, "Alpha", &a[..]),
+            (40, 8, 4, "Beta", &b[..]),
+            (5, 12, 1, "Empty", &[][..]),
+            // Duplicate ID and out of range, so both are dropped
+            (40, 16, 1, "Beta", &[][..]),
+            (7, 60, 8, "Outside", &[][..]),
+        ];
+        let mut mock = image::<64>(&models);
+        let names = mock.blocks()[1..3].to_vec();
+
+        let expected = [(0, &a[..]), (40, &b[..]), (5, &[][..])];
+        for _ in 0..2 {
+            let mut buffers = crate::Buffers::new();
+            let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+
+            let ids: std::vec::Vec<_> = header::Header::from_block(fs.header)
+                .iter_models()
+                .map(header::Model::id)
+                .collect();
+            assert_eq!(ids, [0, 40, 5]);
+
+            for (id, data) in expected {
+                let mut file = fs.model(id).await.unwrap().unwrap();
+                assert_eq!(file.len(), data.len() as u64);
+                let mut buf = std::vec![0; data.len()];
+                file.read_exact(&mut buf).await.unwrap();
+                assert_eq!(buf, data);
+                file.close().await.unwrap();
+            }
+
+            let mut buf = [0; CONFIG.len()];
+            assert_eq!(fs.read_config(&mut buf).await.unwrap(), CONFIG);
+
+            // The old blocks are free for reuse
+            for block in DATA_START..13 {
+                assert!(!fs.bitmap.is_allocated(&mut fs.device, block).await.unwrap());
+            }
+        }
+
+        assert_eq!(mock.blocks()[1..3], names);
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn import_interrupted() {
+        let a = pattern(1000, 3);
+        let b = pattern(300, 4);
+        let models = [(0, 4, 4, "A", &a[..]), (1, 8, 4, "B", &b[..])];
+
+        // Cut the power after each write in turn, until the import finishes.
+        // Only whole blocks are lost, as the new header replaces the start of
+        // the first old model.
+        for writes in 0.. {
+            let mut mock = image::<32>(&models);
+            mock.lose_power_after(writes * BLOCK_BYTES);
+            let finished = {
+                let mut buffers = crate::Buffers::new();
+                Filesystem::new(&mut mock, &mut buffers).await.is_ok()
+            };
+            mock.restore_power();
+
+            let mut buffers = crate::Buffers::new();
+            let mut fs = Filesystem::new(&mut mock, &mut buffers).await.unwrap();
+            for (id, data) in [(0, &a), (1, &b)] {
+                let mut file = fs.model(id).await.unwrap().unwrap();
+                let mut buf = std::vec![0; file.len() as usize];
+                file.read_exact(&mut buf).await.unwrap();
+                assert_eq!(&buf, data);
+                file.close().await.unwrap();
+            }
+
+            let mut buf = [0; CONFIG.len()];
+            assert_eq!(fs.read_config(&mut buf).await.unwrap(), CONFIG);
+
+            if finished {
+                break;
+            }
+        }
+    }
+
+    #[tokio::test]
+    #[test_log::test]
+    async fn import_no_space() {
+        let a = pattern(2000, 4);
+        let mut mock = image::<12>(&[(0, 4, 4, "A", &a[..])]);
+        let before = *mock.blocks();
+
+        let mut buffers = crate::Buffers::new();
+        let result = Filesystem::new(&mut mock, &mut buffers).await;
+        assert!(matches!(
+            result,
+            Err(InitError::HeaderError {
+                kind: HeaderError::Version,
+                ..
+            })
+        ));
+        assert_eq!(*mock.blocks(), before);
+    }
+}
@@ -45,7 +45,13 @@ fn main() -> io::Result<()> {
 }
 
 fn memory_layout(out_dir: &str, root: &str) {
-    let path = feature("CHIP_RP").then_some("src/hal/chip/rp/memory.x");
+    let path = feature("CHIP_RP").then(|| {
+        if feature("STORAGE_FLASH") {
+            "src/hal/chip/rp/memory-flash.x"
+        } else {
+            "src/hal/chip/rp/memory.x"
+        }
+    });
 
     if let Some(path) = path {
         fs::copy(format!("{root}/{path}"), format!("{out_dir}/memory.x"))
@@ -104,7 +110,7 @@ fn pins(out_dir: &str, root: &str, target: &str) -> io::Result<()> {
     struct Target {
         #[expect(unused)]
         chip: String,
-        sd: Sd,
+        sd: Option<Sd>,
         display: Display,
         #[serde(flatten)]
         rest: MiscPins,
@@ -218,7 +224,9 @@ fn pins(out_dir: &str, root: &str, target: &str) -> io::Result<()> {
 
     let mut out = String::from("macro_rules! pins {\n");
     target.rest.format(&mut out, gpio, "");
-    target.sd.pins.format(&mut out, gpio, "sd");
+    if let Some(sd) = target.sd {
+        sd.pins.format(&mut out, gpio, "sd");
+    }
     target.display.pins.format(&mut out, gpio, "display");
     out.push_str("}\n");
 
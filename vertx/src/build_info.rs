@@ -5,3 +5,22 @@ pub(crate) const VERSION: &str = env!("CARGO_PKG_VERSION");
 pub(crate) const DEBUG: bool = include!(concat!(env!("OUT_DIR"), "/is_debug"));
 pub(crate) const GIT_BRANCH: &str = include_str!(concat!(env!("OUT_DIR"), "/git_branch"));
 pub(crate) const GIT_COMMIT: &str = include_str!(concat!(env!("OUT_DIR"), "/git_commit"));
+
+/// [`VERSION`] packed as `0x00MMmmpp`
+pub(crate) const VERSION_PACKED: u32 = u32::from_be_bytes([
+    0,
+    parse_u8(env!("CARGO_PKG_VERSION_MAJOR")),
+    parse_u8(env!("CARGO_PKG_VERSION_MINOR")),
+    parse_u8(env!("CARGO_PKG_VERSION_PATCH")),
+]);
+
+const fn parse_u8(s: &str) -> u8 {
+    let bytes = s.as_bytes();
+    let mut value = 0u8;
+    let mut i = 0;
+    while i < bytes.len() {
+        value = value * 10 + (bytes[i] - b'0');
+        i += 1;
+    }
+    value
+}
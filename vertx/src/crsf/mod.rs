@@ -0,0 +1,34 @@
+mod router;
+mod timing;
+
+use alloc::ffi::CString;
+
+use vertx_crsf::{Address, Packet};
+
+use crate::build_info;
+
+const NAME: &str = const_format::concatcp!("VerTX ", build_info::TARGET);
+
+/// Build the reply to a packet that the handset must answer directly, if any
+pub(crate) fn reply(packet: &Packet) -> Option<Packet> {
+    match *packet {
+        Packet::DevicePing {
+            to: Address::Handset | Address::Broadcast,
+            from,
+        } => Some(device_info(from)),
+        _ => None,
+    }
+}
+
+fn device_info(to: Address) -> Packet {
+    Packet::DeviceInfo {
+        to,
+        from: Address::Handset,
+        name: CString::new(NAME).unwrap(),
+        serial_number: 0,
+        hardware_version: 0,
+        software_version: build_info::VERSION_PACKED,
+        parameter_count: 0,
+        protocol_version: 0,
+    }
+}
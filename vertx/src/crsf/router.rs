@@ -0,0 +1,357 @@
+//! Owns the module UART, fanning decoded frames out to the tasks that handle
+//! them and merging the frames those tasks send back
+
+use core::cell::RefCell;
+
+use embassy_futures::select::{Either3, select3};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant};
+use embedded_io_async::{Read, Write};
+use vertx_crsf::{Address, Packet, RcChannelsPacked, Transport};
+
+use super::timing::FrameTiming;
+use crate::mutex::MultiCore;
+
+const SUBSCRIBERS: usize = 4;
+const SUBSCRIBER_DEPTH: usize = 8;
+const OUTBOUND_DEPTH: usize = 8;
+
+type Queue = Channel<MultiCore, Packet, SUBSCRIBER_DEPTH>;
+type Channels = RcChannelsPacked<[u8; 22]>;
+
+/// Which received frames a [`Subscriber`] gets
+#[derive(Clone, Copy)]
+pub(crate) struct Filter {
+    /// Extended frames are only delivered if they are addressed to one of
+    /// these or broadcast
+    addresses: &'static [Address],
+    accept: fn(&Packet) -> bool,
+}
+
+#[cfg_attr(
+    not(test),
+    expect(dead_code, reason = "no HAL provides the module UART to run it on yet")
+)]
+impl Filter {
+    pub(crate) const fn new(addresses: &'static [Address], accept: fn(&Packet) -> bool) -> Self {
+        Self { addresses, accept }
+    }
+
+    fn matches(&self, packet: &Packet) -> bool {
+        let addressed = match packet.destination() {
+            None | Some(Address::Broadcast) => true,
+            Some(to) => self.addresses.contains(&to),
+        };
+
+        addressed && (self.accept)(packet)
+    }
+}
+
+pub(crate) struct Router {
+    queues: [Queue; SUBSCRIBERS],
+    filters: Mutex<MultiCore, RefCell<[Option<Filter>; SUBSCRIBERS]>>,
+    /// Only the latest channels are worth sending
+    rc: Signal<MultiCore, Channels>,
+    outbound: Channel<MultiCore, Packet, OUTBOUND_DEPTH>,
+    /// Channel frame cadence, locked to the module's OpenTX sync frames
+    timing: Mutex<MultiCore, RefCell<FrameTiming>>,
+}
+
+#[cfg_attr(
+    not(test),
+    expect(dead_code, reason = "no HAL provides the module UART to run it on yet")
+)]
+impl Router {
+    pub(crate) const fn new() -> Self {
+        Self {
+            queues: [const { Queue::new() }; SUBSCRIBERS],
+            filters: Mutex::new(RefCell::new([None; SUBSCRIBERS])),
+            rc: Signal::new(),
+            outbound: Channel::new(),
+            timing: Mutex::new(RefCell::new(FrameTiming::new())),
+        }
+    }
+
+    /// Start receiving frames that match `filter`. Returns `None` if all
+    /// subscriber slots are taken.
+    pub(crate) fn subscribe(&'static self, filter: Filter) -> Option<Subscriber> {
+        self.filters.lock(|filters| {
+            let mut filters = filters.borrow_mut();
+            let index = filters.iter().position(Option::is_none)?;
+            filters[index] = Some(filter);
+            Some(Subscriber {
+                router: self,
+                index,
+            })
+        })
+    }
+
+    /// Queue a frame to send, waiting if the queue is full
+    pub(crate) async fn send(&self, packet: Packet) {
+        self.outbound.send(packet).await;
+    }
+
+    /// Set the channels to send next, replacing any that have not been sent
+    /// yet. These go out ahead of any frames queued with [`send`](Self::send).
+    pub(crate) fn send_channels(&self, channels: Channels) {
+        self.rc.signal(channels);
+    }
+
+    /// Time to wait before sending the next channels, so they arrive just
+    /// before the module transmits
+    pub(crate) fn next_channel_period(&self, now: Instant) -> Duration {
+        self.timing
+            .lock(|timing| timing.borrow_mut().next_period(now))
+    }
+
+    pub(crate) async fn run<U: Read + Write>(&self, mut transport: Transport<U>) -> ! {
+        loop {
+            let next = select3(self.rc.wait(), self.outbound.receive(), transport.read());
+
+            let outgoing = match next.await {
+                Either3::First(channels) => Packet::RcChannelsPacked(channels),
+                Either3::Second(packet) => packet,
+                Either3::Third(Ok(packet)) => {
+                    // Replies go out straight away, ahead of anything queued
+                    let Some(reply) = self.receive(&packet, Instant::now()) else {
+                        continue;
+                    };
+                    reply
+                }
+                Either3::Third(Err(err)) => {
+                    loog::warn!("Failed to read CRSF frame: {:?}", loog::Debug2Format(&err));
+                    continue;
+                }
+            };
+
+            if let Err(err) = transport.write(&outgoing).await {
+                loog::warn!("Failed to send CRSF frame: {:?}", loog::Debug2Format(&err));
+            }
+        }
+    }
+
+    /// Handle a received frame, returning the reply to send if there is one
+    fn receive(&self, packet: &Packet, now: Instant) -> Option<Packet> {
+        if let Packet::RadioId {
+            interval, offset, ..
+        } = *packet
+        {
+            self.timing
+                .lock(|timing| timing.borrow_mut().sync(interval, offset, now));
+        }
+
+        self.dispatch(packet);
+        super::reply(packet)
+    }
+
+    fn dispatch(&self, packet: &Packet) {
+        self.filters.lock(|filters| {
+            let filters = filters.borrow();
+            for (filter, queue) in filters.iter().zip(&self.queues) {
+                let Some(filter) = filter else { continue };
+
+                // Never block the UART on a slow subscriber
+                if filter.matches(packet) && queue.try_send(packet.clone()).is_err() {
+                    loog::warn!("CRSF subscriber queue full, dropping frame");
+                }
+            }
+        });
+    }
+}
+
+/// Receives the frames matching a [`Filter`]. Unsubscribes when dropped.
+pub(crate) struct Subscriber {
+    router: &'static Router,
+    index: usize,
+}
+
+#[cfg_attr(
+    not(test),
+    expect(dead_code, reason = "no HAL provides the module UART to run it on yet")
+)]
+impl Subscriber {
+    pub(crate) async fn receive(&self) -> Packet {
+        self.router.queues[self.index].receive().await
+    }
+
+    pub(crate) fn try_receive(&self) -> Option<Packet> {
+        self.router.queues[self.index].try_receive().ok()
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        self.router.filters.lock(|filters| {
+            filters.borrow_mut()[self.index] = None;
+        });
+        self.router.queues[self.index].clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use core::convert::Infallible;
+    use core::future;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use embassy_futures::select::{Either, select};
+    use vertx_crsf::Duplex;
+
+    use super::*;
+
+    fn ping(to: Address) -> Packet {
+        Packet::DevicePing {
+            to,
+            from: Address::Transmitter,
+        }
+    }
+
+    fn any(_: &Packet) -> bool {
+        true
+    }
+
+    #[tokio::test]
+    async fn filters() {
+        static ROUTER: Router = Router::new();
+
+        let handset = ROUTER
+            .subscribe(Filter::new(&[Address::Handset], any))
+            .unwrap();
+        let lua = ROUTER
+            .subscribe(Filter::new(&[Address::ElrsLua], |packet| {
+                matches!(packet, Packet::DevicePing { .. })
+            }))
+            .unwrap();
+
+        ROUTER.dispatch(&ping(Address::Handset));
+        ROUTER.dispatch(&ping(Address::ElrsLua));
+        ROUTER.dispatch(&ping(Address::Broadcast));
+        ROUTER.dispatch(&Packet::FlightMode(Default::default()));
+
+        assert_eq!(handset.receive().await, ping(Address::Handset));
+        let handset: Vec<_> = core::iter::from_fn(|| handset.try_receive()).collect();
+        assert_eq!(
+            handset,
+            [
+                ping(Address::Broadcast),
+                Packet::FlightMode(Default::default()),
+            ]
+        );
+
+        let lua: Vec<_> = core::iter::from_fn(|| lua.try_receive()).collect();
+        assert_eq!(lua, [ping(Address::ElrsLua), ping(Address::Broadcast)]);
+    }
+
+    #[test]
+    fn unsubscribe() {
+        static ROUTER: Router = Router::new();
+
+        let subscribers: Vec<_> =
+            core::iter::repeat_with(|| ROUTER.subscribe(Filter::new(&[], any)).unwrap())
+                .take(SUBSCRIBERS)
+                .collect();
+        assert!(ROUTER.subscribe(Filter::new(&[], any)).is_none());
+
+        ROUTER.dispatch(&ping(Address::Broadcast));
+        drop(subscribers);
+
+        let subscriber = ROUTER.subscribe(Filter::new(&[], any)).unwrap();
+        assert_eq!(subscriber.try_receive(), None);
+    }
+
+    #[test]
+    fn answers_ping() {
+        static ROUTER: Router = Router::new();
+
+        let now = Instant::from_millis(0);
+        let reply = ROUTER.receive(&ping(Address::Handset), now).unwrap();
+        assert!(matches!(
+            reply,
+            Packet::DeviceInfo {
+                to: Address::Transmitter,
+                from: Address::Handset,
+                ..
+            }
+        ));
+
+        assert_eq!(ROUTER.receive(&ping(Address::ElrsLua), now), None);
+    }
+
+    #[test]
+    fn channel_timing() {
+        static ROUTER: Router = Router::new();
+
+        let now = Instant::from_millis(1000);
+        let sync = Packet::RadioId {
+            to: Address::Handset,
+            from: Address::Transmitter,
+            interval: 20_000,
+            offset: 0,
+        };
+        assert_eq!(ROUTER.receive(&sync, now), None);
+
+        assert_eq!(
+            ROUTER.next_channel_period(now),
+            Duration::from_micros(2_000)
+        );
+    }
+
+    /// Records writes and never has anything to read
+    #[derive(Default)]
+    struct SinkUart(Rc<RefCell<Vec<u8>>>);
+
+    impl embedded_io_async::ErrorType for SinkUart {
+        type Error = Infallible;
+    }
+
+    impl Read for SinkUart {
+        async fn read(&mut self, _buf: &mut [u8]) -> Result<usize, Self::Error> {
+            future::pending().await
+        }
+    }
+
+    impl Write for SinkUart {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    #[tokio::test]
+    async fn channels_first() {
+        static ROUTER: Router = Router::new();
+
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let transport = Transport::new(SinkUart(sent.clone()), Duplex::Full);
+
+        ROUTER.send(ping(Address::Transmitter)).await;
+        ROUTER.send_channels(RcChannelsPacked::pack(&[172; 16]));
+        // Replaces the previous channels
+        ROUTER.send_channels(RcChannelsPacked::pack(&[992; 16]));
+
+        // Channels, then ping
+        let done = async {
+            while sent.borrow().len() < 26 + 6 {
+                tokio::task::yield_now().await;
+            }
+        };
+        let Either::Second(()) = select(ROUTER.run(transport), done).await;
+
+        let sent = sent.borrow();
+        let packets: Vec<_> = [&sent[0..26], &sent[26..]]
+            .into_iter()
+            .map(|mut frame| Packet::read(&mut frame).unwrap())
+            .collect();
+        assert_eq!(
+            packets,
+            [
+                Packet::RcChannelsPacked(RcChannelsPacked::pack(&[992; 16])),
+                ping(Address::Transmitter),
+            ]
+        );
+    }
+}
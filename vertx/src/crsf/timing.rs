@@ -0,0 +1,269 @@
+//! Phase-locks the channel frame cadence to the transmitter module using
+//! OpenTX sync ([`Packet::RadioId`](vertx_crsf::Packet::RadioId)) frames
+//!
+//! Each sync frame carries the desired interval and how early the last channel
+//! frame arrived. Half of the offset is corrected immediately, and part of the
+//! average error per frame since the previous sync is integrated into a trim
+//! that cancels out clock drift between the handset and the module.
+
+use embassy_time::{Duration, Instant, TICK_HZ};
+
+/// Used until the first sync frame arrives, or after sync is lost
+const DEFAULT_INTERVAL: u32 = 40_000;
+/// Sync frames asking for a faster rate than 1kHz are ignored as garbage
+const MIN_INTERVAL: u32 = 10_000;
+/// Sync frames asking for a slower rate than 25Hz, the slowest ExpressLRS
+/// packet rate, are ignored as garbage
+const MAX_INTERVAL: u32 = 400_000;
+/// Sync is considered lost if no sync frame arrives for this long
+const SYNC_TIMEOUT: Duration = Duration::from_millis(500);
+/// Fractional bits of [`FrameTiming::trim`]
+const TRIM_SHIFT: u32 = 10;
+/// Units of 0.1µs per second
+const TENTHS_PER_SECOND: u64 = 10_000_000;
+
+#[derive(Debug)]
+pub(crate) struct FrameTiming {
+    /// Nominal interval requested by the transmitter in 0.1µs
+    interval: u32,
+    /// One-shot correction for the next period in 0.1µs
+    adjustment: i64,
+    /// Persistent correction for clock drift in 0.1µs, with [`TRIM_SHIFT`]
+    /// fractional bits
+    trim: i64,
+    /// Frames sent since the last sync
+    frames: u32,
+    last_sync: Option<Instant>,
+    /// Sub-tick remainder carried over between periods, so the average period
+    /// is exact regardless of tick rate
+    carry: u64,
+}
+
+impl FrameTiming {
+    pub(crate) const fn new() -> Self {
+        Self {
+            interval: DEFAULT_INTERVAL,
+            adjustment: 0,
+            trim: 0,
+            frames: 0,
+            last_sync: None,
+            carry: 0,
+        }
+    }
+
+    /// Handle an OpenTX sync frame. Frames with an implausible interval are
+    /// ignored.
+    pub(crate) fn sync(&mut self, interval: u32, offset: i32, now: Instant) {
+        if !(MIN_INTERVAL..=MAX_INTERVAL).contains(&interval) {
+            return;
+        }
+
+        if interval != self.interval {
+            self.trim = 0;
+        }
+
+        // The offset is relative to whichever transmission came next, so a frame
+        // that just missed one looks almost a full interval early
+        let offset = i64::from(offset) % i64::from(interval);
+        let offset = if offset > i64::from(interval / 2) {
+            offset - i64::from(interval)
+        } else {
+            offset
+        };
+        if self.last_sync.is_some() && self.frames > 0 {
+            self.trim += (offset << TRIM_SHIFT) / (8 * i64::from(self.frames));
+            let max_trim = i64::from(interval / 64) << TRIM_SHIFT;
+            self.trim = self.trim.clamp(-max_trim, max_trim);
+        }
+
+        self.interval = interval;
+        self.adjustment = offset / 2;
+        self.frames = 0;
+        self.last_sync = Some(now);
+    }
+
+    pub(crate) fn is_synced(&self, now: Instant) -> bool {
+        self.last_sync
+            .is_some_and(|last| now.saturating_duration_since(last) < SYNC_TIMEOUT)
+    }
+
+    /// Time to wait before sending the next channel frame
+    pub(crate) fn next_period(&mut self, now: Instant) -> Duration {
+        if !self.is_synced(now) {
+            *self = Self::new();
+        }
+
+        let interval = i64::from(self.interval);
+        let max_adjustment = interval / 4;
+        let adjustment = self.adjustment.clamp(-max_adjustment, max_adjustment);
+        self.adjustment = 0;
+
+        let period = interval + adjustment + (self.trim >> TRIM_SHIFT);
+        // Keep the fractional part of the trim from being rounded away
+        let fraction = self.trim & ((1 << TRIM_SHIFT) - 1);
+        let period = ((period.max(1) as u64) << TRIM_SHIFT) | fraction as u64;
+
+        self.frames = self.frames.saturating_add(1);
+
+        let scaled = period * TICK_HZ + self.carry;
+        let divisor = TENTHS_PER_SECOND << TRIM_SHIFT;
+        self.carry = scaled % divisor;
+        Duration::from_ticks(scaled / divisor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use vertx_crsf::mock::{self, MockTransmitter};
+    use vertx_crsf::{Packet, RcChannelsPacked};
+
+    use super::*;
+
+    /// Safety margin the transmitter subtracts from the raw offset, in 0.1µs
+    const MARGIN: i64 = 1_000;
+
+    struct Simulation {
+        /// Module packet interval in 0.1µs
+        interval: i64,
+        /// Handset clock error in parts per million, positive is fast
+        drift_ppm: i64,
+        /// Module packets between sync frames
+        sync_every: u32,
+    }
+
+    impl Simulation {
+        /// Run for `frames` channel frames, returning the offsets reported in
+        /// each sync frame
+        fn run(&self, frames: usize) -> Vec<i64> {
+            let mut timing = FrameTiming::new();
+            let mut offsets = Vec::new();
+
+            // All times in 0.1µs of true time
+            let mut send = 12_345;
+            let mut last_arrival = None;
+            let mut rf = 0;
+            let mut rf_count = 0;
+
+            for _ in 0..frames {
+                // Module packets up to the next channel frame
+                while rf <= send {
+                    if let Some(arrival) = last_arrival {
+                        rf_count += 1;
+                        if rf_count % self.sync_every == 0 {
+                            let offset = rf - arrival - MARGIN;
+                            offsets.push(offset);
+                            timing.sync(
+                                self.interval as u32,
+                                offset as i32,
+                                self.handset_clock(rf),
+                            );
+                        }
+                    }
+                    rf += self.interval;
+                }
+
+                last_arrival = Some(send);
+                let period = timing.next_period(self.handset_clock(send));
+                let period = period.as_ticks() as i64 * 10_000_000 / TICK_HZ as i64;
+                send += period * 1_000_000 / (1_000_000 + self.drift_ppm);
+            }
+
+            offsets
+        }
+
+        fn handset_clock(&self, time: i64) -> Instant {
+            let tenths = time * (1_000_000 + self.drift_ppm) / 1_000_000;
+            Instant::from_micros(tenths as u64 / 10)
+        }
+    }
+
+    #[test]
+    fn locks_to_mock_transmitter() {
+        let config = mock::Config {
+            packet_interval: 2_000,
+            sync_interval: 10,
+            ..Default::default()
+        };
+        let mut module = MockTransmitter::new(config, Vec::new());
+        let mut timing = FrameTiming::new();
+        let mut offsets = Vec::new();
+        let channels = Packet::RcChannelsPacked(RcChannelsPacked::pack(&[992; 16]));
+
+        // In µs
+        let mut now = 1_234;
+        for _ in 0..2_000 {
+            while let Some(packet) = module.poll(now) {
+                if let Packet::RadioId {
+                    interval, offset, ..
+                } = packet
+                {
+                    offsets.push(offset);
+                    timing.sync(interval, offset, Instant::from_micros(now));
+                }
+            }
+
+            module.receive(now, &channels);
+            now += timing.next_period(Instant::from_micros(now)).as_micros();
+        }
+
+        let settled = &offsets[offsets.len() / 2..];
+        let worst = settled.iter().map(|offset| offset.abs()).max().unwrap();
+        assert!(worst <= 10, "worst offset {worst}");
+    }
+
+    #[test]
+    fn default_period() {
+        let mut timing = FrameTiming::new();
+        let now = Instant::from_secs(1);
+        assert!(!timing.is_synced(now));
+        assert_eq!(timing.next_period(now), Duration::from_millis(4));
+    }
+
+    #[test]
+    fn converges() {
+        for drift_ppm in [-500, -100, 0, 100, 500] {
+            for sync_every in [1, 10, 50] {
+                let simulation = Simulation {
+                    interval: 20_000,
+                    drift_ppm,
+                    sync_every,
+                };
+
+                let offsets = simulation.run(5_000);
+                let settled = &offsets[offsets.len() / 2..];
+                let worst = settled.iter().map(|offset| offset.abs()).max().unwrap();
+                assert!(
+                    worst < 50,
+                    "drift {drift_ppm}ppm, sync every {sync_every}: worst offset {worst}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn implausible_interval() {
+        let mut timing = FrameTiming::new();
+        let now = Instant::from_secs(1);
+        timing.sync(20_000, 0, now);
+
+        for interval in [0, 1, MIN_INTERVAL - 1, MAX_INTERVAL + 1, u32::MAX] {
+            timing.sync(interval, 1_000, now);
+        }
+
+        assert_eq!(timing.next_period(now), Duration::from_millis(2));
+    }
+
+    #[test]
+    fn sync_lost() {
+        let mut timing = FrameTiming::new();
+        let start = Instant::from_secs(1);
+        timing.sync(20_000, 0, start);
+        assert_eq!(timing.next_period(start), Duration::from_millis(2));
+
+        let later = start + SYNC_TIMEOUT;
+        assert!(!timing.is_synced(later));
+        assert_eq!(timing.next_period(later), Duration::from_millis(4));
+    }
+}
@@ -1,4 +1,5 @@
 use alloc::vec::Vec;
+use core::ops::Range;
 
 pub(super) const SECTOR_BYTES: u32 = esp_storage::FlashStorage::SECTOR_SIZE;
 const PARTITION_TABLE_ADDRESS: u32 = 0x8000;
@@ -135,14 +136,14 @@ impl Partition {
         }
     }
 
-    /// Erase the first `count` sectors of this partition
-    pub(super) fn erase(&mut self, count: u32) -> Result<(), i32> {
-        assert!(count < self.sectors());
+    /// Erase `sectors` of this partition, relative to its start
+    pub(super) fn erase(&mut self, sectors: Range<u32>) -> Result<(), i32> {
+        assert!(sectors.end <= self.sectors());
 
         let first = self.start / SECTOR_BYTES;
-        for sector in first..(first + count) {
+        for sector in sectors {
             // SAFETY: assert prevents overflowing flash
-            unsafe { esp_storage::ll::spiflash_erase_sector(sector)? }
+            unsafe { esp_storage::ll::spiflash_erase_sector(first + sector)? }
         }
 
         Ok(())
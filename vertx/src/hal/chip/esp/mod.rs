@@ -1,16 +1,23 @@
-#[expect(unused, reason = "preserve for future OTA updates")]
+#[cfg_attr(
+    not(feature = "storage-flash"),
+    expect(unused, reason = "preserve for future OTA updates")
+)]
 mod flash;
 mod leds;
 mod network;
+#[cfg(feature = "storage-flash")]
+mod storage;
 mod ui;
 
 use embassy_executor::Spawner;
 use esp_hal::clock::CpuClock;
+#[cfg(feature = "storage-sd-spi")]
 use esp_hal::dma::{DmaRxBuf, DmaTxBuf};
 use esp_hal::gpio;
 use esp_hal::i2c::master::{self as i2c, I2c};
 use esp_hal::rmt::Rmt;
 use esp_hal::rng::Rng;
+#[cfg(feature = "storage-sd-spi")]
 use esp_hal::spi::master::{self as spi, Spi};
 use esp_hal::time::Rate;
 use esp_hal::timer::timg;
@@ -41,6 +48,7 @@ pub(crate) fn init(spawner: Spawner) -> hal::Init {
 
     let status_led = leds::StatusLed::new(rmt.channel0, pins!(p, leds.status));
 
+    #[cfg(feature = "storage-sd-spi")]
     let spi = {
         #[expect(clippy::manual_div_ceil)]
         let (rx_buffer, rx_descriptors, tx_buffer, tx_descriptors) = esp_hal::dma_buffers!(32000);
@@ -57,6 +65,7 @@ pub(crate) fn init(spawner: Spawner) -> hal::Init {
             .into_async()
     };
 
+    #[cfg(feature = "storage-sd-spi")]
     let storage = async {
         let sd_cs = gpio::Output::new(
             pins!(p, sd.cs),
@@ -75,6 +84,17 @@ pub(crate) fn init(spawner: Spawner) -> hal::Init {
         .await
     };
 
+    #[cfg(feature = "storage-flash")]
+    let storage = async {
+        static BUFFERS: ConstStaticCell<vertx_filesystem::Buffers<aligned::A4>> =
+            ConstStaticCell::new(vertx_filesystem::Buffers::new());
+        let buffers = BUFFERS.take();
+
+        let flash = storage::config_storage();
+        let size = flash.size();
+        crate::storage::flash::new(buffers, flash, 0..size).await
+    };
+
     let ui = {
         let config = i2c::Config::default().with_frequency(Rate::from_mhz(1));
 
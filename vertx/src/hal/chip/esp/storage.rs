@@ -0,0 +1,104 @@
+use embedded_storage_async::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+#[cfg(feature = "defmt")]
+use loog::defmt;
+
+use super::flash::{self, Partition, SECTOR_BYTES};
+
+/// Find the config partition and wrap it for use as filesystem storage
+pub(super) fn config_storage() -> PartitionFlash {
+    loog::unwrap!(flash::unlock());
+
+    let config = flash::read_partition_table()
+        .into_iter()
+        .flatten()
+        .find(Partition::is_config);
+    let Some(config) = config else {
+        loog::panic!("Missing config partition");
+    };
+
+    PartitionFlash(config)
+}
+
+/// Async [`NorFlash`] access to a partition. Offsets are relative to the start
+/// of the partition.
+#[derive(Debug)]
+pub(super) struct PartitionFlash(Partition);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(super) enum FlashError {
+    NotAligned,
+    OutOfBounds,
+    /// Error code returned by the ROM
+    Rom(i32),
+}
+
+impl NorFlashError for FlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Self::NotAligned => NorFlashErrorKind::NotAligned,
+            Self::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            Self::Rom(_) => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+impl PartitionFlash {
+    pub(super) fn size(&self) -> u32 {
+        self.0.size
+    }
+
+    /// Convert a byte range into a word offset, checking it is in bounds
+    fn offset_words(&self, offset: u32, len: usize) -> Result<u32, FlashError> {
+        if !offset.is_multiple_of(4) {
+            Err(FlashError::NotAligned)
+        } else if offset as usize + len > self.0.size as usize {
+            Err(FlashError::OutOfBounds)
+        } else {
+            Ok(offset / 4)
+        }
+    }
+}
+
+impl ErrorType for PartitionFlash {
+    type Error = FlashError;
+}
+
+impl ReadNorFlash for PartitionFlash {
+    const READ_SIZE: usize = 4;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let offset = self.offset_words(offset, bytes.len())?;
+        let words = bytemuck::try_cast_slice_mut(bytes).map_err(|_| FlashError::NotAligned)?;
+        self.0.read_into(offset, words).map_err(FlashError::Rom)
+    }
+
+    fn capacity(&self) -> usize {
+        self.0.size as usize
+    }
+}
+
+impl NorFlash for PartitionFlash {
+    const ERASE_SIZE: usize = SECTOR_BYTES as usize;
+    const WRITE_SIZE: usize = 4;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if !from.is_multiple_of(SECTOR_BYTES) || !to.is_multiple_of(SECTOR_BYTES) {
+            return Err(FlashError::NotAligned);
+        }
+        if from > to || to > self.0.size {
+            return Err(FlashError::OutOfBounds);
+        }
+
+        let sectors = from / SECTOR_BYTES..to / SECTOR_BYTES;
+        self.0.erase(sectors).map_err(FlashError::Rom)
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let offset = self.offset_words(offset, bytes.len())?;
+        let words = bytemuck::try_cast_slice(bytes).map_err(|_| FlashError::NotAligned)?;
+        self.0.write(offset, words).map_err(FlashError::Rom)
+    }
+}
@@ -0,0 +1,7 @@
+MEMORY {
+    BOOT2   : ORIGIN = 0x10000000, LENGTH = 0x100
+    FLASH   : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 256K
+    /* Kept out of the firmware for `storage-flash` */
+    STORAGE : ORIGIN = 0x10000000 + 2048K - 256K, LENGTH = 256K
+    RAM     : ORIGIN = 0x20000000, LENGTH = 264K
+}
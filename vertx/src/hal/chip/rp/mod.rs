@@ -4,6 +4,7 @@ mod ui;
 use embassy_executor::Spawner;
 use embassy_rp::i2c::{self, I2c};
 use embassy_rp::pio::{self, Pio};
+#[cfg(feature = "storage-sd-spi")]
 use embassy_rp::spi::{self, Spi};
 use embassy_rp::watchdog::Watchdog;
 use embassy_rp::{bind_interrupts, gpio, peripherals};
@@ -58,6 +59,7 @@ pub(crate) fn init(_spawner: Spawner) -> hal::Init {
         leds::StatusDriver::<_, 0>::new(&mut common, sm0, pin)
     };
 
+    #[cfg(feature = "storage-sd-spi")]
     let spi = Spi::new(
         p.SPI1,
         pins!(p, spi.sclk),
@@ -68,6 +70,7 @@ pub(crate) fn init(_spawner: Spawner) -> hal::Init {
         spi::Config::default(),
     );
 
+    #[cfg(feature = "storage-sd-spi")]
     let storage = async {
         static BUFFERS: ConstStaticCell<vertx_filesystem::Buffers<aligned::A1>> =
             ConstStaticCell::new(vertx_filesystem::Buffers::new());
@@ -77,6 +80,23 @@ pub(crate) fn init(_spawner: Spawner) -> hal::Init {
         crate::storage::sd_spi::new_exclusive_spi(buffers, spi, sd_cs, Spi::set_frequency).await
     };
 
+    #[cfg(feature = "storage-flash")]
+    let storage = async {
+        use embassy_rp::flash::{Async, Flash};
+
+        const FLASH_BYTES: usize = 2048 * 1024;
+        /// Must match the `STORAGE` region in `memory-flash.x`
+        const STORAGE_BYTES: u32 = 256 * 1024;
+
+        static BUFFERS: ConstStaticCell<vertx_filesystem::Buffers<aligned::A4>> =
+            ConstStaticCell::new(vertx_filesystem::Buffers::new());
+        let buffers = BUFFERS.take();
+
+        let flash = Flash::<_, Async, FLASH_BYTES>::new(p.FLASH, p.DMA_CH2);
+        let end = FLASH_BYTES as u32;
+        crate::storage::flash::new(buffers, flash, end - STORAGE_BYTES..end).await
+    };
+
     let ui = {
         let scl = pins!(p, display.scl);
         let sda = pins!(p, display.sda);
@@ -92,7 +92,7 @@ pub(crate) fn init(_spawner: Spawner) -> hal::Init {
     hal::Init {
         reset: Reset,
         status_led: StatusLed,
-        storage: async { storage::Storage },
+        storage: async { (storage::Storage, None) },
         ui: ui::Ui::new(UI_INPUTS.receiver()),
         configurator: configurator::Configurator,
     }
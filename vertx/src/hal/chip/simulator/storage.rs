@@ -1,16 +1,68 @@
-use std::convert::Infallible;
 use std::string::String;
 use std::vec::Vec;
 
-use embedded_io_async::{ErrorType, Read, ReadExactError, Seek, SeekFrom, Write};
+use embedded_io_async::{ErrorKind, ErrorType, Read, ReadExactError, Seek, SeekFrom, Write};
 
 use super::ipc;
+use crate::models::NAME_LEN;
 use crate::storage::pal;
 
+/// Same limit as the SD card filesystem
+const MAX_MODELS: crate::models::Id = 64;
+/// Model IDs in listing order, one byte each
+const ORDER_PATH: &str = "model/order";
+const FILE_DIR: &str = "file/";
+
 #[derive(Debug, Clone, Copy)]
 pub(super) struct Storage;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Error {
+    TooManyModels,
+    ModelNameOverflow,
+}
+
+impl embedded_io_async::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::TooManyModels => ErrorKind::InvalidData,
+            Self::ModelNameOverflow => ErrorKind::InvalidInput,
+        }
+    }
+}
+
 impl Storage {
+    /// IDs of every model in listing order. Any missing from [`ORDER_PATH`] are
+    /// listed last, sorted by ID.
+    fn order(&self) -> Vec<crate::models::Id> {
+        let mut ids = Vec::new();
+        for model_str in ipc::fs_list("model/name/") {
+            let (model, len) = atoi::FromRadix10::from_radix_10(model_str.as_bytes());
+            if len == 0 || len != model_str.len() {
+                loog::warn!("Skipping invalid model name: '{model_str}'");
+                continue;
+            }
+
+            ids.push(model);
+        }
+        ids.sort_unstable();
+
+        let mut order = ipc::fs_read(ORDER_PATH).unwrap_or_default();
+        order.retain(|id| ids.contains(id));
+        for id in ids {
+            if !order.contains(&id) {
+                order.push(id);
+            }
+        }
+        order
+    }
+
+    fn file_path(&self, name: &str) -> String {
+        let mut path = String::from(FILE_DIR);
+        path.push_str(name);
+        path
+    }
+
     fn path<I: itoa::Integer>(&self, dir: &str, id: I) -> String {
         let mut buffer = itoa::Buffer::new();
         let id = buffer.format(id);
@@ -23,7 +75,7 @@ impl Storage {
 }
 
 impl ErrorType for Storage {
-    type Error = Infallible;
+    type Error = Error;
 }
 
 impl pal::Storage for Storage {
@@ -51,19 +103,10 @@ impl pal::Storage for Storage {
     where
         F: FnMut(crate::models::Id, &str),
     {
-        for model_str in ipc::fs_list("model/name/") {
-            let (model, len) = atoi::FromRadix10::from_radix_10(model_str.as_bytes());
-            if len == 0 || len != model_str.len() {
-                loog::warn!("Skipping invalid model name: '{model_str}'");
-                continue;
-            }
-
-            let mut path = String::from("model/name/");
-            path.push_str(&model_str);
-
-            let name = ipc::fs_read(&path).unwrap();
+        for id in self.order() {
+            let name = ipc::fs_read(&self.path("model/name", id)).unwrap();
             let name = String::from_utf8(name).expect("model name is valid UTF-8");
-            f(model, &name);
+            f(id, &name);
         }
 
         Ok(())
@@ -80,6 +123,98 @@ impl pal::Storage for Storage {
     async fn delete_model(&mut self, id: crate::models::Id) -> Result<(), Self::Error> {
         ipc::fs_delete(&self.path("model/data", id));
         ipc::fs_delete(&self.path("model/name", id));
+        ipc::fs_write(ORDER_PATH, &self.order());
+        Ok(())
+    }
+
+    async fn rename_model(&mut self, id: crate::models::Id, name: &str) -> Result<(), Self::Error> {
+        if name.len() > NAME_LEN {
+            return Err(Error::ModelNameOverflow);
+        }
+
+        let path = self.path("model/name", id);
+        if ipc::fs_read(&path).is_some() {
+            ipc::fs_write(&path, name.as_bytes());
+        } else {
+            loog::warn!("There is no model {id}");
+        }
+        Ok(())
+    }
+
+    async fn copy_model(
+        &mut self,
+        id: crate::models::Id,
+        name: &str,
+    ) -> Result<Option<crate::models::Id>, Self::Error> {
+        if name.len() > NAME_LEN {
+            return Err(Error::ModelNameOverflow);
+        }
+
+        let Some(data) = ipc::fs_read(&self.path("model/data", id)) else {
+            loog::warn!("There is no model {id}");
+            return Ok(None);
+        };
+
+        let mut order = self.order();
+        let copy = (0..MAX_MODELS)
+            .find(|id| !order.contains(id))
+            .ok_or(Error::TooManyModels)?;
+
+        ipc::fs_write(&self.path("model/data", copy), &data);
+        ipc::fs_write(&self.path("model/name", copy), name.as_bytes());
+        order.push(copy);
+        ipc::fs_write(ORDER_PATH, &order);
+        Ok(Some(copy))
+    }
+
+    async fn move_model(&mut self, id: crate::models::Id, position: u8) -> Result<(), Self::Error> {
+        let mut order = self.order();
+        let Some(from) = order.iter().position(|&model| model == id) else {
+            loog::warn!("There is no model {id}");
+            return Ok(());
+        };
+
+        order.remove(from);
+        order.insert(usize::from(position).min(order.len()), id);
+        ipc::fs_write(ORDER_PATH, &order);
+        Ok(())
+    }
+
+    async fn file_names<F>(&mut self, mut f: F) -> Result<(), Self::Error>
+    where
+        F: FnMut(&str),
+    {
+        for name in ipc::fs_list(FILE_DIR) {
+            f(&name);
+        }
+
+        Ok(())
+    }
+
+    async fn file(&mut self, name: &str) -> Result<Option<Self::File<'_>>, Self::Error> {
+        Ok(File::open(self.file_path(name)))
+    }
+
+    async fn append_file(&mut self, name: &str) -> Result<Option<Self::File<'_>>, Self::Error> {
+        let file = File::open(self.file_path(name)).map(|mut file| {
+            file.cursor = file.data.len();
+            file
+        });
+        Ok(file)
+    }
+
+    async fn create_file(&mut self, name: &str) -> Result<Self::File<'_>, Self::Error> {
+        let path = self.file_path(name);
+        ipc::fs_write(&path, &[]);
+        Ok(File {
+            path,
+            data: Vec::new(),
+            cursor: 0,
+        })
+    }
+
+    async fn delete_file(&mut self, name: &str) -> Result<(), Self::Error> {
+        ipc::fs_delete(&self.file_path(name));
         Ok(())
     }
 
@@ -111,7 +246,7 @@ impl File {
 }
 
 impl ErrorType for File {
-    type Error = Infallible;
+    type Error = Error;
 }
 
 impl Seek for File {
@@ -138,7 +273,7 @@ impl Read for File {
             return Err(ReadExactError::UnexpectedEof);
         }
 
-        let Ok(_) = self.read(buf).await;
+        self.read(buf).await?;
         Ok(())
     }
 }
@@ -1,6 +1,7 @@
 use core::convert::Infallible;
 use core::task;
 use std::string::String;
+use std::vec::Vec;
 use std::{format, future};
 
 use display_interface::DisplayError;
@@ -9,17 +10,23 @@ use embedded_io_async::ErrorType;
 
 use crate::hal;
 
-#[define_opaque(hal::Reset, hal::StatusLed, hal::StorageFuture, hal::Ui, hal::Network)]
+#[define_opaque(hal::Reset, hal::StatusLed, hal::Ui, hal::Network)]
 pub(crate) fn init(_spawner: embassy_executor::Spawner) -> hal::Init {
     hal::Init {
         reset: Reset,
         status_led: StatusLed,
-        storage: async { Storage },
+        storage: storage_with(Vec::new()),
         ui: Ui,
         network: Network,
     }
 }
 
+/// Storage holding models with the given IDs and names, in listing order
+#[define_opaque(hal::StorageFuture)]
+pub(crate) fn storage_with(models: Vec<(crate::models::Id, String)>) -> hal::StorageFuture {
+    async move { (Storage { models }, None) }
+}
+
 struct Reset;
 
 impl hal::traits::Reset for Reset {
@@ -43,7 +50,10 @@ impl hal::traits::StatusLed for StatusLed {
     }
 }
 
-struct Storage;
+struct Storage {
+    models: Vec<(crate::models::Id, String)>,
+}
+
 #[derive(Clone)]
 struct File(String);
 
@@ -63,11 +73,14 @@ impl crate::storage::pal::Storage for Storage {
         Ok(())
     }
 
-    async fn model_names<F>(&mut self, _f: F) -> Result<(), Self::Error>
+    async fn model_names<F>(&mut self, mut f: F) -> Result<(), Self::Error>
     where
         F: FnMut(crate::models::Id, &str),
     {
         loog::trace!("Listing model names");
+        for (id, name) in &self.models {
+            f(*id, name);
+        }
         Ok(())
     }
 
@@ -81,6 +94,70 @@ impl crate::storage::pal::Storage for Storage {
 
     async fn delete_model(&mut self, id: crate::models::Id) -> Result<(), Self::Error> {
         loog::trace!("Deleting model {id}");
+        self.models.retain(|(model, _)| *model != id);
+        Ok(())
+    }
+
+    async fn rename_model(&mut self, id: crate::models::Id, name: &str) -> Result<(), Self::Error> {
+        loog::trace!("Renaming model {id} to {name:?}");
+        if let Some((_, old)) = self.models.iter_mut().find(|(model, _)| *model == id) {
+            *old = name.into();
+        }
+        Ok(())
+    }
+
+    async fn copy_model(
+        &mut self,
+        id: crate::models::Id,
+        name: &str,
+    ) -> Result<Option<crate::models::Id>, Self::Error> {
+        loog::trace!("Copying model {id} to {name:?}");
+        if !self.models.iter().any(|(model, _)| *model == id) {
+            return Ok(None);
+        }
+
+        let copy = (0..)
+            .find(|id| self.models.iter().all(|(model, _)| model != id))
+            .unwrap();
+        self.models.push((copy, name.into()));
+        Ok(Some(copy))
+    }
+
+    async fn move_model(&mut self, id: crate::models::Id, position: u8) -> Result<(), Self::Error> {
+        loog::trace!("Moving model {id} to position {position}");
+        if let Some(from) = self.models.iter().position(|(model, _)| *model == id) {
+            let model = self.models.remove(from);
+            let to = usize::from(position).min(self.models.len());
+            self.models.insert(to, model);
+        }
+        Ok(())
+    }
+
+    async fn file_names<F>(&mut self, _f: F) -> Result<(), Self::Error>
+    where
+        F: FnMut(&str),
+    {
+        loog::trace!("Listing file names");
+        Ok(())
+    }
+
+    async fn file(&mut self, name: &str) -> Result<Option<Self::File<'_>>, Self::Error> {
+        loog::trace!("Opening file {name:?}");
+        Ok(Some(File(format!("file/{name}"))))
+    }
+
+    async fn append_file(&mut self, name: &str) -> Result<Option<Self::File<'_>>, Self::Error> {
+        loog::trace!("Appending to file {name:?}");
+        Ok(Some(File(format!("file/{name}"))))
+    }
+
+    async fn create_file(&mut self, name: &str) -> Result<Self::File<'_>, Self::Error> {
+        loog::trace!("Creating file {name:?}");
+        Ok(File(format!("file/{name}")))
+    }
+
+    async fn delete_file(&mut self, name: &str) -> Result<(), Self::Error> {
+        loog::trace!("Deleting file {name:?}");
         Ok(())
     }
 
@@ -31,7 +31,8 @@ mod display;
 include!(concat!(env!("OUT_DIR"), "/pins.rs"));
 
 pub(crate) type Reset = impl crate::hal::traits::Reset;
-pub(crate) type StorageFuture = impl core::future::Future<Output = Storage>;
+pub(crate) type StorageFuture =
+    impl core::future::Future<Output = (Storage, Option<crate::storage::Repaired>)>;
 pub(crate) type Storage =
     impl crate::storage::pal::Storage<Error = impl loog::DebugFormat + embedded_io_async::Error>;
 pub(crate) type StatusLed = impl crate::hal::traits::StatusLed;
@@ -45,6 +46,9 @@ pub(crate) type NetworkDriver = <Network as traits::Network>::Driver;
 
 mod chip;
 
+#[cfg(test)]
+pub(crate) use self::chip::storage_with;
+
 pub(crate) fn init(spawner: embassy_executor::Spawner) -> Init {
     chip::init(spawner)
 }
@@ -8,6 +8,7 @@ extern crate std;
 
 mod build_info;
 mod config;
+mod crsf;
 #[cfg(feature = "configurator")]
 mod configurator;
 mod hal;
@@ -20,6 +21,7 @@ mod mutex;
 mod network;
 mod reset;
 mod storage;
+mod telemetry;
 mod ui;
 mod utils;
 
@@ -38,7 +40,7 @@ pub async fn main(spawner: Spawner) {
     static INITS: InitCounter = InitCounter::new();
     let inits = &INITS;
 
-    let (storage, config, models) = storage::init(hal.storage).await;
+    let (storage, config, models, repaired) = storage::init(hal.storage).await;
 
     let config_manager = config::Manager::new(config).await;
     let config = config_manager.config();
@@ -63,6 +65,7 @@ pub async fn main(spawner: Spawner) {
         config,
         hal.ui,
         models,
+        repaired,
         #[cfg(feature = "configurator")]
         configurator,
     ));
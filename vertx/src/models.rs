@@ -1,9 +1,9 @@
 use core::fmt;
 
-use heapless::{String, Vec};
+use heapless::String;
 
 pub(crate) type Id = u8;
-const NAME_LEN: usize = 16;
+pub(crate) const NAME_LEN: usize = 16;
 pub(crate) type Name = String<NAME_LEN>;
 
 #[derive(Clone, Copy)]
@@ -40,6 +40,27 @@ impl Manager {
             name,
         })
     }
+
+    #[cfg_attr(not(test), expect(unused))]
+    pub(crate) async fn rename(self, id: Id, name: &str) -> Result<(), crate::storage::Error> {
+        self.storage.rename(id, name).await
+    }
+
+    /// Duplicate a model under a new name, returning the ID of the copy
+    #[cfg_attr(not(test), expect(unused))]
+    pub(crate) async fn copy(
+        self,
+        id: Id,
+        name: &str,
+    ) -> Result<Option<Id>, crate::storage::Error> {
+        self.storage.copy(id, name).await
+    }
+
+    /// Change where a model is listed by [`Self::for_each_name`]
+    #[cfg_attr(not(test), expect(unused))]
+    pub(crate) async fn move_to(self, id: Id, position: u8) -> Result<(), crate::storage::Error> {
+        self.storage.move_to(id, position).await
+    }
 }
 
 impl fmt::Debug for Manager {
@@ -71,11 +92,60 @@ impl fmt::Debug for Model {
 }
 
 fn name_from_str(raw_name: &str) -> Name {
-    let len = raw_name.len().min(NAME_LEN);
-    let name = &raw_name.as_bytes()[0..len];
-    let name = Vec::from_slice(name).unwrap();
-    let Ok(name) = String::from_utf8(name) else {
-        loog::panic!("Name wasn't ascii: {raw_name=str:?}");
-    };
+    let mut len = raw_name.len().min(NAME_LEN);
+    while !raw_name.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    let mut name = Name::new();
+    loog::unwrap!(name.push_str(&raw_name[0..len]));
     name
 }
+
+#[cfg(test)]
+mod tests {
+    use std::string::String;
+    use std::vec;
+    use std::vec::Vec;
+
+    use super::*;
+
+    async fn names(manager: Manager) -> Vec<(Id, String)> {
+        let mut names = Vec::new();
+        manager
+            .for_each_name(|id, name| names.push((id, name.into())))
+            .await
+            .unwrap();
+        names
+    }
+
+    #[test]
+    fn name_truncation() {
+        assert_eq!(name_from_str("Quad"), "Quad");
+        assert_eq!(name_from_str("Seventeen letters"), "Seventeen letter");
+        // 'é' is two bytes, so it would be cut in half at 16 bytes
+        assert_eq!(name_from_str("Fifteen letterté"), "Fifteen lettert");
+    }
+
+    #[tokio::test]
+    async fn rename_copy_and_move() {
+        let storage = crate::hal::storage_with(vec![(3, "Quad".into()), (5, "Wing".into())]);
+        let (_, _, models, _) = crate::storage::init(storage).await;
+        let manager = Manager::new(models);
+
+        manager.rename(3, "Race quad").await.unwrap();
+        assert_eq!(manager.copy(3, "Race quad 2").await.unwrap(), Some(0));
+        assert_eq!(manager.copy(1, "Missing").await.unwrap(), None);
+        manager.move_to(0, 0).await.unwrap();
+
+        assert_eq!(
+            names(manager).await,
+            [
+                (0, "Race quad 2".into()),
+                (3, "Race quad".into()),
+                (5, "Wing".into()),
+            ]
+        );
+        assert_eq!(manager.open(0).await.unwrap().name(), "Race quad 2");
+    }
+}
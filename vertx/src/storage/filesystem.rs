@@ -0,0 +1,113 @@
+use block_device_driver::BlockDevice;
+use delegate::delegate;
+use vertx_filesystem::{
+    BLOCK_BYTES, Buffers, File, Filesystem, HeaderError, InitError, MAX_CONFIG_BYTES,
+};
+
+use super::{Repaired, pal};
+
+const _: () = assert!(crate::config::BYTE_LENGTH <= MAX_CONFIG_BYTES);
+
+/// Open the filesystem on `device`, repairing the header if it is damaged
+pub(super) async fn mount<D>(
+    device: D,
+    buffers: &mut Buffers<D::Align>,
+) -> (Filesystem<'_, D>, Option<Repaired>)
+where
+    D: BlockDevice<BLOCK_BYTES>,
+    D::Error: loog::DebugFormat,
+{
+    match Filesystem::new(device, buffers).await {
+        Ok(fs) => (fs, None),
+        Err(InitError::HeaderError {
+            kind: HeaderError::Version,
+            ..
+        }) => {
+            loog::panic!("Storage holds an unsupported filesystem version; refusing to modify it");
+        }
+        Err(InitError::HeaderError {
+            kind,
+            device,
+            buffers,
+        }) => {
+            loog::warn!("Filesystem header is invalid ({kind:?}); repairing");
+            match Filesystem::fsck(device, buffers).await {
+                Ok((fs, report)) => {
+                    if report.dropped_files > 0 {
+                        loog::warn!("Dropped {} damaged files", report.dropped_files);
+                    }
+                    let repaired = Repaired {
+                        recovered: report.recovered,
+                        dropped: report.dropped(),
+                    };
+                    (fs, Some(repaired))
+                }
+                Err(err) => loog::panic!("Error while repairing filesystem: {err:?}"),
+            }
+        }
+        Err(InitError::Io(err)) => {
+            loog::panic!("IO error while opening filesystem: {err:?}");
+        }
+    }
+}
+
+impl<'buf, D: BlockDevice<BLOCK_BYTES>> pal::Storage for Filesystem<'buf, D>
+where
+    D::Error: embedded_io_async::Error,
+{
+    type File<'s>
+        = File<'buf, 's, D>
+    where
+        Self: 's;
+
+    delegate! {
+        to self {
+            async fn read_config<'a>(&mut self, buf: &'a mut [u8]) -> Result<&'a [u8], Self::Error>;
+            async fn write_config(&mut self, config: &[u8]) -> Result<(), Self::Error>;
+
+            async fn model_names<F>(&mut self, f: F) -> Result<(), Self::Error>
+            where
+                F: FnMut(crate::models::Id, &str);
+
+            async fn model(&mut self, id: crate::models::Id)
+                -> Result<Option<Self::File<'_>>, Self::Error>;
+
+            async fn delete_model(&mut self, id: crate::models::Id) -> Result<(), Self::Error>;
+            async fn rename_model(&mut self, id: crate::models::Id, name: &str)
+                -> Result<(), Self::Error>;
+            async fn copy_model(&mut self, id: crate::models::Id, name: &str)
+                -> Result<Option<crate::models::Id>, Self::Error>;
+            async fn move_model(&mut self, id: crate::models::Id, position: u8)
+                -> Result<(), Self::Error>;
+
+            async fn file_names<F>(&mut self, f: F) -> Result<(), Self::Error>
+            where
+                F: FnMut(&str);
+            async fn file(&mut self, name: &str) -> Result<Option<Self::File<'_>>, Self::Error>;
+            async fn append_file(&mut self, name: &str)
+                -> Result<Option<Self::File<'_>>, Self::Error>;
+            async fn create_file(&mut self, name: &str) -> Result<Self::File<'_>, Self::Error>;
+            async fn delete_file(&mut self, name: &str) -> Result<(), Self::Error>;
+
+            async fn flush(&mut self) -> Result<(), Self::Error>;
+        }
+    }
+}
+
+impl<D: BlockDevice<BLOCK_BYTES>> pal::File for File<'_, '_, D>
+where
+    D::Error: embedded_io_async::Error,
+{
+    delegate! {
+        to self {
+            #[await(false)]
+            async fn len(&mut self) -> u64;
+
+            #[await(false)]
+            #[expr($; Ok(()))]
+            async fn truncate(&mut self) -> Result<(), Self::Error>;
+
+            async fn close(self) -> Result<(), Self::Error>;
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use core::ops::Range;
+
+use embedded_storage_async::nor_flash::NorFlash;
+use vertx_filesystem::flash::{self, FlashDevice};
+use vertx_filesystem::{Buffers, Filesystem};
+
+use super::Repaired;
+
+/// Most sectors the filesystem will use, regardless of the size of the region
+const SECTORS: usize = 64;
+
+type Device<F> = FlashDevice<F, SECTORS, { flash::blocks(SECTORS) }>;
+
+pub(crate) async fn new<F>(
+    buffers: &mut Buffers<aligned::A4>,
+    flash: F,
+    region: Range<u32>,
+) -> (Filesystem<'_, Device<F>>, Option<Repaired>)
+where
+    F: NorFlash,
+    F::Error: loog::DebugFormat,
+{
+    let device = match FlashDevice::new(flash, region).await {
+        Ok(device) => device,
+        Err(err) => loog::panic!("IO error while mounting flash storage: {err:?}"),
+    };
+
+    super::filesystem::mount(device, buffers).await
+}
@@ -1,6 +1,18 @@
+#[cfg(any(feature = "storage-sd-spi", feature = "storage-flash"))]
+mod filesystem;
+#[cfg(feature = "storage-flash")]
+pub(crate) mod flash;
 #[cfg(feature = "storage-sd-spi")]
 pub(crate) mod sd_spi;
 
+#[cfg(all(
+    not(any(test, feature = "simulator")),
+    not(any(feature = "storage-sd-spi", feature = "storage-flash")),
+))]
+compile_error!("enable a storage backend: `storage-sd-spi` or `storage-flash`");
+#[cfg(all(feature = "storage-sd-spi", feature = "storage-flash"))]
+compile_error!("enable only one of `storage-sd-spi` and `storage-flash`");
+
 use embassy_sync::mutex::Mutex;
 use static_cell::StaticCell;
 
@@ -26,6 +38,42 @@ pub(crate) mod pal {
             id: crate::models::Id,
         ) -> Result<Option<Self::File<'_>>, Self::Error>;
         async fn delete_model(&mut self, id: crate::models::Id) -> Result<(), Self::Error>;
+        async fn rename_model(
+            &mut self,
+            id: crate::models::Id,
+            name: &str,
+        ) -> Result<(), Self::Error>;
+        /// Copy the data and name of a model, returning the ID of the copy if
+        /// the original exists. The copy is listed last.
+        async fn copy_model(
+            &mut self,
+            id: crate::models::Id,
+            name: &str,
+        ) -> Result<Option<crate::models::Id>, Self::Error>;
+        /// Move a model to `position` in the order [`Self::model_names`] lists
+        /// them
+        async fn move_model(
+            &mut self,
+            id: crate::models::Id,
+            position: u8,
+        ) -> Result<(), Self::Error>;
+
+        /// Call `f` with the name of each file, which are kept separately from
+        /// the models
+        #[expect(unused)]
+        async fn file_names<F>(&mut self, f: F) -> Result<(), Self::Error>
+        where
+            F: FnMut(&str);
+        #[expect(unused)]
+        async fn file(&mut self, name: &str) -> Result<Option<Self::File<'_>>, Self::Error>;
+        /// Open a file with the cursor at its end
+        #[expect(unused)]
+        async fn append_file(&mut self, name: &str) -> Result<Option<Self::File<'_>>, Self::Error>;
+        /// Create an empty file, truncating the existing file if there is one
+        #[expect(unused)]
+        async fn create_file(&mut self, name: &str) -> Result<Self::File<'_>, Self::Error>;
+        #[expect(unused)]
+        async fn delete_file(&mut self, name: &str) -> Result<(), Self::Error>;
 
         async fn flush(&mut self) -> Result<(), Self::Error>;
     }
@@ -47,13 +95,22 @@ pub(crate) type File<'a> = <crate::hal::Storage as pal::Storage>::File<'a>;
 
 type Inner = Mutex<crate::mutex::MultiCore, crate::hal::Storage>;
 
-pub(crate) async fn init(storage: crate::hal::StorageFuture) -> (Storage, Config, Models) {
+/// Summary of a repair done while mounting storage
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Repaired {
+    pub(crate) recovered: u8,
+    pub(crate) dropped: u8,
+}
+
+pub(crate) async fn init(
+    storage: crate::hal::StorageFuture,
+) -> (Storage, Config, Models, Option<Repaired>) {
     static INNER: StaticCell<Inner> = StaticCell::new();
 
-    let storage = storage.await;
+    let (storage, repaired) = storage.await;
     let inner = INNER.init_with(|| Mutex::new(storage));
 
-    (Storage(&*inner), Config(&*inner), Models(&*inner))
+    (Storage(&*inner), Config(&*inner), Models(&*inner), repaired)
 }
 
 pub(crate) struct Storage(&'static Inner);
@@ -117,4 +174,23 @@ impl Models {
         let mut storage = self.0.lock().await;
         storage.delete_model(id).await
     }
+
+    pub(crate) async fn rename(&self, id: crate::models::Id, name: &str) -> Result<(), Error> {
+        let mut storage = self.0.lock().await;
+        storage.rename_model(id, name).await
+    }
+
+    pub(crate) async fn copy(
+        &self,
+        id: crate::models::Id,
+        name: &str,
+    ) -> Result<Option<crate::models::Id>, Error> {
+        let mut storage = self.0.lock().await;
+        storage.copy_model(id, name).await
+    }
+
+    pub(crate) async fn move_to(&self, id: crate::models::Id, position: u8) -> Result<(), Error> {
+        let mut storage = self.0.lock().await;
+        storage.move_model(id, position).await
+    }
 }
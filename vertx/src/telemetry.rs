@@ -0,0 +1,510 @@
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::watch;
+use embassy_time::{Duration, Instant};
+use static_cell::StaticCell;
+use vertx_crsf::{FlightModeName, Packet, TxPower};
+
+const SUBSCRIPTIONS: usize = 2;
+
+/// Minimum satellite count before the current position is used as home
+const HOME_SATELLITES: u8 = 6;
+
+#[derive(Clone, Copy)]
+pub(crate) struct Manager {
+    state: &'static Inner,
+    updated: &'static Updated,
+}
+
+type Inner = Mutex<crate::mutex::SingleCore, RefCell<Telemetry>>;
+type Updated = watch::Watch<crate::mutex::MultiCore, (), SUBSCRIPTIONS>;
+
+#[expect(
+    dead_code,
+    reason = "nothing receives CRSF packets until a HAL provides the module UART"
+)]
+impl Manager {
+    /// Values are considered stale `timeout` after they were last received.
+    /// There is only one store, so this returns `None` if it already exists.
+    pub(crate) fn new(timeout: Duration) -> Option<Self> {
+        static STATE: StaticCell<Inner> = StaticCell::new();
+        static UPDATED: Updated = watch::Watch::new();
+
+        let state = STATE.try_init(Mutex::new(RefCell::new(Telemetry::new(timeout))))?;
+        Some(Self {
+            state,
+            updated: &UPDATED,
+        })
+    }
+
+    /// Record a received packet, notifying subscribers if it contained
+    /// telemetry
+    pub(crate) fn update(self, packet: &Packet) {
+        let now = Instant::now();
+        let updated = self
+            .state
+            .lock(|state| state.borrow_mut().update(packet, now));
+
+        if updated {
+            self.updated.sender().send(());
+        }
+    }
+
+    pub(crate) fn with<T>(self, f: impl FnOnce(&Telemetry) -> T) -> T {
+        self.state.lock(|state| f(&state.borrow()))
+    }
+
+    /// Forget all values, including the home position
+    pub(crate) fn reset(self) {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            *state = Telemetry::new(state.timeout);
+        });
+        self.updated.sender().send(());
+    }
+
+    pub(crate) fn subscribe(self) -> Option<Subscriber> {
+        self.updated.receiver().map(Subscriber)
+    }
+}
+
+pub(crate) struct Subscriber(watch::Receiver<'static, crate::mutex::MultiCore, (), SUBSCRIPTIONS>);
+
+#[expect(dead_code, reason = "no UI view shows telemetry yet")]
+impl Subscriber {
+    pub(crate) async fn updated(&mut self) {
+        self.0.changed().await;
+    }
+}
+
+/// Latest value of each sensor, along with values derived from their history
+#[derive(Debug)]
+pub(crate) struct Telemetry {
+    timeout: Duration,
+    gps: Option<Reading<Gps>>,
+    battery: Option<Reading<Battery>>,
+    link: Option<Reading<Link>>,
+    vario: Option<Reading<i16>>,
+    altitude: Option<Reading<Altitude>>,
+    flight_mode: Option<Reading<FlightModeName>>,
+    home: Option<Position>,
+    min_rssi: Option<i16>,
+    max_current: Option<i16>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Reading<T> {
+    pub(crate) value: T,
+    #[cfg_attr(
+        not(test),
+        expect(dead_code, reason = "no UI view shows telemetry yet")
+    )]
+    pub(crate) received: Instant,
+    stale_at: Instant,
+}
+
+impl<T> Reading<T> {
+    pub(crate) fn is_stale(&self, now: Instant) -> bool {
+        now >= self.stale_at
+    }
+
+    /// The value, if it is not stale
+    pub(crate) fn fresh(&self, now: Instant) -> Option<&T> {
+        (!self.is_stale(now)).then_some(&self.value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Position {
+    /// Degrees (7 decimals)
+    pub(crate) latitude: i32,
+    /// Degrees (7 decimals)
+    pub(crate) longitude: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Gps {
+    pub(crate) position: Position,
+    /// km/h (1 decimal)
+    pub(crate) speed: i16,
+    /// Degrees (2 decimals)
+    pub(crate) heading: i16,
+    /// Meters
+    pub(crate) altitude: i32,
+    pub(crate) satellites: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Battery {
+    /// Decivolts
+    pub(crate) voltage: i16,
+    /// Deciamps
+    pub(crate) current: i16,
+    /// mAh
+    pub(crate) used: i32,
+    /// Whole percentage points
+    pub(crate) remaining: i8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Link {
+    /// Uplink RSSI of the active antenna in dBm
+    pub(crate) rssi: i16,
+    /// Uplink link quality in whole percentage points
+    pub(crate) link_quality: u8,
+    /// Uplink signal to noise ratio in dB
+    pub(crate) snr: i8,
+    /// Downlink RSSI in dBm
+    pub(crate) downlink_rssi: i16,
+    /// Downlink link quality in whole percentage points
+    pub(crate) downlink_link_quality: u8,
+    pub(crate) tx_power: TxPower,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Altitude {
+    /// Decimeters
+    pub(crate) altitude: i32,
+    /// cm/s
+    pub(crate) vertical_speed: Option<i16>,
+}
+
+impl Telemetry {
+    pub(crate) const fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            gps: None,
+            battery: None,
+            link: None,
+            vario: None,
+            altitude: None,
+            flight_mode: None,
+            home: None,
+            min_rssi: None,
+            max_current: None,
+        }
+    }
+
+    /// Record a received packet. Returns `false` if it was not telemetry.
+    pub(crate) fn update(&mut self, packet: &Packet, now: Instant) -> bool {
+        match packet {
+            Packet::Gps {
+                latitude,
+                longitude,
+                speed,
+                heading,
+                altitude,
+                satellites,
+            } => {
+                let position = Position {
+                    latitude: *latitude,
+                    longitude: *longitude,
+                };
+
+                if self.home.is_none() && *satellites >= HOME_SATELLITES {
+                    self.home = Some(position);
+                }
+
+                self.gps = Some(self.reading(
+                    Gps {
+                        position,
+                        speed: *speed,
+                        heading: *heading,
+                        altitude: *altitude,
+                        satellites: *satellites,
+                    },
+                    now,
+                ));
+            }
+            Packet::BatterySensor {
+                voltage,
+                current,
+                used,
+                remaining,
+            } => {
+                self.max_current = Some(self.max_current.map_or(*current, |max| max.max(*current)));
+                self.battery = Some(self.reading(
+                    Battery {
+                        voltage: *voltage,
+                        current: *current,
+                        used: *used,
+                        remaining: *remaining,
+                    },
+                    now,
+                ));
+            }
+            Packet::LinkStatistics {
+                up_rssi1,
+                up_rssi2,
+                up_lq,
+                up_snr,
+                active_antenna,
+                tx_power,
+                down_rssi,
+                down_lq,
+                ..
+            } => {
+                let rssi = if *active_antenna == 0 {
+                    up_rssi1
+                } else {
+                    up_rssi2
+                };
+                let rssi = -i16::from(*rssi);
+
+                // RSSI is meaningless without a connection
+                if *up_lq > 0 {
+                    self.min_rssi = Some(self.min_rssi.map_or(rssi, |min| min.min(rssi)));
+                }
+
+                self.link = Some(self.reading(
+                    Link {
+                        rssi,
+                        link_quality: *up_lq,
+                        snr: *up_snr,
+                        downlink_rssi: -i16::from(*down_rssi),
+                        downlink_link_quality: *down_lq,
+                        tx_power: *tx_power,
+                    },
+                    now,
+                ));
+            }
+            Packet::Vario { vertical_speed } => {
+                self.vario = Some(self.reading(*vertical_speed, now));
+            }
+            Packet::BarometricAltitude {
+                altitude,
+                vertical_speed,
+            } => {
+                self.altitude = Some(self.reading(
+                    Altitude {
+                        altitude: *altitude,
+                        vertical_speed: *vertical_speed,
+                    },
+                    now,
+                ));
+            }
+            Packet::FlightMode(mode) => {
+                self.flight_mode = Some(self.reading(mode.clone(), now));
+            }
+            _ => return false,
+        }
+
+        true
+    }
+
+    fn reading<T>(&self, value: T, now: Instant) -> Reading<T> {
+        Reading {
+            value,
+            received: now,
+            stale_at: now + self.timeout,
+        }
+    }
+}
+
+#[expect(dead_code, reason = "no UI view shows telemetry yet")]
+impl Telemetry {
+    pub(crate) fn gps(&self) -> Option<&Reading<Gps>> {
+        self.gps.as_ref()
+    }
+
+    pub(crate) fn battery(&self) -> Option<&Reading<Battery>> {
+        self.battery.as_ref()
+    }
+
+    pub(crate) fn link(&self) -> Option<&Reading<Link>> {
+        self.link.as_ref()
+    }
+
+    /// Vertical speed in cm/s
+    pub(crate) fn vario(&self) -> Option<&Reading<i16>> {
+        self.vario.as_ref()
+    }
+
+    pub(crate) fn altitude(&self) -> Option<&Reading<Altitude>> {
+        self.altitude.as_ref()
+    }
+
+    pub(crate) fn flight_mode(&self) -> Option<&Reading<FlightModeName>> {
+        self.flight_mode.as_ref()
+    }
+
+    /// Lowest uplink RSSI in dBm while connected
+    pub(crate) fn min_rssi(&self) -> Option<i16> {
+        self.min_rssi
+    }
+
+    /// Highest current in deciamps
+    pub(crate) fn max_current(&self) -> Option<i16> {
+        self.max_current
+    }
+
+    /// First position with a good enough GPS fix
+    pub(crate) fn home(&self) -> Option<Position> {
+        self.home
+    }
+
+    /// Distance in meters between home and the latest GPS position
+    pub(crate) fn home_distance(&self, now: Instant) -> Option<u32> {
+        let home = self.home?;
+        let gps = self.gps.as_ref()?.fresh(now)?;
+        Some(distance(home, gps.position))
+    }
+}
+
+/// Distance in meters using an equirectangular approximation, which is plenty
+/// accurate over the range of a radio link
+fn distance(from: Position, to: Position) -> u32 {
+    /// Centimeters per 1e-7 degrees of latitude
+    const CM_PER_UNIT: f32 = 1.113_195;
+    const HALF_TURN: i64 = 1_800_000_000;
+
+    let mut d_longitude = i64::from(to.longitude) - i64::from(from.longitude);
+    if d_longitude > HALF_TURN {
+        d_longitude -= 2 * HALF_TURN;
+    } else if d_longitude < -HALF_TURN {
+        d_longitude += 2 * HALF_TURN;
+    }
+    let d_latitude = i64::from(to.latitude) - i64::from(from.latitude);
+
+    let mid_latitude = i32::midpoint(from.latitude, to.latitude);
+    let mid_latitude = (mid_latitude as f32 * 1e-7).to_radians();
+
+    let x = d_longitude as f32 * CM_PER_UNIT * cos(mid_latitude);
+    let y = d_latitude as f32 * CM_PER_UNIT;
+
+    let cm = ((x * x + y * y) as u64).isqrt();
+    (cm / 100).try_into().unwrap_or(u32::MAX)
+}
+
+/// Bhaskara I's approximation, valid for `-π/2..=π/2`
+fn cos(x: f32) -> f32 {
+    use core::f32::consts::PI;
+
+    let x2 = x * x;
+    (PI * PI - 4.0 * x2) / (PI * PI + x2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gps(latitude: i32, longitude: i32, satellites: u8) -> Packet {
+        Packet::Gps {
+            latitude,
+            longitude,
+            speed: 0,
+            heading: 0,
+            altitude: 0,
+            satellites,
+        }
+    }
+
+    fn link(rssi: u8, lq: u8) -> Packet {
+        Packet::LinkStatistics {
+            up_rssi1: rssi,
+            up_rssi2: 0,
+            up_lq: lq,
+            up_snr: 0,
+            active_antenna: 0,
+            mode: 0,
+            tx_power: TxPower::mW_100,
+            down_rssi: 0,
+            down_lq: 0,
+            down_snr: 0,
+        }
+    }
+
+    fn battery(current: i16) -> Packet {
+        Packet::BatterySensor {
+            voltage: 168,
+            current,
+            used: 0,
+            remaining: 100,
+        }
+    }
+
+    #[test]
+    fn staleness() {
+        let mut telemetry = Telemetry::new(Duration::from_millis(500));
+        let start = Instant::from_millis(1000);
+
+        assert!(telemetry.update(&Packet::Vario { vertical_speed: 12 }, start));
+        let vario = telemetry.vario().unwrap();
+        assert_eq!(vario.received, start);
+        assert_eq!(vario.fresh(start + Duration::from_millis(499)), Some(&12));
+        assert!(vario.is_stale(start + Duration::from_millis(500)));
+
+        assert!(!telemetry.update(
+            &Packet::DevicePing {
+                to: vertx_crsf::Address::Broadcast,
+                from: vertx_crsf::Address::Handset,
+            },
+            start
+        ));
+    }
+
+    #[test]
+    fn derived_extremes() {
+        let mut telemetry = Telemetry::new(Duration::from_secs(1));
+        let now = Instant::from_millis(0);
+
+        for packet in [link(60, 100), link(95, 80), link(120, 0), link(70, 100)] {
+            telemetry.update(&packet, now);
+        }
+        assert_eq!(telemetry.min_rssi(), Some(-95));
+        assert_eq!(telemetry.link().unwrap().value.rssi, -70);
+
+        for current in [12, 305, -4, 150] {
+            telemetry.update(&battery(current), now);
+        }
+        assert_eq!(telemetry.max_current(), Some(305));
+    }
+
+    #[test]
+    fn home_distance() {
+        let mut telemetry = Telemetry::new(Duration::from_secs(1));
+        let now = Instant::from_millis(0);
+
+        // Not enough satellites to set home
+        telemetry.update(&gps(0, 0, 3), now);
+        assert_eq!(telemetry.home(), None);
+        assert_eq!(telemetry.home_distance(now), None);
+
+        // Zurich
+        telemetry.update(&gps(473_769_000, 85_417_000, 8), now);
+        assert_eq!(telemetry.home_distance(now), Some(0));
+
+        // ~1km north
+        telemetry.update(&gps(473_858_832, 85_417_000, 8), now);
+        let distance = telemetry.home_distance(now).unwrap();
+        assert!((999..=1001).contains(&distance), "{distance}");
+
+        // ~1km east
+        telemetry.update(&gps(473_769_000, 85_549_630, 8), now);
+        let distance = telemetry.home_distance(now).unwrap();
+        assert!((995..=1005).contains(&distance), "{distance}");
+
+        assert_eq!(telemetry.home_distance(now + Duration::from_secs(1)), None);
+    }
+
+    #[test]
+    fn distance_across_antimeridian() {
+        let west = Position {
+            latitude: 0,
+            longitude: 1_799_999_000,
+        };
+        let east = Position {
+            latitude: 0,
+            longitude: -1_799_999_000,
+        };
+
+        assert_eq!(distance(west, east), 22);
+    }
+
+    #[test]
+    fn single_store() {
+        assert!(Manager::new(Duration::from_secs(1)).is_some());
+        assert!(Manager::new(Duration::from_secs(1)).is_none());
+    }
+}
@@ -38,6 +38,7 @@ pub(crate) async fn run(
     _config: crate::Config,
     mut ui: crate::hal::Ui,
     models: crate::models::Manager,
+    repaired: Option<crate::storage::Repaired>,
     #[cfg(feature = "configurator")] configurator: crate::configurator::Manager,
 ) -> ! {
     let init = init.start(loog::intern!("ui"));
@@ -67,6 +68,13 @@ pub(crate) async fn run(
     init.finish();
 
     let mut stack = History::<3>::new(menu);
+    if let Some(repaired) = repaired {
+        let mut repaired = State::Repaired(view::Repaired::new(below_title, repaired));
+        repaired.init(false, ui);
+        ui.flush().await;
+        stack.push(repaired);
+    }
+
     loop {
         let current = stack.current();
 
@@ -128,6 +136,7 @@ enum State {
     #[expect(dead_code)]
     ElrsConfig,
     About(view::About),
+    Repaired(view::Repaired),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -193,6 +202,10 @@ impl State {
                 about.init(display)?;
                 about.title()
             }
+            Self::Repaired(repaired) => {
+                repaired.init(display)?;
+                repaired.title()
+            }
         };
 
         draw_title(is_root, title, display)?;
@@ -207,6 +220,7 @@ impl State {
             State::Wifi { .. } => todo!(),
             State::ElrsConfig => todo!(),
             State::About(about) => about.input(input).await,
+            State::Repaired(repaired) => repaired.input(input).await,
         }
     }
 }
@@ -226,6 +240,7 @@ impl Drawable for State {
             Self::Wifi { .. } => todo!(),
             Self::ElrsConfig => todo!(),
             Self::About(about) => about.draw(target),
+            Self::Repaired(repaired) => repaired.draw(target),
         }
     }
 }
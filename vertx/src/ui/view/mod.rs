@@ -1,12 +1,14 @@
 mod about;
 mod menu;
 mod model;
+mod repaired;
 
 use embedded_graphics::prelude::*;
 
 pub(super) use self::about::About;
 pub(super) use self::menu::Menu;
 pub(super) use self::model::Model;
+pub(super) use self::repaired::Repaired;
 
 pub(super) trait View: super::Component + Drawable<Output = ()> {
     fn title(&self) -> &str;
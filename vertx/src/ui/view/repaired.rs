@@ -0,0 +1,63 @@
+use core::fmt::Write as _;
+
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_mogeefont::MogeeTextStyle;
+use embedded_text::TextBox;
+
+use super::View;
+use crate::storage::Repaired as Report;
+use crate::ui::component::Component;
+use crate::ui::{Input, StateChange};
+
+/// Shown on boot when storage had to be repaired, so models do not go missing
+/// without a word
+#[derive(Debug)]
+pub(in crate::ui) struct Repaired {
+    bounds: Rectangle,
+    text: heapless::String<64>,
+}
+
+impl Repaired {
+    pub(in crate::ui) fn new(bounds: Rectangle, report: Report) -> Self {
+        let mut text = heapless::String::new();
+        // At most 35 bytes, so this cannot overflow
+        write!(
+            text,
+            "Recovered {} models\nLost {} models",
+            report.recovered, report.dropped
+        )
+        .unwrap();
+
+        Self { bounds, text }
+    }
+}
+
+impl Component for Repaired {}
+
+impl View for Repaired {
+    fn title(&self) -> &'static str {
+        "Storage repaired"
+    }
+
+    async fn input(&mut self, _input: Input) -> StateChange {
+        StateChange::Pop
+    }
+}
+
+impl Drawable for Repaired {
+    type Color = BinaryColor;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        target.cropped(&self.bounds).clear(BinaryColor::Off)?;
+
+        let style = MogeeTextStyle::new(BinaryColor::On);
+        TextBox::new(&self.text, self.bounds, style).draw(target)?;
+        Ok(())
+    }
+}